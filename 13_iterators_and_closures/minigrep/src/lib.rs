@@ -1,44 +1,103 @@
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::ops::Range;
+
+pub mod matcher;
+
+pub use matcher::Matcher;
 
 pub struct Config {
     pub query: String,
     pub filename: String,
     pub case_sensitive: bool,
+    pub mode: Mode,
+    /// Only match whole words (`-w`).
+    pub whole_word: bool,
+    /// Print the lines that don't match instead (`-v`).
+    pub invert: bool,
+}
+
+/// How the query is matched against each line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// As a plain string, the default.
+    Literal,
+    /// As a regular expression (`-E`).
+    Regex,
+    /// The query names a file of strings, one per line, any of which may
+    /// match (`-f patterns.txt`).
+    PatternsFile,
 }
 
 impl Config {
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
+    /// Reads `[-E] [-w] [-v] QUERY FILENAME` or `[-w] [-v] -f PATTERNS
+    /// FILENAME` from the command line. The flags can come in any order,
+    /// and everything after `--` is taken as it is, so a query can start
+    /// with `-`.
+    pub fn new<I>(mut args: I) -> Result<Config, &'static str>
+        where
+            I: Iterator<Item = String>
+    {
         args.next();
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+        let mut mode = Mode::Literal;
+        let mut whole_word = false;
+        let mut invert = false;
+        let mut patterns_file = None;
+        let mut positional = Vec::new();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => {
+                    positional.extend(args.by_ref());
+                    break;
+                }
+                "-E" => mode = Mode::Regex,
+                "-w" => whole_word = true,
+                "-v" => invert = true,
+                "-f" => match args.next() {
+                    Some(file) => patterns_file = Some(file),
+                    None => return Err("Didn't get a patterns file after -f"),
+                },
+                _ if arg.starts_with('-') && arg.len() > 1 => return Err("Unknown flag; expected -E, -w, -v or -f"),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let query = match patterns_file {
+            Some(_) if mode == Mode::Regex => return Err("-E and -f can't be used together"),
+            Some(file) => {
+                mode = Mode::PatternsFile;
+                file
+            }
+            None => match positional.next() {
+                Some(arg) => arg,
+                None => return Err("Didn't get a query string"),
+            },
         };
 
-        let filename = match args.next() {
+        let filename = match positional.next() {
             Some(arg) => arg,
             None => return Err("Didn't get a file name"),
         };
+        if positional.next().is_some() {
+            return Err("Got more than one file name");
+        }
 
         let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
 
-        Ok(Config { query, filename, case_sensitive })
+        Ok(Config { query, filename, case_sensitive, mode, whole_word, invert })
     }
 }
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.filename)?;
-
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
-    } else {
-        search_case_insensitive(&config.query, &contents)
-    };
+    let contents = fs::read_to_string(&config.filename)?;
+    let matcher = matcher::from_config(&config)?;
 
-    for line in results {
-        println!("{}", line);
+    for found in search_with(&matcher, &contents) {
+        println!("{}", found.line);
     }
 
     /*
@@ -65,9 +124,25 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
 }
 
 pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
+    let matcher = matcher::Literal::new(query, false);
+    search_with(&matcher, contents)
+        .into_iter()
+        .map(|found| found.line)
+        .collect()
+}
+
+/// A line selected by a search.
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    pub line: &'a str,
+    /// The byte offsets of each match within `line`, for highlighting them.
+    /// Empty for lines selected by an inverted search.
+    pub ranges: Vec<Range<usize>>,
+}
+
+pub fn search_with<'a, M: Matcher>(matcher: &M, contents: &'a str) -> Vec<Match<'a>> {
     contents.lines()
-        .filter(|line| line.to_lowercase().contains(&query))
+        .filter_map(|line| matcher.find(line).map(|ranges| Match { line, ranges }))
         .collect()
 }
 
@@ -119,4 +194,110 @@ Trust me.";
         );
     }
 
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        args.into_iter()
+    }
+
+    /// A match, with its ranges given as `(start, end)`.
+    fn found<'a>(line: &'a str, ranges: &[(usize, usize)]) -> Match<'a> {
+        Match { line, ranges: ranges.iter().map(|&(start, end)| start..end).collect() }
+    }
+
+    fn lines<'a>(found: &[Match<'a>]) -> Vec<&'a str> {
+        found.iter().map(|found| found.line).collect()
+    }
+
+    #[test]
+    fn reports_where_each_match_is() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(
+            vec![found("Rust:", &[(0, 4)]), found("Trust me.", &[(1, 5)])],
+            search_with(&matcher::Literal::new("rUsT", false), contents)
+        );
+        // Lowercasing changes the length of some text, but not the offsets.
+        assert_eq!(
+            vec![found("İrust", &[(2, 6)])],
+            search_with(&matcher::Literal::new("RUST", false), "İrust")
+        );
+    }
+
+    #[test]
+    fn regex_mode() {
+        let matcher = matcher::Pattern::regex(r"\bt\w+e\b", true).unwrap();
+        let found = search_with(&matcher, "Pick three.\nTrust me.\ntake");
+
+        assert_eq!(vec!["Pick three.", "take"], lines(&found));
+        assert_eq!(vec![5..10], found[0].ranges);
+        assert!(matcher::Pattern::regex("(", true).is_err());
+    }
+
+    #[test]
+    fn whole_words_and_inverted() {
+        let contents = "\
+Rust:
+Trust me.
+rust_belt";
+        let whole = matcher::Pattern::regex(&matcher::whole_words("rust"), false).unwrap();
+
+        assert_eq!(vec!["Rust:"], lines(&search_with(&whole, contents)));
+        assert_eq!(
+            vec![found("Trust me.", &[]), found("rust_belt", &[])],
+            search_with(&matcher::Invert(whole), contents)
+        );
+    }
+
+    #[test]
+    fn partial_words_dont_hide_whole_ones() {
+        let pattern = matcher::whole_words(&matcher::any_of(vec!["ab", "b"]));
+        let whole = matcher::Pattern::regex(&pattern, true).unwrap();
+
+        assert_eq!(vec![found("xab b", &[(4, 5)])], search_with(&whole, "xab b
+xab"));
+
+        let pattern = matcher::whole_words(&regex::escape("c++"));
+        let whole = matcher::Pattern::regex(&pattern, true).unwrap();
+        assert_eq!(vec!["c++ code"], lines(&search_with(&whole, "c++ code
+c++x")));
+    }
+
+    #[test]
+    fn any_of_a_list_of_strings() {
+        let matcher = matcher::Pattern::fixed_strings(vec!["fast", "", "fa.", "safe"], true).unwrap();
+        let found = search_with(&matcher, "safe, fast, productive.\nfa.r\nfar");
+
+        assert_eq!(vec!["safe, fast, productive.", "fa.r"], lines(&found));
+        assert_eq!(vec![0..4, 6..10], found[0].ranges);
+
+        let nothing = matcher::Pattern::fixed_strings(vec![""], true).unwrap();
+        assert!(search_with(&nothing, "anything").is_empty());
+    }
+
+    #[test]
+    fn flags_select_the_mode() {
+        let config = Config::new(args(&["minigrep", "-w", "-v", "-f", "patterns.txt", "poem.txt"])).unwrap();
+        assert_eq!(Mode::PatternsFile, config.mode);
+        assert_eq!("patterns.txt", config.query);
+        assert_eq!("poem.txt", config.filename);
+        assert!(config.whole_word && config.invert);
+
+        let config = Config::new(args(&["minigrep", "to", "-E", "poem.txt"])).unwrap();
+        assert_eq!(Mode::Regex, config.mode);
+        assert!(!config.whole_word && !config.invert);
+
+        assert!(Config::new(args(&["minigrep", "-E", "-f", "patterns.txt", "poem.txt"])).is_err());
+        assert!(Config::new(args(&["minigrep", "-x", "to", "poem.txt"])).is_err());
+        assert!(Config::new(args(&["minigrep", "-f", "patterns.txt"])).is_err());
+
+        let config = Config::new(args(&["minigrep", "-w", "--", "-v", "poem.txt"])).unwrap();
+        assert_eq!(Mode::Literal, config.mode);
+        assert_eq!("-v", config.query);
+        assert!(config.whole_word && !config.invert);
+        assert!(Config::new(args(&["minigrep", "--", "-v", "poem.txt", "-w"])).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::fs;
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{Config, Mode};
+
+/// Decides which lines a search selects.
+pub trait Matcher {
+    /// Returns `None` if `line` isn't selected, or the byte ranges of the
+    /// matches in it if it is, in order and without overlaps. An inverted
+    /// search selects lines without matches, so it has no ranges to give.
+    fn find(&self, line: &str) -> Option<Vec<Range<usize>>>;
+}
+
+/// Builds the matcher `config` asks for, reading the patterns file if it
+/// names one.
+pub fn from_config(config: &Config) -> Result<Box<dyn Matcher>, Box<dyn Error>> {
+    // A plain string is searched for without the regex engine, unless it
+    // has to be a whole word.
+    let pattern = match config.mode {
+        Mode::Literal if !config.whole_word => None,
+        Mode::Literal => Some(regex::escape(&config.query)),
+        Mode::Regex => Some(config.query.clone()),
+        Mode::PatternsFile => Some(any_of(fs::read_to_string(&config.query)?.lines())),
+    }
+    .map(|pattern| if config.whole_word { whole_words(&pattern) } else { pattern });
+
+    let mut matcher: Box<dyn Matcher> = match pattern {
+        Some(pattern) => Box::new(Pattern::regex(&pattern, config.case_sensitive)?),
+        None => Box::new(Literal::new(&config.query, config.case_sensitive)),
+    };
+    if config.invert {
+        matcher = Box::new(Invert(matcher));
+    }
+    Ok(matcher)
+}
+
+/// Matches a string as it is.
+pub struct Literal {
+    query: String,
+    /// Set when searching without regard to case, which the regex engine
+    /// does without lowercasing every line.
+    folded: Option<Regex>,
+}
+
+impl Literal {
+    pub fn new(query: &str, case_sensitive: bool) -> Literal {
+        let folded = if case_sensitive {
+            None
+        } else {
+            let folded = RegexBuilder::new(&regex::escape(query))
+                .case_insensitive(true)
+                .build()
+                .expect("an escaped string is a valid regex");
+            Some(folded)
+        };
+
+        Literal {
+            query: query.to_string(),
+            folded,
+        }
+    }
+}
+
+impl Matcher for Literal {
+    fn find(&self, line: &str) -> Option<Vec<Range<usize>>> {
+        let ranges: Vec<Range<usize>> = match &self.folded {
+            Some(folded) => folded.find_iter(line).map(|m| m.range()).collect(),
+            None => line
+                .match_indices(self.query.as_str())
+                .map(|(start, found)| start..start + found.len())
+                .collect(),
+        };
+
+        Some(ranges).filter(|ranges| !ranges.is_empty())
+    }
+}
+
+/// Matches a regular expression, or any of a list of fixed strings.
+pub struct Pattern {
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn regex(pattern: &str, case_sensitive: bool) -> Result<Pattern, regex::Error> {
+        let regex = RegexBuilder::new(pattern).case_insensitive(!case_sensitive).build()?;
+        Ok(Pattern { regex })
+    }
+
+    /// Matches any of `strings`; see `any_of`.
+    pub fn fixed_strings<'a, I>(strings: I, case_sensitive: bool) -> Result<Pattern, regex::Error>
+        where
+            I: IntoIterator<Item = &'a str>
+    {
+        Pattern::regex(&any_of(strings), case_sensitive)
+    }
+}
+
+/// A regex matching any of `strings`, preferring the longest where several
+/// start at the same place. Empty strings are left out, rather than matching
+/// every line.
+pub fn any_of<'a, I>(strings: I) -> String
+    where
+        I: IntoIterator<Item = &'a str>
+{
+    let mut strings: Vec<&str> = strings.into_iter().filter(|s| !s.is_empty()).collect();
+    // The regex engine takes the first alternative that matches.
+    strings.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    let alternatives: Vec<String> = strings.iter().map(|s| regex::escape(s)).collect();
+    match alternatives.len() {
+        // Nothing to look for, so nothing matches.
+        0 => "[^\\s\\S]".to_string(),
+        _ => alternatives.join("|"),
+    }
+}
+
+/// Wraps `pattern` so that it only matches whole words, with no letter,
+/// digit or underscore right before or after them.
+///
+/// The boundaries are part of the search, rather than a filter on its
+/// results, so a match that isn't a whole word can't hide an overlapping
+/// one that is.
+pub fn whole_words(pattern: &str) -> String {
+    format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern)
+}
+
+impl Matcher for Pattern {
+    fn find(&self, line: &str) -> Option<Vec<Range<usize>>> {
+        let ranges: Vec<Range<usize>> = self.regex.find_iter(line).map(|m| m.range()).collect();
+        Some(ranges).filter(|ranges| !ranges.is_empty())
+    }
+}
+
+/// Selects the lines the inner matcher doesn't.
+pub struct Invert<M>(pub M);
+
+impl<M: Matcher> Matcher for Invert<M> {
+    fn find(&self, line: &str) -> Option<Vec<Range<usize>>> {
+        match self.0.find(line) {
+            Some(_) => None,
+            None => Some(Vec::new()),
+        }
+    }
+}
+
+impl Matcher for Box<dyn Matcher> {
+    fn find(&self, line: &str) -> Option<Vec<Range<usize>>> {
+        (**self).find(line)
+    }
+}
@@ -0,0 +1,184 @@
+//! Compares the work-stealing `ThreadPool` with the single
+//! `Mutex<mpsc::Receiver>` design it replaced.
+//!
+//! Run with `cargo bench`. Each scenario queues a batch of jobs, waits for
+//! all of them to finish, and reports the median of several rounds.
+
+use multi_threaded_web_server::ThreadPool;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WORKERS: usize = 4;
+const ROUNDS: usize = 7;
+
+/// The pool as it was before work stealing: every worker takes jobs from
+/// one channel behind one mutex.
+mod mutex_pool {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    enum Message {
+        NewJob(Job),
+        Terminate,
+    }
+
+    pub struct MutexPool {
+        workers: Vec<thread::JoinHandle<()>>,
+        sender: mpsc::Sender<Message>,
+    }
+
+    impl MutexPool {
+        pub fn new(size: usize) -> MutexPool {
+            let (sender, receiver) = mpsc::channel();
+            let receiver = Arc::new(Mutex::new(receiver));
+
+            let workers = (0..size)
+                .map(|_| {
+                    let receiver: Arc<Mutex<mpsc::Receiver<Message>>> = Arc::clone(&receiver);
+                    thread::spawn(move || loop {
+                        let message = receiver.lock().unwrap().recv().unwrap();
+                        match message {
+                            Message::NewJob(job) => job(),
+                            Message::Terminate => break,
+                        }
+                    })
+                })
+                .collect();
+
+            MutexPool { workers, sender }
+        }
+
+        pub fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
+            self.sender.send(Message::NewJob(Box::new(f))).unwrap();
+        }
+    }
+
+    impl Drop for MutexPool {
+        fn drop(&mut self) {
+            for _ in &self.workers {
+                self.sender.send(Message::Terminate).unwrap();
+            }
+            for worker in self.workers.drain(..) {
+                worker.join().unwrap();
+            }
+        }
+    }
+}
+
+use mutex_pool::MutexPool;
+
+/// Something jobs can be queued on.
+trait Executor {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>);
+}
+
+impl Executor for ThreadPool {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(job).unwrap();
+    }
+}
+
+impl Executor for MutexPool {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(job);
+    }
+}
+
+/// Queues `jobs` jobs that each spin for `work` iterations and returns how
+/// long it took until all of them had run.
+fn run_batch(pool: &dyn Executor, jobs: usize, work: u64) -> Duration {
+    let remaining = Arc::new(AtomicUsize::new(jobs));
+    let (done, finished) = mpsc::channel();
+    let done = Arc::new(Mutex::new(done));
+
+    let start = Instant::now();
+    for _ in 0..jobs {
+        let remaining = Arc::clone(&remaining);
+        let done = Arc::clone(&done);
+        pool.spawn(Box::new(move || {
+            let mut x = 0u64;
+            for i in 0..work {
+                x = x.wrapping_mul(31).wrapping_add(i);
+            }
+            std::hint::black_box(x);
+
+            if remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+                done.lock().unwrap().send(()).unwrap();
+            }
+        }));
+    }
+    finished.recv().unwrap();
+
+    start.elapsed()
+}
+
+/// Like `run_batch`, but the jobs are queued from several threads at once,
+/// as connections arriving on more than one listener would be.
+fn run_contended(pool: &(dyn Executor + Sync), producers: usize, jobs: usize) -> Duration {
+    let remaining = Arc::new(AtomicUsize::new(producers * jobs));
+    let (done, finished) = mpsc::channel();
+    let done = Arc::new(Mutex::new(done));
+
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..producers {
+            let remaining = Arc::clone(&remaining);
+            let done = Arc::clone(&done);
+            scope.spawn(move || {
+                for _ in 0..jobs {
+                    let remaining = Arc::clone(&remaining);
+                    let done = Arc::clone(&done);
+                    pool.spawn(Box::new(move || {
+                        if remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+                            done.lock().unwrap().send(()).unwrap();
+                        }
+                    }));
+                }
+            });
+        }
+    });
+    finished.recv().unwrap();
+
+    start.elapsed()
+}
+
+fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+fn report(name: &str, mut measure: impl FnMut(&(dyn Executor + Sync)) -> Duration) {
+    let stealing = ThreadPool::build(WORKERS).unwrap();
+    let mutex = MutexPool::new(WORKERS);
+
+    // One unmeasured round each to start the threads and warm the caches.
+    measure(&stealing);
+    measure(&mutex);
+
+    let stealing_time = median((0..ROUNDS).map(|_| measure(&stealing)).collect());
+    let mutex_time = median((0..ROUNDS).map(|_| measure(&mutex)).collect());
+
+    println!(
+        "{:<40} work-stealing {:>10.2?}   mutex-receiver {:>10.2?}   ({:.2}x)",
+        name,
+        stealing_time,
+        mutex_time,
+        mutex_time.as_secs_f64() / stealing_time.as_secs_f64()
+    );
+}
+
+fn main() {
+    println!("{} workers, median of {} rounds\n", WORKERS, ROUNDS);
+
+    report("100k empty jobs", |pool| run_batch(pool, 100_000, 0));
+    report("100k short jobs (1k iterations)", |pool| run_batch(pool, 100_000, 1_000));
+    report("1k longer jobs (1M iterations)", |pool| run_batch(pool, 1_000, 1_000_000));
+    report("4 producers x 25k empty jobs", |pool| run_contended(pool, 4, 25_000));
+}
@@ -1,48 +1,248 @@
-use multi_threaded_web_server::ThreadPool;
+use multi_threaded_web_server::config::{Concurrency, Config, LogLevel, USAGE};
+use multi_threaded_web_server::executor::{Inline, ThreadPerConnection};
+use multi_threaded_web_server::middleware::{AccessLog, Compression, RequestId};
+use multi_threaded_web_server::{
+    tls, Handler, Params, PoolConfig, PoolEvent, ReloadHandle, Request, Router, Server, ShutdownHandle,
+    StaticFiles, TlsConfig,
+};
 
-use std::fs;
-use std::io::prelude::*;
-use std::net::TcpStream;
-use std::net::TcpListener;
+use std::env;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+/// The `LogLevel` in force, which SIGHUP can change.
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LogLevel::Info as usize);
+
+/// Serves the document root on each address in `bind`, over HTTPS if given
+/// a certificate and key. See `config::USAGE` for the settings, or run with
+/// `--help`.
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", USAGE);
+        return;
+    }
 
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap();
+    let config = Config::load(args.clone(), env::vars()).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        eprintln!("Run with --help to see the settings.");
+        process::exit(2);
+    });
+    LOG_LEVEL.store(config.log_level as usize, Ordering::Relaxed);
+    multi_threaded_web_server::log::set_logger(|level, message: &fmt::Arguments| log(level, *message));
+    let tls = load_tls(&config).unwrap_or_else(|e| {
+        eprintln!("Problem loading certificate: {}", e);
+        process::exit(1);
+    });
 
-        pool.execute(|| {
-            handle_connection(stream);
+    let mut servers = Vec::new();
+    for &addr in &config.bind {
+        let router = router(&config).unwrap_or_else(|e| {
+            eprintln!("Can't serve {}: {}", config.document_root.display(), e);
+            process::exit(1);
         });
+        let mut server = bind(addr, router, &config).with_drain_timeout(config.drain_timeout);
+        if let Some(threads) = config.event_loop_threads {
+            server = server.with_event_loop(threads);
+        }
+        if let Some(tls) = &tls {
+            server = server.with_tls(tls.clone());
+        }
+        servers.push(server);
     }
+    let reload_handles: Vec<ReloadHandle> = servers.iter().map(Server::reload_handle).collect();
 
-    println!("Shutting down.");
-}
+    let https_port = config.bind[0].port();
+    for &addr in &config.tls_redirect_from {
+        servers.push(bind(addr, tls::https_redirect(https_port), &config));
+    }
 
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 512];
-    stream.read(&mut buffer).unwrap();
+    if let Err(e) = reload_on_sighup(args, config, reload_handles) {
+        eprintln!("Problem installing signal handlers: {}", e);
+        process::exit(1);
+    }
 
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
+    let running: Vec<_> = servers
+        .into_iter()
+        .map(|server| {
+            install_signal_handlers(&server.shutdown_handle());
+            let addr = server.local_addr();
+            (addr, thread::spawn(move || server.run()))
+        })
+        .collect();
 
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK\r\n\r\n", "hello.html")
-    } else if buffer.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK\r\n\r\n", "hello.html")
+    let mut failed = false;
+    for (addr, thread) in running {
+        if let Ok(Err(e)) = thread.join() {
+            log(LogLevel::Error, format_args!("Server error on {:?}: {}", addr, e));
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// The site: the document root, with a page that takes its time to show
+/// what the worker pool does under load.
+fn router(config: &Config) -> io::Result<Router> {
+    let files = StaticFiles::new(&config.document_root)?.not_found_page(&config.not_found_page);
+    let files = Arc::new(files);
+
+    let hello = Arc::clone(&files);
+    let sleep = Arc::clone(&files);
+    let router = Router::new()
+        .get("/", move |request: &Request, _: &Params| hello.serve(request, "hello.html"))
+        .get("/sleep", move |request: &Request, _: &Params| {
+            thread::sleep(Duration::from_secs(5));
+            sleep.serve(request, "hello.html")
+        })
+        .fallback(move |request: &Request, params: &Params| files.handle(request, params))
+        .wrap(RequestId::new());
+    let router = if config.log_level >= LogLevel::Info {
+        router.wrap(AccessLog::stdout())
     } else {
-        ("HTTP/1.1 404 NOT FOUND\r\n\r\n", "404.html")
+        router
     };
 
-    let contents = fs::read_to_string(filename).unwrap();
+    Ok(router.wrap(Compression::new()))
+}
 
-    let response = format!("{}{}", status_line, contents);
+fn load_tls(config: &Config) -> io::Result<Option<TlsConfig>> {
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => TlsConfig::from_pem_files(cert, key).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn bind(addr: SocketAddr, router: Router, config: &Config) -> Server {
+    let server = Server::bind(addr, router).unwrap_or_else(|e| {
+        eprintln!("Problem binding {}: {}", addr, e);
+        process::exit(1);
+    });
+
+    let server = match config.concurrency {
+        Concurrency::Pool => server.with_pool_config(PoolConfig {
+            min_workers: config.min_workers,
+            max_workers: config.max_workers,
+            queue_capacity: Some(config.queue_capacity),
+            observer: Some(Arc::new(log_pool_event)),
+            ..PoolConfig::default()
+        }),
+        Concurrency::ThreadPerConnection => server.with_executor(ThreadPerConnection),
+        Concurrency::Inline => server.with_executor(Inline),
+    };
+
+    server.with_connection_config(config.connection.clone())
+}
+
+fn install_signal_handlers(handle: &ShutdownHandle) {
+    if let Err(e) = handle.shutdown_on_signals() {
+        eprintln!("Problem installing signal handlers: {}", e);
+        process::exit(1);
+    }
+}
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
- 
-}
\ No newline at end of file
+/// Loads the settings again on every SIGHUP, from the same command line
+/// and the file and environment as they are then, and hands them to the
+/// servers in `handles`. Open connections finish on the old settings.
+fn reload_on_sighup(args: Vec<String>, started: Config, handles: Vec<ReloadHandle>) -> io::Result<()> {
+    use signal_hook::consts::SIGHUP;
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::Builder::new().name("reload-config".to_string()).spawn(move || {
+        for _ in signals.forever() {
+            match reload(&args, &started, &handles) {
+                Ok(()) => log(LogLevel::Info, format_args!("Reloaded the configuration.")),
+                Err(e) => log(LogLevel::Error, format_args!("Not reloading the configuration: {}", e)),
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+fn reload(args: &[String], started: &Config, handles: &[ReloadHandle]) -> Result<(), String> {
+    let config = Config::load(args.iter().cloned(), env::vars()).map_err(|e| e.to_string())?;
+    let tls = load_tls(&config).map_err(|e| format!("problem loading certificate: {}", e))?;
+    let routers = handles
+        .iter()
+        .map(|_| router(&config))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| format!("can't serve {}: {}", config.document_root.display(), e))?;
+    // `set_tls` only swaps certificates on servers already speaking HTTPS;
+    // turning TLS on or off takes a restart.
+    let tls_switched = started.tls_cert.is_some() != config.tls_cert.is_some();
+
+    // Nothing is applied until every server is known to take it.
+    for handle in handles {
+        handle
+            .check_workers(config.min_workers, config.max_workers)
+            .map_err(|e| e.to_string())?;
+    }
+
+    for (handle, router) in handles.iter().zip(routers) {
+        handle.set_router(router);
+        handle.set_connection_config(config.connection.clone());
+        if let Some(tls) = &tls {
+            handle.set_tls(tls.clone());
+        }
+        if let Err(e) = handle.set_workers(config.min_workers, config.max_workers) {
+            log(LogLevel::Error, format_args!("Couldn't resize the pool: {}", e));
+        }
+    }
+    LOG_LEVEL.store(config.log_level as usize, Ordering::Relaxed);
+
+    let needs_restart: Vec<&str> = [
+        ("bind", started.bind != config.bind),
+        ("whether tls is on", tls_switched),
+        ("tls_redirect_from", started.tls_redirect_from != config.tls_redirect_from),
+        ("concurrency", started.concurrency != config.concurrency),
+        ("event_loop_threads", started.event_loop_threads != config.event_loop_threads),
+        ("queue_capacity", started.queue_capacity != config.queue_capacity),
+        ("drain_timeout", started.drain_timeout != config.drain_timeout),
+    ]
+    .iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(name, _)| *name)
+    .collect();
+    if !needs_restart.is_empty() {
+        log(
+            LogLevel::Warn,
+            format_args!("Changes to {} take a restart.", needs_restart.join(", ")),
+        );
+    }
+
+    Ok(())
+}
+
+fn log(level: LogLevel, message: fmt::Arguments) {
+    if level as usize > LOG_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    if level <= LogLevel::Warn {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Logs what the worker pool is doing: failures at `error`, work turned
+/// away at `warn`, workers coming and going at `info` and every job at
+/// `debug`.
+fn log_pool_event(event: &PoolEvent) {
+    let level = match event {
+        PoolEvent::SpawnFailed { .. } | PoolEvent::JobFinished { panicked: true, .. } => LogLevel::Error,
+        PoolEvent::JobRejected | PoolEvent::JobDropped | PoolEvent::ScheduledJobSkipped { .. } => LogLevel::Warn,
+        PoolEvent::JobStarted { .. } | PoolEvent::JobFinished { .. } => LogLevel::Debug,
+        _ => LogLevel::Info,
+    };
+    log(level, format_args!("Pool: {}", event));
+}
@@ -0,0 +1,725 @@
+//! An HTTP/1.1 client, for talking to other servers from handlers, health
+//! checks and tests.
+//!
+//! Requests block the calling thread until the response has been read
+//! whole, so no async runtime is needed:
+//!
+//! ```no_run
+//! use multi_threaded_web_server::client::Client;
+//!
+//! let client = Client::new();
+//! let response = client.get("http://127.0.0.1:7878/").unwrap();
+//! println!("{}", response.status);
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+
+use crate::request::{Method, Request};
+use crate::response::{read_response, Response};
+use crate::status::Status;
+
+/// How long a connection may sit in the pool before it is thought too old
+/// to reuse; servers close idle connections sooner or later.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends requests and reads their responses, keeping connections open
+/// between requests to the same host.
+///
+/// Response bodies are read into memory, and chunked ones decoded; the
+/// headers are left as the server sent them. Redirects are followed, up to a
+/// limit. `https://` URLs need a TLS configuration, given to `with_tls`.
+///
+/// Clones share their pool of idle connections, so a client can be cloned
+/// into each handler or thread that needs one. Requests that can't safely
+/// be sent twice, like `POST`, always go on a new connection.
+#[derive(Clone)]
+pub struct Client {
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_redirects: usize,
+    max_idle_per_host: usize,
+    max_response_size: usize,
+    tls: Option<Arc<ClientConfig>>,
+    idle: Arc<Mutex<HashMap<Origin, Vec<Connection>>>>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            max_redirects: 5,
+            max_idle_per_host: 4,
+            max_response_size: 16 * 1024 * 1024,
+            tls: None,
+            idle: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets how long connecting to a server, including the TLS handshake,
+    /// may take. The default is 10 seconds.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Client {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how long any one read from or write to a server may wait. The
+    /// default is 30 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many redirects in a row are followed before giving up with
+    /// `ClientError::TooManyRedirects`. With 0, redirects are returned like
+    /// any other response. The default is 5.
+    pub fn with_max_redirects(mut self, redirects: usize) -> Client {
+        self.max_redirects = redirects;
+        self
+    }
+
+    /// Sets how many idle connections are kept open to each host. The
+    /// default is 4; with 0, every request gets a connection of its own.
+    pub fn with_max_idle_per_host(mut self, connections: usize) -> Client {
+        self.max_idle_per_host = connections;
+        self
+    }
+
+    /// Sets the largest response body read; larger ones fail with
+    /// `InvalidData`. The default is 16 MiB.
+    pub fn with_max_response_size(mut self, size: usize) -> Client {
+        self.max_response_size = size;
+        self
+    }
+
+    /// Makes `https://` URLs work, trusting the servers `config` does.
+    pub fn with_tls(mut self, config: Arc<ClientConfig>) -> Client {
+        self.tls = Some(config);
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response, ClientError> {
+        let url = Url::parse(url)?;
+        self.execute(url.origin, Request::new(Method::Get, &url.target))
+    }
+
+    pub fn post(&self, url: &str, body: impl Into<Vec<u8>>) -> Result<Response, ClientError> {
+        let url = Url::parse(url)?;
+        self.execute(url.origin, Request::new(Method::Post, &url.target).with_body(body))
+    }
+
+    /// Sends `request` to the server at `origin`, a URL with no path like
+    /// `http://127.0.0.1:8080`. The request's path and query say what to
+    /// ask it for.
+    ///
+    /// `Host` is set to the origin's unless the request has one already,
+    /// and `Content-Length` to the length of the body.
+    pub fn send(&self, origin: &str, request: Request) -> Result<Response, ClientError> {
+        let url = Url::parse(origin)?;
+        if url.target != "/" {
+            return Err(ClientError::InvalidUrl(format!("`{}` has a path; only the request's is used", origin)));
+        }
+        self.execute(url.origin, request)
+    }
+
+    fn execute(&self, mut origin: Origin, mut request: Request) -> Result<Response, ClientError> {
+        let mut redirects = 0;
+
+        loop {
+            let response = self.exchange(&origin, &request)?;
+            let location = match response.headers.get("Location") {
+                Some(location) if self.max_redirects > 0 && is_redirect(response.status) => location,
+                _ => return Ok(response),
+            };
+            if redirects == self.max_redirects {
+                return Err(ClientError::TooManyRedirects(redirects));
+            }
+            redirects += 1;
+
+            let next = Url::resolve(&origin, &request.path, location)?;
+            let see_other = response.status == Status::SeeOther && request.method != Method::Head;
+            let was_post = request.method == Method::Post
+                && matches!(response.status, Status::MovedPermanently | Status::Found);
+            if see_other || was_post {
+                request.method = Method::Get;
+                request.body.clear();
+                request.headers.remove("Content-Type");
+            }
+            if next.origin != origin {
+                // What identified the client to one host isn't for another.
+                request.headers.remove("Authorization");
+                request.headers.remove("Cookie");
+                request.headers.remove("Host");
+            }
+
+            let target = Request::new(Method::Get, &next.target);
+            request.path = target.path;
+            request.query = target.query;
+            origin = next.origin;
+        }
+    }
+
+    /// Sends one request and reads its response, on an idle connection if
+    /// there is one.
+    fn exchange(&self, origin: &Origin, request: &Request) -> Result<Response, ClientError> {
+        let message = request_bytes(origin, request);
+        let head = request.method == Method::Head;
+
+        // A connection that has been idle may be closed by the server just
+        // as it is reused, and then there's no telling whether the request
+        // got through, so requests that can't be repeated get a new one.
+        let repeatable = is_idempotent(&request.method);
+
+        loop {
+            let idle = if repeatable { self.take_idle(origin) } else { None };
+            let (mut connection, reused) = match idle {
+                Some(connection) => (connection, true),
+                None => (self.connect(origin)?, false),
+            };
+
+            match connection.exchange(&message, head, self.max_response_size) {
+                Ok((response, reusable)) => {
+                    if reusable {
+                        self.put_idle(origin, connection);
+                    }
+                    return Ok(response);
+                }
+                // Trying again on a new connection, or the next idle one.
+                Err(_) if reused => continue,
+                Err(e) => return Err(ClientError::Io(e)),
+            }
+        }
+    }
+
+    fn connect(&self, origin: &Origin) -> Result<Connection, ClientError> {
+        let tls = match (origin.secure, &self.tls) {
+            (true, Some(config)) => {
+                let name = ServerName::try_from(origin.host.clone())
+                    .map_err(|_| ClientError::InvalidUrl(format!("`{}` isn't a host name", origin.host)))?;
+                Some(ClientConnection::new(Arc::clone(config), name).map_err(|e| ClientError::Connect(io::Error::other(e)))?)
+            }
+            (true, None) => return Err(ClientError::NoTls),
+            (false, _) => None,
+        };
+
+        let mut socket = self.open_socket(origin).map_err(ClientError::Connect)?;
+        let stream = match tls {
+            Some(mut connection) => {
+                while connection.is_handshaking() {
+                    connection.complete_io(&mut socket).map_err(ClientError::Connect)?;
+                }
+                Stream::Tls(Box::new(StreamOwned::new(connection, socket)))
+            }
+            None => Stream::Plain(socket),
+        };
+
+        Ok(Connection {
+            reader: BufReader::new(stream),
+            idle_since: Instant::now(),
+        })
+    }
+
+    /// Connects to the first of the host's addresses that answers.
+    fn open_socket(&self, origin: &Origin) -> io::Result<TcpStream> {
+        let addrs: Vec<SocketAddr> = (origin.host.as_str(), origin.port).to_socket_addrs()?.collect();
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses found");
+
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(socket) => {
+                    socket.set_read_timeout(Some(self.timeout))?;
+                    socket.set_write_timeout(Some(self.timeout))?;
+                    socket.set_nodelay(true)?;
+                    return Ok(socket);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// An idle connection to `origin` that still looks open, if there is
+    /// one.
+    fn take_idle(&self, origin: &Origin) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.get_mut(origin)?;
+
+        while let Some(connection) = connections.pop() {
+            if connection.idle_since.elapsed() < IDLE_TIMEOUT && connection.is_open() {
+                // Clones may differ in their timeouts.
+                let socket = connection.reader.get_ref().socket();
+                if socket.set_read_timeout(Some(self.timeout)).is_ok()
+                    && socket.set_write_timeout(Some(self.timeout)).is_ok()
+                {
+                    return Some(connection);
+                }
+            }
+        }
+        None
+    }
+
+    fn put_idle(&self, origin: &Origin, mut connection: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.entry(origin.clone()).or_default();
+
+        if connections.len() < self.max_idle_per_host {
+            connection.idle_since = Instant::now();
+            connections.push(connection);
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("connect_timeout", &self.connect_timeout)
+            .field("timeout", &self.timeout)
+            .field("max_redirects", &self.max_redirects)
+            .field("tls", &self.tls.is_some())
+            .finish()
+    }
+}
+
+/// Why a request got no response.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The URL isn't an `http://` or `https://` one.
+    InvalidUrl(String),
+    /// An `https://` URL, for a client without a TLS configuration.
+    NoTls,
+    /// The server couldn't be reached, or the TLS handshake with it failed.
+    /// Nothing was sent.
+    Connect(io::Error),
+    /// The server was reached, but sending the request or reading the
+    /// response failed, or the response wasn't valid HTTP.
+    Io(io::Error),
+    /// Redirected more times in a row than the client follows.
+    TooManyRedirects(usize),
+}
+
+impl ClientError {
+    /// Whether the server took longer than the client's timeouts allow.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            ClientError::Connect(e) | ClientError::Io(e) => {
+                matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl(message) => write!(f, "invalid URL: {}", message),
+            ClientError::NoTls => f.write_str("https needs a TLS configuration"),
+            ClientError::Connect(e) => write!(f, "can't connect: {}", e),
+            ClientError::Io(e) => write!(f, "request failed: {}", e),
+            ClientError::TooManyRedirects(n) => write!(f, "gave up after {} redirects", n),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Connect(e) | ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Where connections go; idle ones are pooled by it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Origin {
+    secure: bool,
+    host: String,
+    port: u16,
+}
+
+impl Origin {
+    /// The host and port as they go in `Host`, leaving out a default port.
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+
+        match (self.secure, self.port) {
+            (false, 80) | (true, 443) => host,
+            (_, port) => format!("{}:{}", host, port),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Url {
+    origin: Origin,
+    /// The path and query, without the fragment.
+    target: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, ClientError> {
+        let invalid = |why: &str| ClientError::InvalidUrl(format!("`{}` {}", url, why));
+
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| invalid("has no scheme"))?;
+        let secure = match scheme.to_ascii_lowercase().as_str() {
+            "http" => false,
+            "https" => true,
+            _ => return Err(invalid("isn't http or https")),
+        };
+        let rest = rest.split('#').next().unwrap_or("");
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        if authority.contains('@') {
+            return Err(invalid("has credentials, which aren't supported"));
+        }
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after) = bracketed.split_once(']').ok_or_else(|| invalid("has a bad IPv6 address"))?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid("has no host"));
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid("has a bad port"))?,
+            None if secure => 443,
+            None => 80,
+        };
+
+        Ok(Url {
+            origin: Origin {
+                secure,
+                host: host.to_ascii_lowercase(),
+                port,
+            },
+            target: if target.starts_with('/') {
+                target.to_string()
+            } else {
+                format!("/{}", target)
+            },
+        })
+    }
+
+    /// Where `location` points, from a response to a request for `path`
+    /// on `origin`.
+    fn resolve(origin: &Origin, path: &str, location: &str) -> Result<Url, ClientError> {
+        let location = location.trim();
+        let target = location.split('#').next().unwrap_or("");
+        let scheme = if origin.secure { "https" } else { "http" };
+
+        let has_scheme = location.split_once("://").is_some_and(|(scheme, _)| {
+            !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        });
+
+        if has_scheme {
+            Url::parse(location)
+        } else if location.starts_with("//") {
+            Url::parse(&format!("{}:{}", scheme, location))
+        } else if target.starts_with('/') {
+            Ok(Url {
+                origin: origin.clone(),
+                target: target.to_string(),
+            })
+        } else {
+            let directory = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+            Ok(Url {
+                origin: origin.clone(),
+                target: format!("/{}{}", directory.trim_start_matches('/'), target),
+            })
+        }
+    }
+}
+
+/// A connection to a server, with whatever of the next response has been
+/// read ahead.
+struct Connection {
+    reader: BufReader<Stream>,
+    idle_since: Instant,
+}
+
+impl Connection {
+    /// Sends `message` and reads the response to it, returning whether the
+    /// connection can be used again.
+    fn exchange(&mut self, message: &[u8], head: bool, max_size: usize) -> io::Result<(Response, bool)> {
+        let stream = self.reader.get_mut();
+        stream.write_all(message)?;
+        stream.flush()?;
+
+        let response = read_response(&mut self.reader, head, max_size)?;
+        let headers = &response.headers;
+        // A body without a length or chunks ends when the server closes.
+        let framed = head
+            || !response.status.has_body()
+            || headers.has_token("Transfer-Encoding", "chunked")
+            || headers.contains("Content-Length");
+        let reusable = framed && !headers.has_token("Connection", "close");
+
+        Ok((response, reusable))
+    }
+
+    /// Whether the server still seems to be holding the connection open:
+    /// it hasn't closed it, nor sent anything unasked for.
+    fn is_open(&self) -> bool {
+        if !self.reader.buffer().is_empty() {
+            return false;
+        }
+        let socket = self.reader.get_ref().socket();
+        if socket.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let waiting = matches!(socket.peek(&mut [0]), Err(ref e) if e.kind() == io::ErrorKind::WouldBlock);
+
+        socket.set_nonblocking(false).is_ok() && waiting
+    }
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    fn socket(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(socket) => socket,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// `request` as it goes on the wire to `origin`.
+fn request_bytes(origin: &Origin, request: &Request) -> Vec<u8> {
+    let mut head = format!("{} {}", request.method, request.path);
+    if let Some(query) = &request.query {
+        head.push('?');
+        head.push_str(query);
+    }
+    head.push_str(" HTTP/1.1\r\n");
+
+    let mut headers = request.headers.clone();
+    if !headers.contains("Host") {
+        headers.insert("Host", origin.authority());
+    }
+    // The body is always sent whole, with its length.
+    headers.remove("Transfer-Encoding");
+    headers.remove("Content-Length");
+    if !request.body.is_empty() || matches!(request.method, Method::Post | Method::Put | Method::Patch) {
+        headers.insert("Content-Length", request.body.len().to_string());
+    }
+    for (name, value) in headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(&request.body);
+    bytes
+}
+
+fn is_redirect(status: Status) -> bool {
+    matches!(
+        status,
+        Status::MovedPermanently | Status::Found | Status::SeeOther | Status::TemporaryRedirect | Status::PermanentRedirect
+    )
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        method,
+        Method::Get | Method::Head | Method::Put | Method::Delete | Method::Options | Method::Trace
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{Params, Router};
+    use crate::testing::TestServer;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn origin(secure: bool, host: &str, port: u16) -> Origin {
+        Origin {
+            secure,
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parses_urls() {
+        let url = Url::parse("HTTP://Example.com:8080/a/b?c=d#e").unwrap();
+        assert_eq!(origin(false, "example.com", 8080), url.origin);
+        assert_eq!("/a/b?c=d", url.target);
+
+        let url = Url::parse("https://[::1]?x").unwrap();
+        assert_eq!(origin(true, "::1", 443), url.origin);
+        assert_eq!("/?x", url.target);
+        assert_eq!("[::1]", url.origin.authority());
+
+        for bad in &["example.com/", "ftp://example.com/", "http://:80/", "http://a:b/", "http://u:p@host/"] {
+            assert!(Url::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn resolves_locations() {
+        let here = origin(false, "example.com", 80);
+        let resolve = |location| Url::resolve(&here, "/a/b", location).unwrap();
+
+        assert_eq!("/a/c", resolve("c").target);
+        assert_eq!("/c?d", resolve("/c?d#e").target);
+        assert_eq!("/c?next=http://other.com/", resolve("/c?next=http://other.com/").target);
+        assert_eq!(origin(false, "other.com", 80), resolve("//other.com/c").origin);
+        assert_eq!(origin(true, "other.com", 443), resolve("https://other.com/").origin);
+    }
+
+    #[test]
+    fn keeps_connections_open_between_requests() {
+        let port = |request: &Request, _: &Params| Response::text(200, request.remote_addr.unwrap().port().to_string());
+        let server = TestServer::start(Router::new().get("/", port).post("/", port));
+        let client = Client::new();
+
+        let first = client.get(&server.url("/")).unwrap();
+        let second = client.get(&server.url("/")).unwrap();
+        assert_eq!(first.body.as_bytes(), second.body.as_bytes());
+        let posted = client.post(&server.url("/"), "a=1").unwrap();
+        assert_ne!(first.body.as_bytes(), posted.body.as_bytes());
+
+        let fresh = Client::new().with_max_idle_per_host(0);
+        let third = fresh.get(&server.url("/")).unwrap();
+        let fourth = fresh.get(&server.url("/")).unwrap();
+        assert_ne!(third.body.as_bytes(), fourth.body.as_bytes());
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let server = TestServer::start(Router::new().get("/", |_: &Request, _: &Params| {
+            let chunks: Vec<io::Result<Vec<u8>>> = vec![Ok(b"hello".to_vec()), Ok(b" world".to_vec())];
+            Response::new(200).with_stream(chunks)
+        }));
+
+        let response = Client::new().get(&server.url("/")).unwrap();
+        assert_eq!(Some("chunked"), response.headers.get("Transfer-Encoding"));
+        assert_eq!(Some(&b"hello world"[..]), response.body.as_bytes());
+    }
+
+    #[test]
+    fn follows_redirects_up_to_the_limit() {
+        let server = TestServer::start(
+            Router::new()
+                .get("/old", |_: &Request, _: &Params| Response::new(301).with_header("Location", "new"))
+                .get("/new", |request: &Request, _: &Params| Response::text(200, request.method.to_string()))
+                .post("/form", |_: &Request, _: &Params| Response::new(303).with_header("Location", "/new"))
+                .get("/loop", |_: &Request, _: &Params| Response::new(302).with_header("Location", "/loop")),
+        );
+        let client = Client::new();
+
+        let response = client.get(&server.url("/old")).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"GET"[..]), client.post(&server.url("/form"), "a=1").unwrap().body.as_bytes());
+        assert!(matches!(client.get(&server.url("/loop")), Err(ClientError::TooManyRedirects(5))));
+
+        let response = client.clone().with_max_redirects(0).get(&server.url("/old")).unwrap();
+        assert_eq!(Status::MovedPermanently, response.status);
+    }
+
+    #[test]
+    fn retries_when_an_idle_connection_was_closed() {
+        // Answers one request on each connection, without saying it will
+        // close it.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                while !request.ends_with("\r\n\r\n") {
+                    if reader.read_line(&mut request).unwrap() == 0 {
+                        break;
+                    }
+                }
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+            }
+        });
+        let client = Client::new();
+
+        for _ in 0..3 {
+            assert_eq!(Some(&b"ok"[..]), client.get(&url).unwrap().body.as_bytes());
+        }
+    }
+
+    #[test]
+    fn reports_what_went_wrong() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let server = TestServer::start(Router::new().get("/slow", |_: &Request, _: &Params| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(200, "late")
+        }));
+        let client = Client::new().with_timeout(Duration::from_millis(50));
+
+        assert!(matches!(client.get(&format!("http://{}/", closed)), Err(ClientError::Connect(_))));
+        assert!(client.get(&server.url("/slow")).unwrap_err().is_timeout());
+        assert!(matches!(client.get("https://localhost/"), Err(ClientError::NoTls)));
+        assert!(matches!(
+            client.send(&server.url("/slow"), Request::new(Method::Get, "/")),
+            Err(ClientError::InvalidUrl(_))
+        ));
+    }
+}
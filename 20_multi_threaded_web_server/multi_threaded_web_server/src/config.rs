@@ -0,0 +1,650 @@
+//! Settings for the server binary, gathered from a TOML file, environment
+//! variables and command-line flags.
+//!
+//! Every setting goes by the same name in all three. In the file it is a
+//! key, with the `tls_` settings in a `[tls]` table:
+//!
+//! ```toml
+//! bind = ["127.0.0.1:7878"]
+//! workers = 4
+//! idle_timeout = 5
+//!
+//! [tls]
+//! cert = "cert.pem"
+//! key = "key.pem"
+//! ```
+//!
+//! As a flag it is `--idle-timeout 5` or `--idle-timeout=5`, and in the
+//! environment `WEB_SERVER_IDLE_TIMEOUT=5`. Flags win over the environment,
+//! which wins over the file. Lists are comma-separated outside the file, and
+//! relative paths in the file are relative to the file.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use toml::{Table, Value};
+
+use crate::connection::ConnectionConfig;
+
+/// What the environment variables holding settings start with.
+pub const ENV_PREFIX: &str = "WEB_SERVER_";
+
+pub const USAGE: &str = "\
+Usage: main [--config FILE] [--SETTING VALUE]...
+
+Settings can also be given in the config file, and in the environment as
+WEB_SERVER_SETTING, like WEB_SERVER_IDLE_TIMEOUT=5. Defaults in brackets.
+
+  --bind ADDRS               addresses to serve on [127.0.0.1:7878]
+  --document-root DIR        where the files served are [public]
+  --not-found-page FILE      page sent with 404 Not Found [404.html]
+  --log-level LEVEL          error, warn, info or debug [info]
+  --concurrency STRATEGY     pool, thread-per-connection or inline [pool]
+  --workers N                sets both --min-workers and --max-workers
+  --min-workers N            workers kept running [4]
+  --max-workers N            workers started under load [4]
+  --queue-capacity N         connections that may wait for a worker [256]
+  --event-loop-threads N     event loops to serve connections on, or 0
+                             to give each connection a worker [0]
+  --idle-timeout SECS        wait for the next request [5]
+  --header-timeout SECS      wait for a request's headers [10]
+  --body-timeout SECS        wait for a request's body [30]
+  --read-timeout SECS        wait for any one read [10]
+  --write-timeout SECS       wait for any one write [10]
+  --response-timeout SECS    wait for a response to be taken [60]
+  --drain-timeout SECS       wait for requests at shutdown [30]
+  --max-header-size BYTES    largest request head [8192]
+  --max-body-size BYTES      largest request body [1048576]
+  --max-requests N           requests per connection [100]
+  --tls-cert FILE            PEM certificate chain, to serve HTTPS
+  --tls-key FILE             PEM private key for it
+  --tls-redirect-from ADDRS  plain HTTP addresses that redirect to HTTPS
+
+Send SIGHUP to reload the settings.
+";
+
+/// Everything the server binary can be configured with.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The addresses to serve on. Each gets a server, and a pool of
+    /// workers, of its own.
+    pub bind: Vec<SocketAddr>,
+    pub document_root: PathBuf,
+    /// A page under the document root to send with 404 Not Found.
+    pub not_found_page: String,
+    pub log_level: LogLevel,
+    pub concurrency: Concurrency,
+    pub min_workers: usize,
+    pub max_workers: usize,
+    /// How many connections may wait for a worker before more are turned
+    /// away.
+    pub queue_capacity: usize,
+    /// `Some` to serve connections on that many event loops, rather than
+    /// giving each one a worker.
+    pub event_loop_threads: Option<usize>,
+    pub connection: ConnectionConfig,
+    pub drain_timeout: Duration,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Plain HTTP addresses that send clients on to HTTPS at the first
+    /// `bind` address.
+    pub tls_redirect_from: Vec<SocketAddr>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: vec![SocketAddr::from(([127, 0, 0, 1], 7878))],
+            document_root: PathBuf::from("public"),
+            not_found_page: "404.html".to_string(),
+            log_level: LogLevel::Info,
+            concurrency: Concurrency::Pool,
+            min_workers: 4,
+            max_workers: 4,
+            queue_capacity: 256,
+            event_loop_threads: None,
+            connection: ConnectionConfig::default(),
+            drain_timeout: Duration::from_secs(30),
+            tls_cert: None,
+            tls_key: None,
+            tls_redirect_from: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Gathers the settings from `args`, the command line without the
+    /// program name, and `vars`, the environment, and from the file named by
+    /// `--config` or `WEB_SERVER_CONFIG` if there is one.
+    pub fn load<A, V>(args: A, vars: V) -> Result<Config, ConfigError>
+        where
+            A: IntoIterator<Item = String>,
+            V: IntoIterator<Item = (String, String)>
+    {
+        let flags = parse_flags(args)?;
+        let mut env: Vec<Setting> = vars
+            .into_iter()
+            .filter_map(|(var, value)| {
+                let name = var.strip_prefix(ENV_PREFIX)?.to_ascii_lowercase();
+                Some(Setting {
+                    name,
+                    value: Raw::Text(value),
+                    source: Source::Env(var),
+                })
+            })
+            .collect();
+        // The environment has no order of its own; see `read_file`.
+        env.sort_by_key(|setting| setting.name != "workers");
+
+        let file = flags
+            .iter()
+            .chain(&env)
+            .find(|setting| setting.name == "config")
+            .map(|setting| match &setting.value {
+                Raw::Text(path) => PathBuf::from(path),
+                Raw::Toml(_) => unreachable!(),
+            });
+
+        let mut config = Config::default();
+        if let Some(path) = file {
+            for setting in read_file(&path)? {
+                config.set(setting)?;
+            }
+        }
+        for setting in env.into_iter().chain(flags) {
+            if setting.name != "config" {
+                config.set(setting)?;
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn set(&mut self, setting: Setting) -> Result<(), ConfigError> {
+        let base = match &setting.source {
+            Source::File { path, .. } => path.parent(),
+            _ => None,
+        };
+        let value = &setting.value;
+
+        let result = match setting.name.as_str() {
+            "bind" => addresses(value).map(|bind| self.bind = bind),
+            "document_root" => path(value, base).map(|path| self.document_root = path),
+            "not_found_page" => text(value).map(|page| self.not_found_page = page),
+            "log_level" => text(value).and_then(|level| level.parse()).map(|level| self.log_level = level),
+            "concurrency" => text(value)
+                .and_then(|strategy| strategy.parse())
+                .map(|strategy| self.concurrency = strategy),
+            "workers" => count(value).map(|n| {
+                self.min_workers = n;
+                self.max_workers = n;
+            }),
+            "min_workers" => whole(value).map(|n| self.min_workers = n),
+            "max_workers" => count(value).map(|n| self.max_workers = n),
+            "queue_capacity" => count(value).map(|n| self.queue_capacity = n),
+            "event_loop_threads" => whole(value).map(|n| self.event_loop_threads = Some(n).filter(|&n| n > 0)),
+            "idle_timeout" => seconds(value).map(|d| self.connection.idle_timeout = d),
+            "header_timeout" => seconds(value).map(|d| self.connection.header_timeout = d),
+            "body_timeout" => seconds(value).map(|d| self.connection.body_timeout = d),
+            "read_timeout" => seconds(value).map(|d| self.connection.read_timeout = d),
+            "write_timeout" => seconds(value).map(|d| self.connection.write_timeout = d),
+            "response_timeout" => seconds(value).map(|d| self.connection.response_timeout = d),
+            "drain_timeout" => seconds(value).map(|d| self.drain_timeout = d),
+            "max_header_size" => count(value).map(|n| self.connection.max_header_size = n),
+            "max_body_size" => whole(value).map(|n| self.connection.max_body_size = n),
+            "max_requests" => count(value).map(|n| self.connection.max_requests = n),
+            "tls_cert" => path(value, base).map(|path| self.tls_cert = Some(path)),
+            "tls_key" => path(value, base).map(|path| self.tls_key = Some(path)),
+            "tls_redirect_from" => addresses(value).map(|addrs| self.tls_redirect_from = addrs),
+            _ => return Err(ConfigError::Unknown(setting.source)),
+        };
+
+        result.map_err(|message| ConfigError::Invalid {
+            source: setting.source,
+            message,
+        })
+    }
+
+    /// Checks the settings that only make sense together.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let problem = |message: String| Err(ConfigError::Inconsistent(message));
+
+        if self.bind.is_empty() {
+            return problem("bind needs at least one address".to_string());
+        }
+        if self.min_workers > self.max_workers {
+            return problem(format!(
+                "min_workers ({}) is greater than max_workers ({})",
+                self.min_workers, self.max_workers
+            ));
+        }
+        if !self.document_root.is_dir() {
+            return problem(format!("document_root {} isn't a directory", self.document_root.display()));
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => return problem("tls_cert is set but tls_key isn't".to_string()),
+            (None, Some(_)) => return problem("tls_key is set but tls_cert isn't".to_string()),
+            _ => {}
+        }
+        if !self.tls_redirect_from.is_empty() && self.tls_cert.is_none() {
+            return problem("tls_redirect_from needs tls_cert and tls_key".to_string());
+        }
+        if let Some(addr) = self.tls_redirect_from.iter().find(|addr| self.bind.contains(addr)) {
+            return problem(format!("{} is in both bind and tls_redirect_from", addr));
+        }
+
+        Ok(())
+    }
+}
+
+/// How much the server binary logs, from least to most. Also how serious
+/// each message passed to a `log::Logger` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    /// Adds a line for every request, and the pool's workers coming and
+    /// going.
+    Info,
+    /// Adds every job the pool runs.
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogLevel, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("expected error, warn, info or debug, got `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        })
+    }
+}
+
+/// What the server runs connections, or with event loops handlers, on;
+/// see `executor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Concurrency {
+    /// A `ThreadPool` sized by the worker settings.
+    Pool,
+    ThreadPerConnection,
+    /// The thread accepting connections, one connection at a time.
+    Inline,
+}
+
+impl FromStr for Concurrency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Concurrency, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "pool" => Ok(Concurrency::Pool),
+            "thread-per-connection" => Ok(Concurrency::ThreadPerConnection),
+            "inline" => Ok(Concurrency::Inline),
+            _ => Err(format!("expected pool, thread-per-connection or inline, got `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for Concurrency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Concurrency::Pool => "pool",
+            Concurrency::ThreadPerConnection => "thread-per-connection",
+            Concurrency::Inline => "inline",
+        })
+    }
+}
+
+/// Where a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File { path: PathBuf, key: String },
+    Env(String),
+    Flag(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File { path, key } => write!(f, "`{}` in {}", key, path.display()),
+            Source::Env(var) => write!(f, "{} in the environment", var),
+            Source::Flag(flag) => write!(f, "{} on the command line", flag),
+        }
+    }
+}
+
+/// Why the settings couldn't be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The command line isn't a list of flags and their values.
+    Usage(String),
+    Read { path: PathBuf, error: io::Error },
+    Parse { path: PathBuf, error: toml::de::Error },
+    /// A setting the server doesn't have, probably misspelt.
+    Unknown(Source),
+    /// A setting with a value it can't take.
+    Invalid { source: Source, message: String },
+    /// Settings that don't fit together.
+    Inconsistent(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Usage(message) | ConfigError::Inconsistent(message) => f.write_str(message),
+            ConfigError::Read { path, error } => write!(f, "can't read {}: {}", path.display(), error),
+            ConfigError::Parse { path, error } => write!(f, "{} isn't valid TOML: {}", path.display(), error),
+            ConfigError::Unknown(source) => write!(f, "unknown setting {}", source),
+            ConfigError::Invalid { source, message } => write!(f, "bad value for {}: {}", source, message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Read { error, .. } => Some(error),
+            ConfigError::Parse { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// A setting as given, before it is checked.
+struct Setting {
+    /// The file key, with `tls.` written `tls_`.
+    name: String,
+    value: Raw,
+    source: Source,
+}
+
+/// Values from the file are typed; flags and environment variables are
+/// always text.
+enum Raw {
+    Toml(Value),
+    Text(String),
+}
+
+fn parse_flags<A: IntoIterator<Item = String>>(args: A) -> Result<Vec<Setting>, ConfigError> {
+    let mut args = args.into_iter();
+    let mut settings = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => return Err(ConfigError::Usage(format!("unexpected argument `{}`; try --help", arg))),
+        };
+        let (flag, value) = match flag.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), value.to_string()),
+            None => match args.next() {
+                Some(value) => (flag.to_string(), value),
+                None => return Err(ConfigError::Usage(format!("--{} needs a value", flag))),
+            },
+        };
+
+        settings.push(Setting {
+            name: flag.replace('-', "_"),
+            value: Raw::Text(value),
+            source: Source::Flag(format!("--{}", flag)),
+        });
+    }
+
+    // As in `read_file`: `--workers` sets both bounds, so it goes first
+    // wherever it was given.
+    settings.sort_by_key(|setting| setting.name != "workers");
+    Ok(settings)
+}
+
+fn read_file(path: &Path) -> Result<Vec<Setting>, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|error| ConfigError::Read {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let table: Table = text.parse().map_err(|error| ConfigError::Parse {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    let mut settings = Vec::new();
+    let mut add = |name: String, key: String, value: Value| {
+        settings.push(Setting {
+            name,
+            value: Raw::Toml(value),
+            source: Source::File {
+                path: path.to_path_buf(),
+                key,
+            },
+        })
+    };
+    for (key, value) in table {
+        match value {
+            Value::Table(tls) if key == "tls" => {
+                for (key, value) in tls {
+                    add(format!("tls_{}", key), format!("tls.{}", key), value);
+                }
+            }
+            value => add(key.clone(), key, value),
+        }
+    }
+
+    // The keys come sorted, so make sure `workers` doesn't overrule
+    // `min_workers` and `max_workers` given alongside it.
+    settings.sort_by_key(|setting| setting.name != "workers");
+    Ok(settings)
+}
+
+fn text(value: &Raw) -> Result<String, String> {
+    match value {
+        Raw::Toml(Value::String(s)) | Raw::Text(s) => Ok(s.clone()),
+        Raw::Toml(value) => Err(format!("expected a string, got {}", value)),
+    }
+}
+
+fn whole(value: &Raw) -> Result<usize, String> {
+    match value {
+        Raw::Toml(Value::Integer(n)) => usize::try_from(*n).map_err(|_| format!("expected a whole number, got {}", n)),
+        Raw::Toml(value) => Err(format!("expected a whole number, got {}", value)),
+        Raw::Text(s) => s.trim().parse().map_err(|_| format!("expected a whole number, got `{}`", s)),
+    }
+}
+
+fn count(value: &Raw) -> Result<usize, String> {
+    match whole(value)? {
+        0 => Err("must be at least 1".to_string()),
+        n => Ok(n),
+    }
+}
+
+fn seconds(value: &Raw) -> Result<Duration, String> {
+    let secs = match value {
+        Raw::Toml(Value::Integer(n)) => *n as f64,
+        Raw::Toml(Value::Float(secs)) => *secs,
+        Raw::Toml(value) => return Err(format!("expected a number of seconds, got {}", value)),
+        Raw::Text(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| format!("expected a number of seconds, got `{}`", s))?,
+    };
+
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| format!("expected a positive number of seconds, got {}", secs))
+}
+
+fn addresses(value: &Raw) -> Result<Vec<SocketAddr>, String> {
+    let items = match value {
+        Raw::Toml(Value::Array(items)) => items.iter().map(|item| text(&Raw::Toml(item.clone()))).collect::<Result<_, _>>()?,
+        Raw::Toml(Value::String(item)) => vec![item.clone()],
+        Raw::Toml(value) => return Err(format!("expected a list of addresses, got {}", value)),
+        Raw::Text(list) => list.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect(),
+    };
+
+    items
+        .iter()
+        .map(|item: &String| {
+            item.parse()
+                .map_err(|_| format!("`{}` isn't an address and port, like 127.0.0.1:7878", item))
+        })
+        .collect()
+}
+
+fn path(value: &Raw, base: Option<&Path>) -> Result<PathBuf, String> {
+    let path = PathBuf::from(text(value)?);
+
+    Ok(match base {
+        Some(base) if path.is_relative() => base.join(path),
+        _ => path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(var, value)| (var.to_string(), value.to_string())).collect()
+    }
+
+    fn error(args_given: &[&str]) -> String {
+        Config::load(args(args_given), Vec::new()).unwrap_err().to_string()
+    }
+
+    /// Writes `contents` to a config file in a directory of its own, with
+    /// a `public` directory next to it.
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("config-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(dir.join("public")).unwrap();
+        let path = dir.join("server.toml");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn flags_beat_the_environment_which_beats_the_file() {
+        let path = config_file(
+            "layers",
+            "bind = [\"127.0.0.1:8080\", \"[::1]:8080\"]\n\
+             document_root = \"public\"\n\
+             workers = 2\n\
+             max_workers = 6\n\
+             idle_timeout = 1.5\n\
+             log_level = \"warn\"\n\
+             concurrency = \"thread-per-connection\"\n\
+             \n\
+             [tls]\n\
+             cert = \"cert.pem\"\n\
+             key = \"/etc/key.pem\"\n",
+        );
+        let config = Config::load(
+            args(&["--config", path.to_str().unwrap(), "--log-level=debug", "--max-body-size", "0"]),
+            vars(&[("WEB_SERVER_LOG_LEVEL", "error"), ("WEB_SERVER_IDLE_TIMEOUT", "3"), ("HOME", "/root")]),
+        )
+        .unwrap();
+        let dir = path.parent().unwrap();
+
+        assert_eq!(2, config.bind.len());
+        assert_eq!(dir.join("public"), config.document_root);
+        assert_eq!((2, 6), (config.min_workers, config.max_workers));
+        assert_eq!(Duration::from_secs(3), config.connection.idle_timeout);
+        assert_eq!(LogLevel::Debug, config.log_level);
+        assert_eq!(Concurrency::ThreadPerConnection, config.concurrency);
+        assert_eq!(0, config.connection.max_body_size);
+        assert_eq!(Some(dir.join("cert.pem")), config.tls_cert);
+        assert_eq!(Some(PathBuf::from("/etc/key.pem")), config.tls_key);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn workers_never_overrules_the_bounds_given_with_it() {
+        for given in [["--max-workers", "6", "--workers", "2"], ["--workers", "2", "--max-workers", "6"]] {
+            let config = Config::load(args(&given), Vec::new()).unwrap();
+
+            assert_eq!((2, 6), (config.min_workers, config.max_workers));
+        }
+    }
+
+    #[test]
+    fn document_root_defaults_to_public_in_the_working_directory() {
+        // `cargo test` runs in the package directory, which has one.
+        let config = Config::load(Vec::new(), Vec::new()).unwrap();
+
+        assert_eq!(PathBuf::from("public"), config.document_root);
+    }
+
+    #[test]
+    fn says_which_setting_is_wrong_and_where() {
+        let path = config_file("errors", "idle_timout = 5\n");
+
+        assert_eq!(
+            format!("unknown setting `idle_timout` in {}", path.display()),
+            error(&["--config", path.to_str().unwrap()])
+        );
+        assert_eq!(
+            "bad value for --workers on the command line: expected a whole number, got `four`",
+            error(&["--workers", "four"])
+        );
+        assert_eq!(
+            "bad value for --bind on the command line: `localhost` isn't an address and port, like 127.0.0.1:7878",
+            error(&["--bind", "localhost"])
+        );
+        assert_eq!(
+            "bad value for WEB_SERVER_READ_TIMEOUT in the environment: expected a positive number of seconds, got 0",
+            Config::load(Vec::new(), vars(&[("WEB_SERVER_READ_TIMEOUT", "0")])).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "bad value for --concurrency on the command line: expected pool, thread-per-connection or inline, got `async`",
+            error(&["--concurrency", "async"])
+        );
+        assert_eq!("--workers needs a value", error(&["--workers"]));
+        assert_eq!("unexpected argument `8`; try --help", error(&["8"]));
+
+        fs::write(&path, "workers = \n").unwrap();
+        assert!(error(&["--config", path.to_str().unwrap()]).contains("isn't valid TOML"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn checks_settings_fit_together() {
+        assert_eq!(
+            "min_workers (8) is greater than max_workers (4)",
+            error(&["--min-workers", "8"])
+        );
+        assert_eq!("tls_cert is set but tls_key isn't", error(&["--tls-cert", "cert.pem"]));
+        assert_eq!(
+            "tls_redirect_from needs tls_cert and tls_key",
+            error(&["--tls-redirect-from", "127.0.0.1:80"])
+        );
+        assert_eq!(
+            "127.0.0.1:7878 is in both bind and tls_redirect_from",
+            error(&["--tls-cert", "c", "--tls-key", "k", "--tls-redirect-from", "127.0.0.1:7878"])
+        );
+        assert!(error(&["--document-root", "/no/such/dir"]).ends_with("isn't a directory"));
+    }
+}
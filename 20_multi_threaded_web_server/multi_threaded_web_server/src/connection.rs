@@ -0,0 +1,636 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use rustls::ServerConnection;
+
+use crate::log::{log, LogLevel};
+use crate::request::{ParseError, Request, RequestReader, Version};
+use crate::response::Response;
+use crate::router::Router;
+
+/// Limits on how long a client may hold on to a connection, and on how
+/// big its requests may be.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// How long to wait for the next request before closing the connection.
+    pub idle_timeout: Duration,
+    /// How many requests to answer before closing the connection.
+    pub max_requests: usize,
+    /// How long a client has to send the request line and headers once the
+    /// first byte of a request has arrived, however steadily it sends them.
+    pub header_timeout: Duration,
+    /// How long a client has to send a request body once the headers are
+    /// in, however steadily it sends it.
+    pub body_timeout: Duration,
+    /// How long any one read may wait once a request has started.
+    pub read_timeout: Duration,
+    /// How long any one write may wait for the client to take the data.
+    pub write_timeout: Duration,
+    /// How long a client has to take a whole response, however steadily it
+    /// reads it.
+    pub response_timeout: Duration,
+    /// The largest request line and headers accepted, in bytes. Larger ones
+    /// are answered with 431 Request Header Fields Too Large.
+    pub max_header_size: usize,
+    /// The largest request body accepted, in bytes. Larger ones are answered
+    /// with 413 Content Too Large.
+    pub max_body_size: usize,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> ConnectionConfig {
+        ConnectionConfig {
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(10),
+            write_timeout: Duration::from_secs(10),
+            response_timeout: Duration::from_secs(60),
+            max_header_size: 8 * 1024,
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+/// How long to keep reading from a client after refusing its request; see
+/// `refuse`.
+pub(crate) const LINGER: Duration = Duration::from_secs(1);
+
+/// Answers requests on `stream` until the client closes it, asks for it to
+/// be closed, goes quiet for longer than the idle timeout, or reaches the
+/// request limit. A client that stalls in the middle of a request, or sends
+/// one that is too big, gets an error response and the connection closed.
+///
+/// Requests are read and answered one at a time, so pipelined requests are
+/// answered in the order they were sent.
+pub fn handle_connection(stream: TcpStream, router: &Router, config: &ConnectionConfig) {
+    serve_connection(&stream, router, config, &ConnectionState::default());
+}
+
+/// Flags shared between a connection and the server that owns it.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionState {
+    /// Set by the server when it is shutting down, so that the connection
+    /// closes after the response it is working on.
+    pub draining: AtomicBool,
+    /// Set by the connection while it is waiting for the next request, when
+    /// it is safe for the server to close it.
+    pub idle: AtomicBool,
+}
+
+/// What requests are read from and responses written to: a client's socket,
+/// or a TLS connection on top of it.
+pub(crate) trait Transport: Read + Write {
+    /// The socket underneath, for setting timeouts.
+    fn socket(&self) -> &TcpStream;
+
+    /// Tells the client that nothing more is coming.
+    fn close_write(&mut self) -> io::Result<()> {
+        self.socket().shutdown(Shutdown::Write)
+    }
+
+    /// The TLS session, for carrying on with it after an upgrade.
+    fn into_tls(self) -> Option<ServerConnection>
+        where
+            Self: Sized
+    {
+        None
+    }
+}
+
+impl Transport for &TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+pub(crate) fn serve_connection<S: Transport>(
+    stream: S,
+    router: &Router,
+    config: &ConnectionConfig,
+    state: &ConnectionState,
+) {
+    if let Err(e) = serve(stream, router, config, state) {
+        if !is_disconnect(&e) {
+            log(LogLevel::Warn, format_args!("Error on connection: {}", e));
+        }
+    }
+}
+
+fn serve<S: Transport>(
+    stream: S,
+    router: &Router,
+    config: &ConnectionConfig,
+    state: &ConnectionState,
+) -> io::Result<()> {
+    let remote_addr = stream.socket().peer_addr().ok();
+
+    let mut reader = RequestReader::new(TimedStream::new(stream, config))
+        .with_max_header_size(config.max_header_size)
+        .with_max_body_size(config.max_body_size);
+    let mut served = 0;
+
+    loop {
+        // The first request may have been sent, and the connection queued,
+        // before the server started shutting down, so it is waited for and
+        // answered, with the connection closed afterwards.
+        let first = served == 0;
+        state.idle.store(!first, Ordering::SeqCst);
+        if state.draining.load(Ordering::SeqCst) && !first {
+            return reader.get_mut().stream.close_write();
+        }
+        let buffered = !reader.buffer().is_empty();
+        reader.get_mut().start_request(buffered);
+
+        let head = reader.read_head();
+        let mut request = match head {
+            Ok(Some(request)) => request,
+            Ok(None) => return reader.get_mut().stream.close_write(),
+            Err(e) => return refuse_request(&mut reader, e),
+        };
+        request.remote_addr = remote_addr;
+        reader.get_mut().start_body();
+        if let Err(e) = reader.read_body(&mut request) {
+            return refuse_request(&mut reader, e);
+        }
+        state.idle.store(false, Ordering::SeqCst);
+        served += 1;
+
+        let mut response = router.handle(&request);
+        reader.get_mut().start_response();
+        if let Some(upgrade) = response.upgrade.take() {
+            let writer = reader.get_mut();
+            response.write_for(&request, writer)?;
+            writer.flush()?;
+
+            let (timed, buffered) = reader.into_parts();
+            let socket = timed.stream.socket().try_clone()?;
+            upgrade.start(socket, timed.stream.into_tls(), buffered, config);
+            return Ok(());
+        }
+        let draining = state.draining.load(Ordering::SeqCst);
+        let keep_alive = set_persistence(&mut response, &request, served, config, draining);
+
+        let writer = reader.get_mut();
+        response.write_for(&request, writer)?;
+        writer.flush()?;
+
+        if !keep_alive {
+            return writer.stream.close_write();
+        }
+    }
+}
+
+/// Sets the `Connection` and `Keep-Alive` headers on the response to
+/// `request`, the `served`th on its connection, and returns whether the
+/// connection stays open for another request afterwards.
+pub(crate) fn set_persistence(
+    response: &mut Response,
+    request: &Request,
+    served: usize,
+    config: &ConnectionConfig,
+    draining: bool,
+) -> bool {
+    let keep_alive = wants_keep_alive(request)
+        && !response.headers.has_token("Connection", "close")
+        && served < config.max_requests
+        // An HTTP/1.0 client can only tell where a streamed body ends by
+        // the connection closing.
+        && (request.version == Version::Http11 || response.body.len().is_some())
+        && !draining;
+
+    if keep_alive {
+        response.headers.insert("Connection", "keep-alive");
+        response.headers.insert(
+            "Keep-Alive",
+            format!("timeout={}, max={}", config.idle_timeout.as_secs(), config.max_requests - served),
+        );
+    } else {
+        response.headers.insert("Connection", "close");
+    }
+
+    keep_alive
+}
+
+/// The response to a request that couldn't be read because of `error`,
+/// which closes the connection. `started` says whether any of the request
+/// had arrived; a client that went quiet before starting one isn't owed a
+/// response, and neither is one whose connection failed, so for those the
+/// error is handed back.
+pub(crate) fn error_response(error: ParseError, started: bool) -> io::Result<Response> {
+    let status = match error {
+        ParseError::Io(ref e) if started && is_timeout(e) => 408,
+        ParseError::Io(e) => return Err(e),
+        ParseError::HeaderTooLarge => 431,
+        ParseError::BodyTooLarge => 413,
+        _ => 400,
+    };
+
+    Ok(Response::text(status, format!("{}\n", error)).with_header("Connection", "close"))
+}
+
+/// Answers a request that couldn't be read with the matching error, if the
+/// client is owed one, and closes the connection.
+fn refuse_request<S: Transport>(reader: &mut RequestReader<TimedStream<'_, S>>, error: ParseError) -> io::Result<()> {
+    let started = reader.get_ref().started || !reader.buffer().is_empty();
+    let response = error_response(error, started)?;
+    let timed = reader.get_mut();
+    timed.start_response();
+    refuse(timed, response)
+}
+
+/// Sends `response` and closes the connection.
+///
+/// Closing a socket with unread data in it makes it send a reset, which can
+/// reach the client before the response does, so read and throw away what
+/// the client sends for a little while first.
+fn refuse<S: Transport>(timed: &mut TimedStream<'_, S>, mut response: Response) -> io::Result<()> {
+    response.write_to(timed)?;
+    timed.flush()?;
+    timed.stream.close_write()?;
+
+    // Whatever arrives now is thrown away, so there's no need to decrypt it.
+    let mut socket = timed.stream.socket();
+    let deadline = Instant::now() + LINGER;
+    socket.set_read_timeout(Some(LINGER / 10))?;
+
+    let mut buffer = [0; 4096];
+    while Instant::now() < deadline {
+        match socket.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads from and writes to a client under the timeouts in a
+/// `ConnectionConfig`, so that one that stalls can't hold on to a worker.
+///
+/// Until the first byte of a request arrives, a read waits up to the idle
+/// timeout. After that, each read waits up to the read timeout, the whole
+/// request line and headers must arrive before the header timeout, and the
+/// body before the body timeout. Each write waits up to the write timeout,
+/// and the whole response must be taken before the response timeout.
+struct TimedStream<'a, S> {
+    stream: S,
+    config: &'a ConnectionConfig,
+    /// Whether any of the current request has arrived.
+    started: bool,
+    /// When the part of the request being read, the head or the body, must
+    /// be in by, and what to call the timeout if it isn't.
+    deadline: Option<(Instant, &'static str)>,
+    /// The read timeout the socket has now, to save setting it again.
+    timeout: Option<Duration>,
+    /// When the response being written must be taken by.
+    response_deadline: Option<Instant>,
+    /// The write timeout the socket has now.
+    write_timeout: Option<Duration>,
+}
+
+impl<'a, S: Transport> TimedStream<'a, S> {
+    fn new(stream: S, config: &'a ConnectionConfig) -> TimedStream<'a, S> {
+        TimedStream {
+            stream,
+            config,
+            started: false,
+            deadline: None,
+            timeout: None,
+            response_deadline: None,
+            write_timeout: None,
+        }
+    }
+
+    /// Gets ready for the next request. `buffered` is true if some of it
+    /// has already been read.
+    fn start_request(&mut self, buffered: bool) {
+        self.started = false;
+        self.deadline = None;
+
+        if buffered {
+            self.request_started();
+        }
+    }
+
+    fn request_started(&mut self) {
+        self.started = true;
+        self.deadline = Some((Instant::now() + self.config.header_timeout, "header timeout"));
+    }
+
+    /// The headers are in; the body has its own deadline.
+    fn start_body(&mut self) {
+        self.deadline = Some((Instant::now() + self.config.body_timeout, "body timeout"));
+    }
+
+    fn start_response(&mut self) {
+        self.response_deadline = Some(Instant::now() + self.config.response_timeout);
+    }
+
+    /// Gives the socket the write timeout for the next write, or fails if
+    /// the response is out of time.
+    fn before_write(&mut self) -> io::Result<()> {
+        let timeout = match self.response_deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "response timeout"));
+                }
+                left.min(self.config.write_timeout)
+            }
+            None => self.config.write_timeout,
+        };
+
+        if self.write_timeout != Some(timeout) {
+            self.stream.socket().set_write_timeout(Some(timeout))?;
+            self.write_timeout = Some(timeout);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, S: Transport> Read for TimedStream<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            _ if !self.started => self.config.idle_timeout,
+            Some((deadline, name)) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, name));
+                }
+                left.min(self.config.read_timeout)
+            }
+            None => self.config.read_timeout,
+        };
+
+        if self.timeout != Some(timeout) {
+            self.stream.socket().set_read_timeout(Some(timeout))?;
+            self.timeout = Some(timeout);
+        }
+
+        let n = self.stream.read(buf)?;
+        if n > 0 && !self.started {
+            self.request_started();
+        }
+        Ok(n)
+    }
+}
+
+impl<'a, S: Transport> Write for TimedStream<'a, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.before_write()?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.before_write()?;
+        self.stream.flush()
+    }
+}
+
+/// What a blocking socket read reports when its timeout runs out; which one
+/// depends on the platform.
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// HTTP/1.1 connections are persistent unless either side says otherwise;
+/// HTTP/1.0 ones only if the client asks.
+fn wants_keep_alive(request: &Request) -> bool {
+    match request.version {
+        Version::Http11 => !request.headers.has_token("Connection", "close"),
+        Version::Http10 => request.headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+/// Errors that just mean the client went away or went quiet.
+pub(crate) fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Params;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Starts a thread that runs `handle_connection` for one client, and
+    /// connects that client.
+    fn connect(config: ConnectionConfig) -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let router = Router::new()
+                .get("/files/huge", |_: &Request, _: &Params| Response::text(200, vec![b'x'; 32 * 1024 * 1024]))
+                .get("/:name", |_: &Request, params: &Params| {
+                    Response::text(200, params.get("name").unwrap().to_string())
+                })
+                .post("/:name", |request: &Request, _: &Params| {
+                    Response::text(200, String::from_utf8_lossy(&request.body).into_owned())
+                });
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &router, &config);
+        });
+
+        (TcpStream::connect(addr).unwrap(), server)
+    }
+
+    /// Runs `handle_connection` for one client and returns what it sent back.
+    fn exchange(config: ConnectionConfig, input: &[u8]) -> String {
+        let (mut client, server) = connect(config);
+        client.write_all(input).unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        server.join().unwrap();
+
+        output
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order() {
+        let output = exchange(
+            ConnectionConfig::default(),
+            b"GET /one HTTP/1.1\r\nHost: x\r\n\r\n\
+GET /two HTTP/1.1\r\nHost: x\r\n\r\n\
+GET /three HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+        );
+
+        let one = output.find("\r\n\r\none").unwrap();
+        let two = output.find("\r\n\r\ntwo").unwrap();
+        let three = output.find("\r\n\r\nthree").unwrap();
+        assert!(one < two && two < three);
+        assert_eq!(2, output.matches("Connection: keep-alive").count());
+        assert!(output.ends_with("Connection: close\r\nContent-Length: 5\r\n\r\nthree"));
+    }
+
+    #[test]
+    fn http_10_closes_by_default() {
+        let output = exchange(
+            ConnectionConfig::default(),
+            b"GET /one HTTP/1.0\r\n\r\nGET /two HTTP/1.0\r\n\r\n",
+        );
+
+        assert!(output.contains("Connection: close"));
+        assert!(!output.contains("two"));
+    }
+
+    #[test]
+    fn closes_after_max_requests() {
+        let config = ConnectionConfig {
+            max_requests: 2,
+            ..ConnectionConfig::default()
+        };
+        let output = exchange(
+            config,
+            b"GET /one HTTP/1.1\r\nHost: x\r\n\r\n\
+GET /two HTTP/1.1\r\nHost: x\r\n\r\n\
+GET /three HTTP/1.1\r\nHost: x\r\n\r\n",
+        );
+
+        assert!(output.contains("two"));
+        assert!(!output.contains("three"));
+    }
+
+    #[test]
+    fn idle_connections_time_out() {
+        let config = ConnectionConfig {
+            idle_timeout: Duration::from_millis(50),
+            ..ConnectionConfig::default()
+        };
+        let output = exchange(config, b"GET /one HTTP/1.1\r\nHost: x\r\n\r\n");
+
+        assert!(output.contains("one"));
+    }
+
+    #[test]
+    fn stalled_requests_get_408() {
+        let config = ConnectionConfig {
+            read_timeout: Duration::from_millis(50),
+            ..ConnectionConfig::default()
+        };
+        let output = exchange(config, b"GET /one HTTP/1.1\r\nHost: x\r\n");
+
+        assert!(output.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(output.contains("Connection: close"));
+    }
+
+    #[test]
+    fn trickled_headers_hit_the_header_timeout() {
+        let config = ConnectionConfig {
+            header_timeout: Duration::from_millis(200),
+            ..ConnectionConfig::default()
+        };
+        let (mut client, server) = connect(config);
+        let started = Instant::now();
+
+        let mut writer = client.try_clone().unwrap();
+        let trickle = thread::spawn(move || {
+            writer.write_all(b"GET /one HTTP/1.1\r\n").unwrap();
+            // Keep sending, but never finish the headers. Writes start to
+            // fail once the server gives up.
+            while writer.write_all(b"X").is_ok() && started.elapsed() < Duration::from_secs(5) {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        server.join().unwrap();
+        drop(client);
+        trickle.join().unwrap();
+
+        assert!(output.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn slow_readers_hit_the_response_timeout() {
+        let config = ConnectionConfig {
+            response_timeout: Duration::from_millis(300),
+            ..ConnectionConfig::default()
+        };
+        let (mut client, server) = connect(config);
+        let started = Instant::now();
+        client.write_all(b"GET /files/huge HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n").unwrap();
+
+        // Take nothing until the server has given up; each write on its own
+        // has far longer than the whole response.
+        server.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(3));
+        let mut received = Vec::new();
+        let _ = client.read_to_end(&mut received);
+
+        assert!(received.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(received.len() < 32 * 1024 * 1024);
+    }
+
+    #[test]
+    fn trickled_bodies_hit_the_body_timeout() {
+        let config = ConnectionConfig {
+            body_timeout: Duration::from_millis(200),
+            ..ConnectionConfig::default()
+        };
+        let (mut client, server) = connect(config);
+        let started = Instant::now();
+
+        let mut writer = client.try_clone().unwrap();
+        let trickle = thread::spawn(move || {
+            writer.write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 1000\r\n\r\n").unwrap();
+            while writer.write_all(b"X").is_ok() && started.elapsed() < Duration::from_secs(5) {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        server.join().unwrap();
+        drop(client);
+        trickle.join().unwrap();
+
+        assert!(output.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn oversized_headers_get_431() {
+        let config = ConnectionConfig {
+            max_header_size: 64,
+            ..ConnectionConfig::default()
+        };
+        let mut input = b"GET /one HTTP/1.1\r\nHost: x\r\nX-Padding: ".to_vec();
+        input.extend_from_slice(&[b'a'; 100]);
+        input.extend_from_slice(b"\r\n\r\n");
+        let output = exchange(config, &input);
+
+        assert!(output.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn oversized_bodies_get_413() {
+        let config = ConnectionConfig {
+            max_body_size: 4,
+            ..ConnectionConfig::default()
+        };
+        let output = exchange(
+            config,
+            b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\n0123456789",
+        );
+
+        assert!(output.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+    }
+}
@@ -0,0 +1,887 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{self, Shutdown, SocketAddr, TcpListener};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio::net::{TcpListener as Listener, TcpStream};
+use mio::{Events, Interest, Poll, Registry, Token, Waker};
+use rustls::ServerConnection;
+
+use crate::connection::{error_response, is_disconnect, set_persistence, LINGER};
+use crate::executor::Executor;
+use crate::log::{log, LogLevel};
+use crate::request::{Method, ParseError, Request, RequestReader, Version};
+use crate::response::{Pieces, Response};
+use crate::router::Router;
+use crate::server::{busy_response, ReloadHandle, Settings, ShutdownHandle};
+use crate::websocket::Upgrade;
+
+const LISTENER: Token = Token(0);
+const WAKER: Token = Token(1);
+
+/// How often connections are checked for timeouts, and how soon a loop
+/// notices that shutdown has been requested.
+const SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the event loops share with the `Server` running them.
+pub(crate) struct Context<'a> {
+    pub settings: &'a ReloadHandle,
+    pub executor: &'a dyn Executor,
+    pub shutdown: &'a ShutdownHandle,
+    pub drain_timeout: Duration,
+}
+
+/// Serves connections from `listener` on `threads` event loops until
+/// shutdown is requested, then until the open connections have finished or
+/// the drain timeout has passed. Returns false if connections were still
+/// open at the deadline.
+///
+/// Each loop accepts connections itself and keeps them for their whole
+/// life. Requests are read and responses written on the loop; only the
+/// handlers run on the executor.
+pub(crate) fn run(listener: &TcpListener, threads: usize, context: &Context) -> io::Result<bool> {
+    listener.set_nonblocking(true)?;
+    let loops = (0..threads.max(1))
+        .map(|_| EventLoop::new(listener, context))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    thread::scope(|scope| {
+        let mut threads = Vec::new();
+
+        for (i, mut event_loop) in loops.into_iter().enumerate() {
+            let spawned = thread::Builder::new()
+                .name(format!("event-loop-{}", i))
+                .spawn_scoped(scope, move || event_loop.run());
+
+            match spawned {
+                Ok(thread) => threads.push(thread),
+                Err(e) => {
+                    // Stop the loops that did start, or the scope would wait
+                    // for them forever.
+                    context.shutdown.shutdown();
+                    return Err(e);
+                }
+            }
+        }
+
+        // A loop only panics if the executor it calls does.
+        Ok(threads.into_iter().all(|thread| thread.join().unwrap_or(false)))
+    })
+}
+
+struct EventLoop<'a> {
+    poll: Poll,
+    /// Taken away once shutdown starts.
+    listener: Option<Listener>,
+    connections: HashMap<Token, Connection>,
+    next_token: usize,
+    finished: Receiver<Finished>,
+    env: Env<'a>,
+}
+
+/// What connections need from their loop to move along.
+struct Env<'a> {
+    context: &'a Context<'a>,
+    finished: Sender<Finished>,
+    waker: Arc<Waker>,
+    draining: bool,
+}
+
+impl<'a> EventLoop<'a> {
+    fn new(listener: &TcpListener, context: &'a Context<'a>) -> io::Result<EventLoop<'a>> {
+        let poll = Poll::new()?;
+        let mut listener = Listener::from_std(listener.try_clone()?);
+        poll.registry().register(&mut listener, LISTENER, Interest::READABLE)?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let (sender, finished) = mpsc::channel();
+
+        Ok(EventLoop {
+            poll,
+            listener: Some(listener),
+            connections: HashMap::new(),
+            next_token: WAKER.0 + 1,
+            finished,
+            env: Env {
+                context,
+                finished: sender,
+                waker,
+                draining: false,
+            },
+        })
+    }
+
+    /// Returns false if it gave up on connections at the drain deadline.
+    fn run(&mut self) -> bool {
+        let mut events = Events::with_capacity(1024);
+        let mut last_sweep = Instant::now();
+        let mut drain_deadline = None;
+
+        loop {
+            if let Err(e) = self.poll.poll(&mut events, Some(SWEEP_INTERVAL)) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                log(LogLevel::Error, format_args!("Event loop error: {}", e));
+                return false;
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER => self.accept(),
+                    // Just a nudge to look at the finished handlers below.
+                    WAKER => {}
+                    token => self.advance(token),
+                }
+            }
+            self.collect_finished();
+
+            let now = Instant::now();
+            if now - last_sweep >= SWEEP_INTERVAL {
+                self.sweep(now);
+                last_sweep = now;
+            }
+
+            if drain_deadline.is_none() && self.env.context.shutdown.is_requested() {
+                drain_deadline = Some(now + self.env.context.drain_timeout);
+                self.start_draining();
+            }
+            if let Some(deadline) = drain_deadline {
+                if self.connections.is_empty() {
+                    return true;
+                }
+                if now >= deadline {
+                    return false;
+                }
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        let listener = match &self.listener {
+            Some(listener) => listener,
+            None => return,
+        };
+
+        loop {
+            let (mut stream, addr) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log(LogLevel::Warn, format_args!("Error accepting connection: {}", e));
+                    return;
+                }
+            };
+
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            // Registering both at once saves changing the interest whenever
+            // the connection switches between reading and writing.
+            let interest = Interest::READABLE | Interest::WRITABLE;
+            if let Err(e) = self.poll.registry().register(&mut stream, token, interest) {
+                log(LogLevel::Warn, format_args!("Error accepting connection: {}", e));
+                continue;
+            }
+
+            let settings = self.env.context.settings.current();
+            let tls = match settings.tls.as_ref().map(|tls| tls.accept()).transpose() {
+                Ok(tls) => tls.map(Box::new),
+                Err(e) => {
+                    log(LogLevel::Warn, format_args!("Error accepting connection: {}", e));
+                    continue;
+                }
+            };
+            let socket = Socket {
+                wire: Wire {
+                    stream,
+                    last_active: Instant::now(),
+                },
+                tls,
+            };
+            self.connections.insert(token, Connection::new(socket, addr, settings));
+        }
+    }
+
+    fn advance(&mut self, token: Token) {
+        let open = match self.connections.get_mut(&token) {
+            Some(connection) => connection.advance(token, &self.env),
+            None => return,
+        };
+        if !open {
+            if let Some(connection) = self.connections.remove(&token) {
+                connection.hand_off(self.poll.registry());
+            }
+        }
+    }
+
+    /// Picks up what the handlers have finished and starts writing it.
+    fn collect_finished(&mut self) {
+        while let Ok(Finished { token, outcome }) = self.finished.try_recv() {
+            let connection = match self.connections.get_mut(&token) {
+                Some(connection) => connection,
+                // It was closed at the drain deadline.
+                None => continue,
+            };
+
+            match outcome {
+                Outcome::Answered(answer) => {
+                    let (request, mut response) = *answer;
+                    let config = &connection.settings.connection_config;
+                    let after = match response.upgrade.take() {
+                        Some(upgrade) => After::Upgrade(upgrade),
+                        None if set_persistence(&mut response, &request, connection.served, config, self.env.draining) => {
+                            After::KeepAlive
+                        }
+                        None => After::Close,
+                    };
+                    let pieces = response.into_pieces(request.version, request.method == Method::Head);
+                    connection.start_writing(pieces, after);
+                }
+                Outcome::Panicked => {
+                    let response = Response::text(500, "Internal Server Error\n").with_header("Connection", "close");
+                    connection.start_writing(response.into_pieces(Version::Http11, false), After::Close);
+                }
+                Outcome::NotRun => {
+                    connection.start_writing(busy_response().into_pieces(Version::Http11, false), After::Linger);
+                }
+            }
+
+            self.advance(token);
+        }
+    }
+
+    /// Deals with connections that have been quiet for too long.
+    fn sweep(&mut self, now: Instant) {
+        let expired: Vec<(Token, Expiry)> = self
+            .connections
+            .iter()
+            .filter_map(|(&token, connection)| connection.expired(now).map(|expiry| (token, expiry)))
+            .collect();
+
+        for (token, expiry) in expired {
+            match expiry {
+                Expiry::Close => {
+                    self.connections.remove(&token);
+                }
+                Expiry::RequestTimeout => {
+                    let timed_out = ParseError::Io(io::ErrorKind::TimedOut.into());
+                    let answering = match self.connections.get_mut(&token) {
+                        Some(connection) => connection.refuse(timed_out),
+                        None => continue,
+                    };
+                    if answering {
+                        self.advance(token);
+                    } else {
+                        self.connections.remove(&token);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops accepting, and closes the connections that are between
+    /// requests. The rest close once their current response is written.
+    fn start_draining(&mut self) {
+        if let Some(mut listener) = self.listener.take() {
+            let _ = self.poll.registry().deregister(&mut listener);
+        }
+        self.env.draining = true;
+        self.connections.retain(|_, connection| !connection.is_idle());
+    }
+}
+
+/// The result of a handler run on the pool, sent back to the loop that
+/// owns the connection.
+struct Finished {
+    token: Token,
+    outcome: Outcome,
+}
+
+enum Outcome {
+    Answered(Box<(Request, Response)>),
+    Panicked,
+    /// The pool turned the job away, or dropped it from a full queue.
+    NotRun,
+}
+
+/// Reports back to the loop when a handler job ends, however it ends:
+/// dropping it sends whatever outcome it has got to.
+struct Reply {
+    token: Token,
+    outcome: Outcome,
+    finished: Sender<Finished>,
+    waker: Arc<Waker>,
+}
+
+impl Reply {
+    fn set(&mut self, outcome: Outcome) {
+        self.outcome = outcome;
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        let outcome = mem::replace(&mut self.outcome, Outcome::NotRun);
+        // The loop is gone only if it gave up at the drain deadline.
+        let _ = self.finished.send(Finished { token: self.token, outcome });
+        let _ = self.waker.wake();
+    }
+}
+
+/// A connection and the bookkeeping for its timeouts.
+struct Connection {
+    reader: RequestReader<Socket>,
+    phase: Phase,
+    served: usize,
+    remote_addr: SocketAddr,
+    /// When the first byte of the request being read arrived.
+    started: Option<Instant>,
+    /// When the head of the request being read was in, and its body due.
+    body_started: Option<Instant>,
+    /// What the connection was accepted with.
+    settings: Arc<Settings>,
+}
+
+enum Phase {
+    /// Waiting for a request, or the rest of one. Holds the request once
+    /// its head is in.
+    Reading(Option<Request>),
+    /// A handler is working on the request.
+    Handling,
+    Writing {
+        pieces: Pieces,
+        piece: Vec<u8>,
+        written: usize,
+        after: After,
+        /// When the client must have taken the whole response by.
+        due: Instant,
+    },
+    /// Throwing away what the client sends after an error response, until
+    /// it stops or the time runs out; see `connection::refuse`.
+    Lingering { until: Instant },
+    /// Switched to another protocol, and waiting to be handed off the loop.
+    Upgraded(Upgrade),
+}
+
+/// What happens to a connection once its response is written.
+enum After {
+    KeepAlive,
+    Close,
+    Linger,
+    /// Leave the loop for whatever the response switched to.
+    Upgrade(Upgrade),
+}
+
+enum Expiry {
+    Close,
+    RequestTimeout,
+}
+
+impl Connection {
+    fn new(socket: Socket, remote_addr: SocketAddr, settings: Arc<Settings>) -> Connection {
+        let config = &settings.connection_config;
+        let reader = RequestReader::new(socket)
+            .with_max_header_size(config.max_header_size)
+            .with_max_body_size(config.max_body_size);
+
+        Connection {
+            reader,
+            phase: Phase::Reading(None),
+            served: 0,
+            remote_addr,
+            started: None,
+            body_started: None,
+            settings,
+        }
+    }
+
+    /// Waiting for a request with none of it read yet.
+    fn is_idle(&self) -> bool {
+        matches!(self.phase, Phase::Reading(None)) && self.reader.buffer().is_empty()
+    }
+
+    /// Does as much as can be done without blocking. Returns false once the
+    /// connection should be closed.
+    fn advance(&mut self, token: Token, env: &Env) -> bool {
+        loop {
+            match &mut self.phase {
+                Phase::Reading(head) => {
+                    let mut request = match head.take() {
+                        Some(request) => request,
+                        None => match self.reader.read_head() {
+                            Ok(Some(mut request)) => {
+                                request.remote_addr = Some(self.remote_addr);
+                                self.body_started = Some(Instant::now());
+                                request
+                            }
+                            Ok(None) => return false,
+                            Err(ParseError::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                                if self.started.is_none() && !self.reader.buffer().is_empty() {
+                                    self.started = Some(Instant::now());
+                                }
+                                return true;
+                            }
+                            Err(e) => {
+                                if !self.refuse(e) {
+                                    return false;
+                                }
+                                continue;
+                            }
+                        },
+                    };
+
+                    match self.reader.read_body(&mut request) {
+                        Ok(()) => {
+                            self.served += 1;
+                            self.started = None;
+                            self.body_started = None;
+                            self.phase = Phase::Handling;
+                            dispatch(token, request, &self.settings.router, env);
+                        }
+                        Err(ParseError::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                            self.phase = Phase::Reading(Some(request));
+                            return true;
+                        }
+                        Err(e) => {
+                            self.phase = Phase::Reading(Some(request));
+                            if !self.refuse(e) {
+                                return false;
+                            }
+                        }
+                    }
+                }
+                Phase::Handling => return true,
+                Phase::Writing { pieces, piece, written, after, .. } => {
+                    match write_pieces(self.reader.get_mut(), pieces, piece, written) {
+                        Ok(true) => {}
+                        Ok(false) => return true,
+                        Err(e) => {
+                            if !is_disconnect(&e) {
+                                log(LogLevel::Warn, format_args!("Error on connection: {}", e));
+                            }
+                            return false;
+                        }
+                    }
+
+                    match mem::replace(after, After::Close) {
+                        After::KeepAlive if !env.draining => self.phase = Phase::Reading(None),
+                        After::KeepAlive | After::Close => {
+                            let _ = self.reader.get_mut().close_write();
+                            return false;
+                        }
+                        After::Linger => {
+                            let _ = self.reader.get_mut().close_write();
+                            self.phase = Phase::Lingering {
+                                until: Instant::now() + LINGER,
+                            };
+                        }
+                        After::Upgrade(upgrade) => {
+                            self.phase = Phase::Upgraded(upgrade);
+                            return false;
+                        }
+                    }
+                }
+                Phase::Upgraded(_) => return false,
+                Phase::Lingering { .. } => {
+                    let mut buffer = [0; 4096];
+                    loop {
+                        match self.reader.get_mut().read(&mut buffer) {
+                            Ok(0) => return false,
+                            Ok(_) => {}
+                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                            Err(_) => return false,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Passes the connection on, if it has been upgraded, to carry on off
+    /// the loop with a blocking socket. Otherwise dropping it closes it.
+    fn hand_off(self, registry: &Registry) {
+        let upgrade = match self.phase {
+            Phase::Upgraded(upgrade) => upgrade,
+            _ => return,
+        };
+
+        let (socket, buffered) = self.reader.into_parts();
+        let mut stream = socket.wire.stream;
+        let stream = registry
+            .deregister(&mut stream)
+            .map(|_| net::TcpStream::from(stream))
+            .and_then(|stream| stream.set_nonblocking(false).map(|_| stream));
+        match stream {
+            Ok(stream) => {
+                let tls = socket.tls.map(|tls| *tls);
+                upgrade.start(stream, tls, buffered, &self.settings.connection_config);
+            }
+            Err(e) => log(LogLevel::Warn, format_args!("Error on connection: {}", e)),
+        }
+    }
+
+    fn start_writing(&mut self, pieces: Pieces, after: After) {
+        self.phase = Phase::Writing {
+            pieces,
+            piece: Vec::new(),
+            written: 0,
+            after,
+            due: Instant::now() + self.settings.connection_config.response_timeout,
+        };
+    }
+
+    /// Starts writing the response to a request that couldn't be read.
+    /// Returns false if the client isn't owed one and the connection should
+    /// just be closed.
+    fn refuse(&mut self, error: ParseError) -> bool {
+        let started = self.started.is_some()
+            || !self.reader.buffer().is_empty()
+            || matches!(self.phase, Phase::Reading(Some(_)));
+
+        match error_response(error, started) {
+            Ok(response) => {
+                self.start_writing(response.into_pieces(Version::Http11, false), After::Linger);
+                true
+            }
+            Err(e) => {
+                if !is_disconnect(&e) {
+                    log(LogLevel::Warn, format_args!("Error on connection: {}", e));
+                }
+                false
+            }
+        }
+    }
+
+    /// Applies the same timeouts as a connection served by a worker: the
+    /// idle timeout between requests, the header, body and read timeouts
+    /// while one arrives, and the write and response timeouts while
+    /// answering.
+    fn expired(&self, now: Instant) -> Option<Expiry> {
+        let config = &self.settings.connection_config;
+        let quiet = now - self.reader.get_ref().wire.last_active;
+
+        let (expired, expiry) = match &self.phase {
+            Phase::Reading(None) if self.is_idle() => (quiet >= config.idle_timeout, Expiry::Close),
+            Phase::Reading(None) => {
+                let header_time = self.started.map_or(Duration::ZERO, |started| now - started);
+                let expired = quiet >= config.read_timeout || header_time >= config.header_timeout;
+                (expired, Expiry::RequestTimeout)
+            }
+            Phase::Reading(Some(_)) => {
+                let body_time = self.body_started.map_or(Duration::ZERO, |started| now - started);
+                let expired = quiet >= config.read_timeout || body_time >= config.body_timeout;
+                (expired, Expiry::RequestTimeout)
+            }
+            Phase::Handling => (false, Expiry::Close),
+            Phase::Writing { due, .. } => (quiet >= config.write_timeout || now >= *due, Expiry::Close),
+            Phase::Lingering { until } => (now >= *until, Expiry::Close),
+            Phase::Upgraded(_) => (false, Expiry::Close),
+        };
+
+        if expired {
+            Some(expiry)
+        } else {
+            None
+        }
+    }
+}
+
+/// Runs the handler for `request` on the executor.
+fn dispatch(token: Token, request: Request, router: &Arc<Router>, env: &Env) {
+    let mut reply = Reply {
+        token,
+        outcome: Outcome::NotRun,
+        finished: env.finished.clone(),
+        waker: Arc::clone(&env.waker),
+    };
+    let router = Arc::clone(router);
+
+    // If the executor turns the job away, dropping it sends back `NotRun`.
+    let _ = env.context.executor.execute(Box::new(move || {
+        reply.set(Outcome::Panicked);
+        let response = router.handle(&request);
+        reply.set(Outcome::Answered(Box::new((request, response))));
+    }));
+}
+
+/// Writes pieces until they run out and are flushed, returning true, or the
+/// socket is full, returning false.
+fn write_pieces(
+    socket: &mut Socket,
+    pieces: &mut Pieces,
+    piece: &mut Vec<u8>,
+    written: &mut usize,
+) -> io::Result<bool> {
+    loop {
+        if *written == piece.len() {
+            match pieces.next() {
+                Some(next) => {
+                    *piece = next?;
+                    *written = 0;
+                    continue;
+                }
+                None => break,
+            }
+        }
+
+        match socket.write(&piece[*written..]) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => *written += n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    match socket.flush() {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// A client's connection: its socket, with TLS on top for HTTPS.
+struct Socket {
+    wire: Wire,
+    tls: Option<Box<ServerConnection>>,
+}
+
+impl Socket {
+    /// Tells the client that nothing more is coming, as far as that can be
+    /// done without blocking.
+    fn close_write(&mut self) -> io::Result<()> {
+        if let Some(tls) = &mut self.tls {
+            tls.send_close_notify();
+            // The alert is tiny; if even that doesn't fit, the client isn't
+            // reading anyway.
+            let _ = self.flush();
+        }
+        self.wire.stream.shutdown(Shutdown::Write)
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.tls {
+            Some(tls) => rustls::Stream::new(&mut **tls, &mut self.wire).read(buf),
+            None => self.wire.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    /// With TLS, the data may only be encrypted and queued, so `flush`
+    /// must also succeed before it is all on its way.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.tls {
+            Some(tls) => rustls::Stream::new(&mut **tls, &mut self.wire).write(buf),
+            None => self.wire.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.tls {
+            Some(tls) => rustls::Stream::new(&mut **tls, &mut self.wire).flush(),
+            None => self.wire.flush(),
+        }
+    }
+}
+
+/// A client's socket, remembering when data last went either way.
+struct Wire {
+    stream: TcpStream,
+    last_active: Instant,
+}
+
+impl Read for Wire {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        if n > 0 {
+            self.last_active = Instant::now();
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Wire {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.stream.write(buf)?;
+        if n > 0 {
+            self.last_active = Instant::now();
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::ConnectionConfig;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::router::{Params, Router};
+    use crate::server::{Server, ShutdownHandle};
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn start(config: ConnectionConfig) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<io::Result<()>>) {
+        let router = Router::new()
+            .get("/", |_: &Request, _: &Params| Response::text(200, "hi"))
+            .get("/big", |_: &Request, _: &Params| Response::text(200, vec![b'x'; 4 * 1024 * 1024]))
+            .get("/huge", |_: &Request, _: &Params| Response::text(200, vec![b'x'; 32 * 1024 * 1024]))
+            .get("/slow", |_: &Request, _: &Params| {
+                thread::sleep(Duration::from_millis(300));
+                Response::text(200, "done")
+            })
+            .get("/panic", |_: &Request, _: &Params| -> Response { panic!("handler failed") });
+        let server = Server::bind("127.0.0.1:0", router)
+            .unwrap()
+            .with_workers(1)
+            .with_event_loop(1)
+            .with_connection_config(config)
+            .with_drain_timeout(Duration::from_secs(5));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+
+        (addr, handle, thread::spawn(move || server.run()))
+    }
+
+    fn send(addr: SocketAddr, requests: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(requests.as_bytes()).unwrap();
+        stream
+    }
+
+    fn read_all(stream: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn idle_connections_dont_hold_the_workers() {
+        let (addr, handle, server) = start(ConnectionConfig::default());
+
+        // With one worker, any one of these would block everyone else if it
+        // had the worker to itself.
+        let _idle: Vec<TcpStream> = (0..20).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        let mut kept_alive = send(addr, "GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+        let mut buffer = [0; 1024];
+        let n = kept_alive.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..n]).contains("keep-alive"));
+
+        let started = Instant::now();
+        let response = read_all(&mut send(addr, "GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n"));
+        assert!(response.ends_with("hi"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order() {
+        let (addr, handle, server) = start(ConnectionConfig::default());
+
+        let response = read_all(&mut send(
+            addr,
+            "GET /slow HTTP/1.1\r\nHost: x\r\n\r\nGET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+        ));
+        let done = response.find("done").unwrap();
+        let hi = response.find("hi").unwrap();
+        assert!(done < hi);
+        assert!(response.ends_with("Connection: close\r\nContent-Length: 2\r\n\r\nhi"));
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn large_responses_wait_for_a_slow_reader() {
+        let (addr, handle, server) = start(ConnectionConfig::default());
+
+        let mut stream = send(addr, "GET /big HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+        // Let the socket buffers fill up before reading anything.
+        thread::sleep(Duration::from_millis(200));
+        let response = read_all(&mut stream);
+        assert!(response.contains("Content-Length: 4194304\r\n"));
+        assert_eq!(4 * 1024 * 1024, response.len() - response.find("\r\n\r\n").unwrap() - 4);
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn slow_readers_hit_the_response_timeout() {
+        let config = ConnectionConfig {
+            response_timeout: Duration::from_millis(300),
+            ..ConnectionConfig::default()
+        };
+        let (addr, handle, server) = start(config);
+
+        let mut stream = send(addr, "GET /huge HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+        thread::sleep(Duration::from_secs(1));
+        let mut received = Vec::new();
+        let _ = stream.read_to_end(&mut received);
+        assert!(received.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(received.len() < 32 * 1024 * 1024);
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn stalled_requests_get_408() {
+        let config = ConnectionConfig {
+            header_timeout: Duration::from_millis(200),
+            body_timeout: Duration::from_millis(200),
+            ..ConnectionConfig::default()
+        };
+        let (addr, handle, server) = start(config);
+
+        let response = read_all(&mut send(addr, "GET / HTTP/1.1\r\nHost: x\r\n"));
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        let response = read_all(&mut send(addr, "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\nabc"));
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn panicking_handlers_get_500() {
+        let (addr, handle, server) = start(ConnectionConfig::default());
+
+        let response = read_all(&mut send(addr, "GET /panic HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn in_flight_requests_finish_before_run_returns() {
+        let (addr, handle, server) = start(ConnectionConfig::default());
+
+        let mut idle = TcpStream::connect(addr).unwrap();
+        let mut slow = send(addr, "GET /slow HTTP/1.1\r\nHost: x\r\n\r\n");
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        let response = read_all(&mut slow);
+        assert!(response.contains("Connection: close"));
+        assert!(response.ends_with("done"));
+        assert_eq!("", read_all(&mut idle));
+        server.join().unwrap().unwrap();
+    }
+}
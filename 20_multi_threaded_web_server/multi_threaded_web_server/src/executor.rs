@@ -0,0 +1,93 @@
+//! Where a `Server` runs its work: each connection, or with an event loop
+//! each handler.
+//!
+//! A `ThreadPool` is the default. `ThreadPerConnection` starts a thread for
+//! every job instead, and `Inline` runs jobs on the thread that hands them
+//! over, which makes a single-threaded server that answers one connection
+//! at a time.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+use crate::pool::{ExecuteError, ThreadPool};
+
+/// Work done for the server: serving a connection, or running a handler.
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Runs the jobs a `Server` hands it. A job that is turned away is dropped,
+/// and the server tells the client it is busy.
+pub trait Executor: Send + Sync {
+    fn execute(&self, job: Job) -> Result<(), ExecuteError>;
+}
+
+impl Executor for ThreadPool {
+    fn execute(&self, job: Job) -> Result<(), ExecuteError> {
+        ThreadPool::execute(self, job)
+    }
+}
+
+/// Runs each job on the calling thread before returning. A job that
+/// panics is stopped there, as it would be on a pool's worker, rather than
+/// taking the calling thread with it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Inline;
+
+impl Executor for Inline {
+    fn execute(&self, job: Job) -> Result<(), ExecuteError> {
+        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+        Ok(())
+    }
+}
+
+/// Starts a thread for each job, without limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadPerConnection;
+
+impl Executor for ThreadPerConnection {
+    fn execute(&self, job: Job) -> Result<(), ExecuteError> {
+        thread::Builder::new()
+            .name("connection".to_string())
+            .spawn(job)
+            .map(|_| ())
+            .map_err(ExecuteError::Spawn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Runs a job on `executor`, returning the name of the thread it ran on.
+    fn run_on(executor: &dyn Executor) -> Option<String> {
+        let (sender, receiver) = mpsc::channel();
+        executor
+            .execute(Box::new(move || {
+                sender.send(thread::current().name().map(str::to_string)).unwrap();
+            }))
+            .unwrap();
+
+        receiver.recv().unwrap()
+    }
+
+    #[test]
+    fn inline_runs_jobs_on_the_caller() {
+        assert_eq!(thread::current().name().map(str::to_string), run_on(&Inline));
+    }
+
+    #[test]
+    fn inline_survives_panicking_jobs() {
+        assert!(Inline.execute(Box::new(|| panic!("oops"))).is_ok());
+    }
+
+    #[test]
+    fn thread_per_connection_starts_a_thread_each() {
+        assert_eq!(Some("connection".to_string()), run_on(&ThreadPerConnection));
+    }
+
+    #[test]
+    fn pools_are_executors() {
+        let pool = ThreadPool::new(1);
+        assert_ne!(thread::current().name().map(str::to_string), run_on(&pool));
+    }
+}
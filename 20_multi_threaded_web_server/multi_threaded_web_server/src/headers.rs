@@ -0,0 +1,111 @@
+use std::fmt;
+
+/// An ordered list of HTTP header fields.
+///
+/// Header names are compared case-insensitively, as required by RFC 7230, but
+/// the original spelling is kept so that headers are written back out the way
+/// they were given.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers { entries: Vec::new() }
+    }
+
+    /// Returns the first value for `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns every value for `name`, in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Returns true if `name` holds `token` in any of its comma-separated
+    /// values, ignoring ASCII case. Useful for `Connection` and similar lists.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Sets `name` to `value`, replacing any values it already had.
+    pub fn insert(&mut self, name: &str, value: impl Into<String>) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Adds a value for `name`, keeping any values it already had.
+    pub fn append(&mut self, name: &str, value: impl Into<String>) {
+        self.entries.push((name.to_string(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Debug for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_ignores_case() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+
+        assert_eq!(Some("text/html"), headers.get("content-type"));
+        assert_eq!(Some("text/html"), headers.get("CONTENT-TYPE"));
+    }
+
+    #[test]
+    fn insert_replaces_every_value() {
+        let mut headers = Headers::new();
+        headers.append("Accept", "text/html");
+        headers.append("accept", "text/plain");
+        headers.insert("ACCEPT", "*/*");
+
+        assert_eq!(vec!["*/*"], headers.get_all("Accept").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn finds_tokens_in_lists() {
+        let mut headers = Headers::new();
+        headers.append("Connection", "Upgrade, Keep-Alive");
+
+        assert!(headers.has_token("connection", "keep-alive"));
+        assert!(!headers.has_token("connection", "close"));
+    }
+}
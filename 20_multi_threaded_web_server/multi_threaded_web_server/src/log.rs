@@ -0,0 +1,44 @@
+//! Where the server reports what it can't hand back to a caller: errors on
+//! connections it was serving, and its own comings and goings.
+//!
+//! By default errors and warnings go to standard error and the rest to
+//! standard output. A program that wants them filtered or sent elsewhere
+//! installs a `Logger` with `set_logger`.
+
+use std::fmt;
+use std::sync::{PoisonError, RwLock};
+
+pub use crate::config::LogLevel;
+
+/// Receives the server's log messages.
+///
+/// It is called on whichever thread the message comes from, often one
+/// serving a connection, so it should be quick and must not panic.
+pub trait Logger: Send + Sync + 'static {
+    fn log(&self, level: LogLevel, message: &fmt::Arguments);
+}
+
+impl<F> Logger for F
+    where
+        F: Fn(LogLevel, &fmt::Arguments) + Send + Sync + 'static
+{
+    fn log(&self, level: LogLevel, message: &fmt::Arguments) {
+        self(level, message)
+    }
+}
+
+static LOGGER: RwLock<Option<Box<dyn Logger>>> = RwLock::new(None);
+
+/// Sends every message from now on to `logger`, in place of the default
+/// or whatever logger was set before.
+pub fn set_logger<L: Logger>(logger: L) {
+    *LOGGER.write().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(logger));
+}
+
+pub(crate) fn log(level: LogLevel, message: fmt::Arguments) {
+    match &*LOGGER.read().unwrap_or_else(PoisonError::into_inner) {
+        Some(logger) => logger.log(level, &message),
+        None if level <= LogLevel::Warn => eprintln!("{}", message),
+        None => println!("{}", message),
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+use super::{Middleware, Next};
+use crate::date::format_log_date;
+use crate::request::Request;
+use crate::response::Response;
+
+/// Writes a line for every request, in Apache's combined log format:
+///
+/// ```text
+/// 127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /a.gif HTTP/1.1" 200 2326 "http://example.com/" "Mozilla/5.0"
+/// ```
+///
+/// The line is written when the handler returns, before the response is
+/// sent. The size is that of the body as it stands then, or `-` if it is
+/// empty or streamed; wrap the log around `Compression` to see compressed
+/// sizes.
+pub struct AccessLog {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new<W: Write + Send + 'static>(out: W) -> AccessLog {
+        AccessLog { out: Mutex::new(Box::new(out)) }
+    }
+
+    pub fn stdout() -> AccessLog {
+        AccessLog::new(io::stdout())
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        let received = SystemTime::now();
+        let response = next.run(request);
+        let line = format_line(request, &response, received);
+
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        // Nowhere to report a failure to log; serving the request matters
+        // more.
+        let _ = out.write_all(line.as_bytes()).and_then(|_| out.flush());
+
+        response
+    }
+}
+
+fn format_line(request: &Request, response: &Response, received: SystemTime) -> String {
+    let client = request
+        .remote_addr
+        .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+    let size = match response.body.len() {
+        Some(len) if len > 0 => len.to_string(),
+        _ => "-".to_string(),
+    };
+
+    let mut line = format!(
+        "{} - - [{}] \"{} {}",
+        client,
+        format_log_date(received),
+        request.method,
+        escape(&request.path)
+    );
+    if let Some(query) = &request.query {
+        line.push('?');
+        line.push_str(&escape(query));
+    }
+    let _ = writeln!(
+        line,
+        " {}\" {} {} \"{}\" \"{}\"",
+        request.version,
+        response.status.code(),
+        size,
+        escape(request.header("Referer").unwrap_or("-")),
+        escape(request.header("User-Agent").unwrap_or("-"))
+    );
+
+    line
+}
+
+/// Escapes quotes, backslashes and anything unprintable, so that what a
+/// client sends can't break up or forge log lines.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_ascii_graphic() || c == ' ' => escaped.push(c),
+            c => {
+                let mut utf8 = [0; 4];
+                for byte in c.encode_utf8(&mut utf8).bytes() {
+                    let _ = write!(escaped, "\\x{:02x}", byte);
+                }
+            }
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+    use crate::router::{Params, Router};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn formats_combined_log_lines() {
+        let mut request = Request::new(Method::Get, "/a.gif?size=2");
+        request.remote_addr = Some("127.0.0.1:50000".parse().unwrap());
+        request.headers.insert("User-Agent", "curl/8.0 \"quoted\"\n");
+        let response = Response::text(200, "hello");
+        let received = UNIX_EPOCH + Duration::from_secs(971_186_136);
+
+        assert_eq!(
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /a.gif?size=2 HTTP/1.1\" 200 5 \
+             \"-\" \"curl/8.0 \\\"quoted\\\"\\x0a\"\n",
+            format_line(&request, &response, received)
+        );
+    }
+
+    #[test]
+    fn logs_each_request() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let log = Shared::default();
+        let router = Router::new()
+            .get("/", |_: &Request, _: &Params| Response::new(204))
+            .wrap(AccessLog::new(log.clone()));
+        router.handle(&Request::new(Method::Get, "/"));
+        router.handle(&Request::new(Method::Get, "/missing"));
+
+        let output = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].ends_with("\"GET / HTTP/1.1\" 204 - \"-\" \"-\""));
+        assert!(lines[1].contains("\"GET /missing HTTP/1.1\" 404 "));
+    }
+}
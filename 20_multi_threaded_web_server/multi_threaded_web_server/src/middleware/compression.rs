@@ -0,0 +1,366 @@
+use std::io::{self, Read, Write};
+use std::iter;
+use std::mem;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use super::{Middleware, Next};
+use crate::request::Request;
+use crate::response::{Body, Response};
+
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Compresses response bodies with gzip or deflate, whichever the client's
+/// `Accept-Encoding` prefers, preferring gzip on a tie.
+///
+/// Only text-like content types are compressed: `text/*`, JSON, JavaScript,
+/// XML and SVG. Bodies smaller than the minimum size aren't worth it and are
+/// sent as they are. Files and streams are compressed as they are sent, so
+/// their length isn't known up front and they go out chunked.
+pub struct Compression {
+    min_size: u64,
+    level: flate2::Compression,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Compression {
+    /// Compresses bodies of 1 KiB and over at the default level.
+    pub fn new() -> Compression {
+        Compression {
+            min_size: 1024,
+            level: flate2::Compression::default(),
+        }
+    }
+
+    pub fn with_min_size(mut self, bytes: u64) -> Compression {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Sets the compression level, from 0 (none) to 9 (smallest and
+    /// slowest).
+    pub fn with_level(mut self, level: u32) -> Compression {
+        self.level = flate2::Compression::new(level.min(9));
+        self
+    }
+
+    fn compress(&self, response: &mut Response, encoding: Encoding) -> io::Result<()> {
+        let mut encoder = Encoder::new(encoding, self.level);
+
+        response.body = match mem::replace(&mut response.body, Body::Bytes(Vec::new())) {
+            Body::Bytes(bytes) => {
+                encoder.write(&bytes)?;
+                encoder.finish()?;
+                Body::Bytes(encoder.take())
+            }
+            Body::File { file, len } => Body::Stream(Box::new(Compressed {
+                chunks: Box::new(read_chunks(file.take(len))),
+                encoder,
+                done: false,
+            })),
+            Body::Stream(chunks) => Body::Stream(Box::new(Compressed {
+                chunks,
+                encoder,
+                done: false,
+            })),
+        };
+
+        response.headers.insert("Content-Encoding", encoding.as_str());
+        // The compressed bytes differ from the original ones, so a strong
+        // validator no longer applies to them.
+        if let Some(etag) = response.headers.get("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{}", etag);
+                response.headers.insert("ETag", weak);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        let mut response = next.run(request);
+        if !is_compressible(&response) {
+            return response;
+        }
+
+        // Whether it is compressed or not, the response depends on the
+        // client's Accept-Encoding, and caches need to know.
+        response.headers.append("Vary", "Accept-Encoding");
+
+        let encoding = match negotiate(request.header("Accept-Encoding")) {
+            Some(encoding) => encoding,
+            None => return response,
+        };
+        if matches!(response.body.len(), Some(len) if len < self.min_size) {
+            return response;
+        }
+
+        // Only an in-memory body is compressed here, which fails only if the
+        // encoder does, and by then the original body is gone.
+        match self.compress(&mut response, encoding) {
+            Ok(()) => response,
+            Err(_) => Response::text(500, "Internal Server Error\n"),
+        }
+    }
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+fn is_compressible(response: &Response) -> bool {
+    if !response.status.has_body() || response.headers.contains("Content-Encoding") {
+        return false;
+    }
+
+    let content_type = match response.headers.get("Content-Type") {
+        Some(value) => value.split(';').next().unwrap_or("").trim().to_ascii_lowercase(),
+        None => return false,
+    };
+    content_type.starts_with("text/")
+        || content_type.ends_with("json")
+        || content_type.ends_with("javascript")
+        || content_type.ends_with("xml")
+}
+
+/// Picks the encoding the client rates highest in `Accept-Encoding`. A
+/// quality of 0 rules an encoding out, and `*` stands for any encoding not
+/// named.
+fn negotiate(accept_encoding: Option<&str>) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut any = None;
+
+    for item in accept_encoding?.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("q") {
+                    Some(value.trim().parse::<f32>().unwrap_or(0.0))
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(1.0);
+
+        match coding.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "deflate" => deflate = Some(quality),
+            "*" => any = Some(quality),
+            _ => {}
+        }
+    }
+
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding, level: flate2::Compression) -> Encoder {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), level)),
+            // HTTP's "deflate" is the zlib format, not a raw deflate stream.
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), level)),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write_all(data),
+            Encoder::Deflate(encoder) => encoder.write_all(data),
+        }
+    }
+
+    /// Pushes out everything written so far, so that the client can
+    /// decompress it without waiting for more.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.try_finish(),
+            Encoder::Deflate(encoder) => encoder.try_finish(),
+        }
+    }
+
+    /// Takes the compressed output produced so far.
+    fn take(&mut self) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(encoder) => mem::take(encoder.get_mut()),
+            Encoder::Deflate(encoder) => mem::take(encoder.get_mut()),
+        }
+    }
+}
+
+/// A streamed body, compressed a chunk at a time. Each chunk is flushed
+/// through the encoder, so that a stream that trickles out, like a feed of
+/// events, still reaches the client as it is produced.
+struct Compressed {
+    chunks: Box<dyn Iterator<Item = io::Result<Vec<u8>>> + Send>,
+    encoder: Encoder,
+    done: bool,
+}
+
+impl Iterator for Compressed {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        while !self.done {
+            let result = match self.chunks.next() {
+                Some(Ok(chunk)) => self.encoder.write(&chunk).and_then(|_| self.encoder.flush()),
+                Some(Err(e)) => Err(e),
+                None => {
+                    self.done = true;
+                    self.encoder.finish()
+                }
+            };
+            if let Err(e) = result {
+                self.done = true;
+                return Some(Err(e));
+            }
+
+            let output = self.encoder.take();
+            if !output.is_empty() {
+                return Some(Ok(output));
+            }
+        }
+        None
+    }
+}
+
+fn read_chunks<R: Read + Send>(mut reader: R) -> impl Iterator<Item = io::Result<Vec<u8>>> + Send {
+    iter::from_fn(move || {
+        let mut buffer = vec![0; READ_CHUNK];
+        loop {
+            return match reader.read(&mut buffer) {
+                Ok(0) => None,
+                Ok(n) => {
+                    buffer.truncate(n);
+                    Some(Ok(buffer))
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Some(Err(e)),
+            };
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+    use crate::router::{Params, Router};
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    const TEXT: &str = "All work and no play makes Jack a dull boy. ";
+
+    fn router() -> Router {
+        Router::new()
+            .get("/big", |_: &Request, _: &Params| {
+                Response::text(200, TEXT.repeat(100)).with_header("ETag", "\"v1\"")
+            })
+            .get("/small", |_: &Request, _: &Params| Response::text(200, TEXT))
+            .get("/stream", |_: &Request, _: &Params| {
+                Response::text(200, "")
+                    .with_stream((0..100).map(|_| Ok(TEXT.as_bytes().to_vec())))
+            })
+            .wrap(Compression::new())
+    }
+
+    fn get(path: &str, accept_encoding: &str) -> Response {
+        let mut request = Request::new(Method::Get, path);
+        request.headers.insert("Accept-Encoding", accept_encoding);
+        router().handle(&request)
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        match response.body {
+            Body::Bytes(bytes) => bytes,
+            Body::Stream(chunks) => chunks.flat_map(Result::unwrap).collect(),
+            Body::File { .. } => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn picks_the_preferred_encoding() {
+        assert_eq!(Some(Encoding::Gzip), negotiate(Some("deflate, gzip")));
+        assert_eq!(Some(Encoding::Deflate), negotiate(Some("gzip;q=0.5, deflate")));
+        assert_eq!(Some(Encoding::Deflate), negotiate(Some("gzip;q=0, *")));
+        assert_eq!(None, negotiate(Some("br, identity")));
+        assert_eq!(None, negotiate(None));
+    }
+
+    #[test]
+    fn gzips_large_text_bodies() {
+        let response = get("/big", "gzip, deflate");
+
+        assert_eq!(Some("gzip"), response.headers.get("Content-Encoding"));
+        assert_eq!(Some("W/\"v1\""), response.headers.get("ETag"));
+        assert!(response.headers.has_token("Vary", "Accept-Encoding"));
+
+        let mut text = String::new();
+        GzDecoder::new(&body(response)[..]).read_to_string(&mut text).unwrap();
+        assert_eq!(TEXT.repeat(100), text);
+    }
+
+    #[test]
+    fn deflates_streams() {
+        let response = get("/stream", "deflate");
+
+        assert_eq!(Some("deflate"), response.headers.get("Content-Encoding"));
+        assert_eq!(None, response.body.len());
+
+        let mut text = String::new();
+        ZlibDecoder::new(&body(response)[..]).read_to_string(&mut text).unwrap();
+        assert_eq!(TEXT.repeat(100), text);
+    }
+
+    #[test]
+    fn leaves_small_or_unwanted_bodies_alone() {
+        let small = get("/small", "gzip");
+        let unwanted = get("/big", "identity");
+
+        assert!(!small.headers.contains("Content-Encoding"));
+        assert!(!unwanted.headers.contains("Content-Encoding"));
+        assert_eq!(TEXT.repeat(100).into_bytes(), body(unwanted));
+    }
+}
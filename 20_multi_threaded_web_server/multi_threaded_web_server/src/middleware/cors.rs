@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use super::{Middleware, Next};
+use crate::request::{Method, Request};
+use crate::response::Response;
+
+/// Adds the CORS headers that let pages from other origins call the server
+/// from a browser, and answers their preflight requests.
+///
+/// Requests without an `Origin` header, or from an origin that isn't
+/// allowed, are passed on untouched; without the headers the browser keeps
+/// the response from the page.
+pub struct Cors {
+    /// `None` allows any origin.
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    /// `None` allows whatever headers the preflight asks for.
+    headers: Option<Vec<String>>,
+    exposed_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// Allows any origin to make `GET`, `HEAD` and `POST` requests with any
+    /// headers, without credentials.
+    pub fn new() -> Cors {
+        Cors {
+            origins: None,
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: None,
+            exposed_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allows `origin`, such as `https://example.com`. Once an origin has
+    /// been given, only the ones given are allowed.
+    pub fn with_allowed_origin(mut self, origin: &str) -> Cors {
+        self.origins.get_or_insert_with(Vec::new).push(origin.to_string());
+        self
+    }
+
+    pub fn with_allowed_methods(mut self, methods: &[Method]) -> Cors {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// Limits the request headers pages may send to `headers`, besides the
+    /// ones browsers always allow.
+    pub fn with_allowed_headers(mut self, headers: &[&str]) -> Cors {
+        self.headers = Some(headers.iter().map(|h| h.to_string()).collect());
+        self
+    }
+
+    /// Lets pages read `headers` from responses, besides the ones browsers
+    /// always expose.
+    pub fn with_exposed_headers(mut self, headers: &[&str]) -> Cors {
+        self.exposed_headers = headers.iter().map(|h| h.to_string()).collect();
+        self
+    }
+
+    /// Lets pages send cookies and read responses to requests that carry
+    /// them. The allowed origin is then always named in the response, never
+    /// `*`.
+    pub fn with_credentials(mut self) -> Cors {
+        self.credentials = true;
+        self
+    }
+
+    /// Lets browsers cache the answer to a preflight request for `max_age`.
+    pub fn with_max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        match &self.origins {
+            Some(origins) => origins.iter().any(|o| o.eq_ignore_ascii_case(origin)),
+            None => true,
+        }
+    }
+
+    fn add_origin(&self, response: &mut Response, origin: &str) {
+        if self.origins.is_none() && !self.credentials {
+            response.headers.insert("Access-Control-Allow-Origin", "*");
+        } else {
+            response.headers.insert("Access-Control-Allow-Origin", origin);
+            response.headers.append("Vary", "Origin");
+        }
+        if self.credentials {
+            response.headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    fn preflight(&self, request: &Request, origin: &str) -> Response {
+        let mut response = Response::new(204);
+        self.add_origin(&mut response, origin);
+
+        let methods: Vec<&str> = self.methods.iter().map(|m| m.as_str()).collect();
+        response.headers.insert("Access-Control-Allow-Methods", methods.join(", "));
+
+        let headers = match &self.headers {
+            Some(headers) => Some(headers.join(", ")),
+            None => request.header("Access-Control-Request-Headers").map(str::to_string),
+        };
+        if let Some(headers) = headers {
+            response.headers.insert("Access-Control-Allow-Headers", headers);
+        }
+        if let Some(max_age) = self.max_age {
+            response.headers.insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+
+        response
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        let origin = match request.header("Origin") {
+            Some(origin) if self.allows(origin) => origin,
+            _ => return next.run(request),
+        };
+
+        if request.method == Method::Options && request.header("Access-Control-Request-Method").is_some() {
+            return self.preflight(request, origin);
+        }
+
+        let mut response = next.run(request);
+        self.add_origin(&mut response, origin);
+        if !self.exposed_headers.is_empty() {
+            response.headers.insert("Access-Control-Expose-Headers", self.exposed_headers.join(", "));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{Params, Router};
+    use crate::status::Status;
+
+    fn from(origin: &str, method: Method) -> Request {
+        let mut request = Request::new(method, "/");
+        request.headers.insert("Origin", origin);
+        request
+    }
+
+    fn router(cors: Cors) -> Router {
+        Router::new()
+            .get("/", |_: &Request, _: &Params| Response::text(200, "hi"))
+            .wrap(cors)
+    }
+
+    #[test]
+    fn answers_preflight_requests() {
+        let router = router(Cors::new().with_max_age(Duration::from_secs(600)));
+        let mut request = from("https://example.com", Method::Options);
+        request.headers.insert("Access-Control-Request-Method", "POST");
+        request.headers.insert("Access-Control-Request-Headers", "content-type");
+        let response = router.handle(&request);
+
+        assert_eq!(Status::NoContent, response.status);
+        assert_eq!(Some("*"), response.headers.get("Access-Control-Allow-Origin"));
+        assert_eq!(Some("GET, HEAD, POST"), response.headers.get("Access-Control-Allow-Methods"));
+        assert_eq!(Some("content-type"), response.headers.get("Access-Control-Allow-Headers"));
+        assert_eq!(Some("600"), response.headers.get("Access-Control-Max-Age"));
+    }
+
+    #[test]
+    fn names_the_origin_when_restricted_or_with_credentials() {
+        let router = router(
+            Cors::new()
+                .with_allowed_origin("https://example.com")
+                .with_credentials()
+                .with_exposed_headers(&["X-Request-Id"]),
+        );
+        let response = router.handle(&from("https://example.com", Method::Get));
+
+        assert_eq!(Some("https://example.com"), response.headers.get("Access-Control-Allow-Origin"));
+        assert_eq!(Some("true"), response.headers.get("Access-Control-Allow-Credentials"));
+        assert_eq!(Some("X-Request-Id"), response.headers.get("Access-Control-Expose-Headers"));
+        assert!(response.headers.has_token("Vary", "Origin"));
+    }
+
+    #[test]
+    fn ignores_other_origins() {
+        let router = router(Cors::new().with_allowed_origin("https://example.com"));
+        let response = router.handle(&from("https://evil.example", Method::Get));
+        let preflight = router.handle(&from("https://evil.example", Method::Options));
+
+        assert_eq!(Status::Ok, response.status);
+        assert!(!response.headers.contains("Access-Control-Allow-Origin"));
+        assert_eq!(Status::MethodNotAllowed, preflight.status);
+    }
+}
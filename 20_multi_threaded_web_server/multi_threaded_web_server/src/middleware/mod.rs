@@ -0,0 +1,98 @@
+//! Code that runs around every request a `Router` handles.
+//!
+//! A middleware sees each request before the router does and can answer it
+//! itself, pass it on unchanged or altered, and change the response on the
+//! way back. Add them with `Router::wrap`.
+
+mod access_log;
+mod compression;
+mod cors;
+mod request_id;
+
+pub use access_log::AccessLog;
+pub use compression::Compression;
+pub use cors::Cors;
+pub use request_id::RequestId;
+
+use crate::request::Request;
+use crate::response::Response;
+
+/// Something that wraps the handling of a request.
+///
+/// Closures taking the request and the rest of the chain implement this
+/// automatically.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, request: &Request, next: Next) -> Response;
+}
+
+impl<F> Middleware for F
+    where
+        F: Fn(&Request, Next) -> Response + Send + Sync + 'static
+{
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        self(request, next)
+    }
+}
+
+/// The rest of the chain: the middleware added after this one, and then the
+/// router's own dispatch.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Fn(&Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a [Box<dyn Middleware>],
+        endpoint: &'a dyn Fn(&Request) -> Response,
+    ) -> Next<'a> {
+        Next { middleware, endpoint }
+    }
+
+    /// Passes `request` on and returns the response.
+    pub fn run(self, request: &Request) -> Response {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(request, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+    use crate::router::{Params, Router};
+
+    fn tag(name: &'static str) -> impl Middleware {
+        move |request: &Request, next: Next| {
+            let mut request = request.clone();
+            request.headers.append("X-Seen", name);
+            let response = next.run(&request);
+            response.with_header("X-Last", name)
+        }
+    }
+
+    #[test]
+    fn first_added_runs_outermost() {
+        let router = Router::new()
+            .get("/", |request: &Request, _: &Params| {
+                let seen: Vec<&str> = request.headers.get_all("X-Seen").collect();
+                Response::text(200, seen.join(","))
+            })
+            .wrap(tag("outer"))
+            .wrap(tag("inner"));
+        let response = router.handle(&Request::new(Method::Get, "/"));
+
+        assert_eq!(Some(&b"outer,inner"[..]), response.body.as_bytes());
+        assert_eq!(Some("outer"), response.headers.get("X-Last"));
+    }
+
+    #[test]
+    fn can_answer_without_the_router() {
+        let router = Router::new().wrap(|_: &Request, _: Next| Response::text(503, "down"));
+
+        assert_eq!(503, router.handle(&Request::new(Method::Get, "/")).status.code());
+    }
+}
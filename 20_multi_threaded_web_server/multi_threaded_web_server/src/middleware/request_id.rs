@@ -0,0 +1,112 @@
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Middleware, Next};
+use crate::request::Request;
+use crate::response::Response;
+
+/// Gives every request an ID, in a header on both the request the handlers
+/// see and the response, so that everything logged about one request can be
+/// tied together.
+///
+/// An ID that came with the request, say from a proxy in front of the
+/// server, is kept if it is at most 128 visible ASCII characters. Otherwise
+/// a new one is made up: unique within a run of the server, and unlikely to
+/// repeat across runs.
+pub struct RequestId {
+    header: String,
+    prefix: u32,
+    next: AtomicU64,
+}
+
+impl RequestId {
+    /// Uses the `X-Request-Id` header.
+    pub fn new() -> RequestId {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos() ^ elapsed.as_secs() as u32)
+            .unwrap_or(0);
+
+        RequestId {
+            header: "X-Request-Id".to_string(),
+            prefix: started ^ process::id().rotate_left(16),
+            next: AtomicU64::new(1),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str) -> RequestId {
+        self.header = name.to_string();
+        self
+    }
+
+    fn generate(&self) -> String {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        format!("{:08x}-{:06x}", self.prefix, n)
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> RequestId {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        let (id, mut response) = match request.header(&self.header) {
+            Some(id) if is_acceptable(id) => (id.to_string(), next.run(request)),
+            _ => {
+                let id = self.generate();
+                let mut request = request.clone();
+                request.headers.insert(&self.header, id.clone());
+                (id, next.run(&request))
+            }
+        };
+
+        response.headers.insert(&self.header, id);
+        response
+    }
+}
+
+fn is_acceptable(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+    use crate::router::{Params, Router};
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |request: &Request, _: &Params| {
+                Response::text(200, request.header("X-Request-Id").unwrap_or("none").to_string())
+            })
+            .wrap(RequestId::new())
+    }
+
+    #[test]
+    fn requests_get_an_id() {
+        let router = router();
+        let first = router.handle(&Request::new(Method::Get, "/"));
+        let second = router.handle(&Request::new(Method::Get, "/"));
+
+        let id = first.headers.get("X-Request-Id").unwrap();
+        assert_eq!(Some(id.as_bytes()), first.body.as_bytes());
+        assert_ne!(Some(id), second.headers.get("X-Request-Id"));
+    }
+
+    #[test]
+    fn keeps_an_acceptable_incoming_id() {
+        let router = router();
+        let mut request = Request::new(Method::Get, "/");
+        request.headers.insert("X-Request-Id", "abc-123");
+        let mut bad = Request::new(Method::Get, "/");
+        bad.headers.insert("X-Request-Id", "has spaces");
+
+        assert_eq!(Some("abc-123"), router.handle(&request).headers.get("X-Request-Id"));
+        assert_ne!(Some("has spaces"), router.handle(&bad).headers.get("X-Request-Id"));
+    }
+}
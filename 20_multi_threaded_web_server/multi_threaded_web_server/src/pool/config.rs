@@ -0,0 +1,97 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::error::PoolCreationError;
+use super::observer::PoolObserver;
+
+/// How many workers a `ThreadPool` runs.
+///
+/// The pool starts `min_workers` threads and keeps at least that many
+/// running. When jobs queue up faster than the workers take them, it starts
+/// more, up to `max_workers`. A worker beyond the minimum that has waited
+/// `keep_alive` without getting a job exits.
+///
+/// With a `queue_capacity`, at most that many jobs wait for a worker, and
+/// `overflow` decides what happens to a job that arrives when they are all
+/// taken. Without one the queue grows as needed.
+///
+/// The pool doesn't log anything itself; give it an `observer` to hear
+/// about workers coming and going, jobs, and shutdown.
+#[derive(Clone)]
+pub struct PoolConfig {
+    pub min_workers: usize,
+    pub max_workers: usize,
+    pub keep_alive: Duration,
+    pub queue_capacity: Option<usize>,
+    pub overflow: OverflowPolicy,
+    pub observer: Option<Arc<dyn PoolObserver>>,
+}
+
+/// What `ThreadPool::execute` does with a job when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait for a worker to take a job off the queue.
+    Block,
+    /// Return `ExecuteError::QueueFull`.
+    #[default]
+    Reject,
+    /// Run the job on the thread that called `execute`.
+    CallerRuns,
+    /// Throw away the job that has been waiting longest to make room.
+    DropOldest,
+}
+
+impl PoolConfig {
+    /// A pool that always runs exactly `size` workers.
+    pub fn fixed(size: usize) -> PoolConfig {
+        PoolConfig {
+            min_workers: size,
+            max_workers: size,
+            ..PoolConfig::default()
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), PoolCreationError> {
+        if self.queue_capacity == Some(0) {
+            return Err(PoolCreationError::ZeroCapacity);
+        }
+        check_bounds(self.min_workers, self.max_workers)
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            min_workers: 1,
+            max_workers: 8,
+            keep_alive: Duration::from_secs(60),
+            queue_capacity: None,
+            overflow: OverflowPolicy::default(),
+            observer: None,
+        }
+    }
+}
+
+impl fmt::Debug for PoolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PoolConfig")
+            .field("min_workers", &self.min_workers)
+            .field("max_workers", &self.max_workers)
+            .field("keep_alive", &self.keep_alive)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("overflow", &self.overflow)
+            .field("observer", &self.observer.as_ref().map(|_| "PoolObserver"))
+            .finish()
+    }
+}
+
+pub(super) fn check_bounds(min: usize, max: usize) -> Result<(), PoolCreationError> {
+    if max == 0 {
+        Err(PoolCreationError::ZeroSize)
+    } else if min > max {
+        Err(PoolCreationError::InvalidBounds { min, max })
+    } else {
+        Ok(())
+    }
+}
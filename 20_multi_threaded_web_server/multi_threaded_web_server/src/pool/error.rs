@@ -0,0 +1,73 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a `ThreadPool` couldn't be built.
+#[derive(Debug)]
+pub enum PoolCreationError {
+    /// A pool needs at least one worker.
+    ZeroSize,
+    /// `min_workers` was greater than `max_workers`.
+    InvalidBounds { min: usize, max: usize },
+    /// A bounded queue needs room for at least one job.
+    ZeroCapacity,
+    /// The operating system refused to start a worker thread.
+    Spawn(io::Error),
+}
+
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolCreationError::ZeroSize => f.write_str("a thread pool needs at least one thread"),
+            PoolCreationError::InvalidBounds { min, max } => {
+                write!(f, "min_workers ({}) is greater than max_workers ({})", min, max)
+            }
+            PoolCreationError::ZeroCapacity => f.write_str("a bounded job queue needs room for at least one job"),
+            PoolCreationError::Spawn(e) => write!(f, "failed to spawn a worker thread: {}", e),
+        }
+    }
+}
+
+impl Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolCreationError::ZeroSize
+            | PoolCreationError::InvalidBounds { .. }
+            | PoolCreationError::ZeroCapacity => None,
+            PoolCreationError::Spawn(e) => Some(e),
+        }
+    }
+}
+
+/// Why a `ThreadPool`, or another `Executor`, couldn't take a job.
+#[derive(Debug)]
+pub enum ExecuteError {
+    /// The pool has been shut down, or has no workers left to run the job.
+    ShutDown,
+    /// The queue is full and the pool's overflow policy is to reject jobs.
+    QueueFull,
+    /// A repeating job was given a period of zero.
+    ZeroPeriod,
+    /// The operating system refused to start a thread for the job.
+    Spawn(io::Error),
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecuteError::ShutDown => f.write_str("the thread pool has shut down"),
+            ExecuteError::QueueFull => f.write_str("the thread pool's queue is full"),
+            ExecuteError::ZeroPeriod => f.write_str("the period of a repeating job must not be zero"),
+            ExecuteError::Spawn(e) => write!(f, "failed to spawn a thread for the job: {}", e),
+        }
+    }
+}
+
+impl Error for ExecuteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ExecuteError::ShutDown | ExecuteError::QueueFull | ExecuteError::ZeroPeriod => None,
+            ExecuteError::Spawn(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// Why a submitted job didn't produce a value.
+pub enum JobError {
+    /// The job panicked. This holds the value it panicked with, as
+    /// `thread::JoinHandle::join` would.
+    Panicked(Box<dyn Any + Send + 'static>),
+    /// The job was dropped without being run, or its result was already
+    /// taken from the handle.
+    Cancelled,
+    /// `join_timeout` gave up waiting. The job may still finish later.
+    TimedOut,
+}
+
+impl JobError {
+    /// Returns the panic message, if the job panicked with a string.
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            JobError::Panicked(payload) => payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Panicked(_) => f
+                .debug_tuple("Panicked")
+                .field(&self.panic_message().unwrap_or("Box<dyn Any>"))
+                .finish(),
+            JobError::Cancelled => f.write_str("Cancelled"),
+            JobError::TimedOut => f.write_str("TimedOut"),
+        }
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Panicked(_) => match self.panic_message() {
+                Some(message) => write!(f, "job panicked: {}", message),
+                None => f.write_str("job panicked"),
+            },
+            JobError::Cancelled => f.write_str("job was cancelled before it produced a result"),
+            JobError::TimedOut => f.write_str("timed out waiting for job"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+/// A handle to the result of a job passed to `ThreadPool::submit`.
+///
+/// Dropping the handle doesn't cancel the job; its result is just thrown
+/// away.
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<thread::Result<T>>,
+    taken: bool,
+}
+
+impl<T> JobHandle<T> {
+    pub(crate) fn new(receiver: mpsc::Receiver<thread::Result<T>>) -> JobHandle<T> {
+        JobHandle {
+            receiver,
+            taken: false,
+        }
+    }
+
+    /// Blocks until the job has finished and returns its result.
+    pub fn join(self) -> Result<T, JobError> {
+        match self.receiver.recv() {
+            Ok(result) => result.map_err(JobError::Panicked),
+            Err(_) => Err(JobError::Cancelled),
+        }
+    }
+
+    /// Returns the job's result if it has finished, without blocking.
+    ///
+    /// Once a result has been returned, later calls return
+    /// `Some(Err(JobError::Cancelled))`.
+    pub fn try_join(&mut self) -> Option<Result<T, JobError>> {
+        if self.taken {
+            return Some(Err(JobError::Cancelled));
+        }
+
+        match self.receiver.try_recv() {
+            Ok(result) => {
+                self.taken = true;
+                Some(result.map_err(JobError::Panicked))
+            }
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(JobError::Cancelled)),
+        }
+    }
+
+    /// Blocks until the job has finished or `timeout` has passed. After a
+    /// `JobError::TimedOut` the handle can still be used to wait again.
+    pub fn join_timeout(&mut self, timeout: Duration) -> Result<T, JobError> {
+        if self.taken {
+            return Err(JobError::Cancelled);
+        }
+
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => {
+                self.taken = true;
+                result.map_err(JobError::Panicked)
+            }
+            Err(RecvTimeoutError::Timeout) => Err(JobError::TimedOut),
+            Err(RecvTimeoutError::Disconnected) => Err(JobError::Cancelled),
+        }
+    }
+}
+
+impl<T> fmt::Debug for JobHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JobHandle").finish()
+    }
+}
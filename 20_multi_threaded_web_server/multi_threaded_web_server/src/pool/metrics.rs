@@ -0,0 +1,181 @@
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in microseconds. Anything slower
+/// goes in a final, unbounded bucket.
+const BUCKET_BOUNDS_MICROS: [u64; 16] = [
+    50, 100, 250, 500,
+    1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 10_000_000,
+];
+
+const BUCKETS: usize = BUCKET_BOUNDS_MICROS.len() + 1;
+
+/// A snapshot of a pool's counters, taken by `ThreadPool::metrics`.
+///
+/// The fields are read one at a time while the pool keeps running, so they
+/// may not add up exactly.
+#[derive(Debug, Clone)]
+pub struct PoolMetrics {
+    pub workers: usize,
+    /// Workers running a job.
+    pub active_workers: usize,
+    pub idle_workers: usize,
+    pub queued_jobs: usize,
+    /// Jobs accepted by `execute` and its variants, including ones run on
+    /// the caller's thread.
+    pub jobs_submitted: u64,
+    /// Jobs that have run, whether or not they panicked.
+    pub jobs_completed: u64,
+    pub jobs_panicked: u64,
+    pub jobs_rejected: u64,
+    pub jobs_dropped: u64,
+    pub workers_respawned: u64,
+    pub shutting_down: bool,
+    /// How long jobs waited in the queue before starting.
+    pub queue_wait: Histogram,
+    /// How long jobs took to run.
+    pub run_time: Histogram,
+}
+
+/// A histogram of durations, with buckets from 50µs to 10s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u64; BUCKETS],
+    total: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(self.total / count as u32),
+        }
+    }
+
+    /// An upper bound on the duration below which `quantile` of the
+    /// samples fall, such as 0.99 for the 99th percentile: the top of the
+    /// bucket it lands in, or the largest sample if that is smaller.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((quantile.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        for (bound, bucket) in self.buckets() {
+            seen += bucket;
+            if seen >= rank {
+                return Some(bound.map_or(self.max, |bound| bound.min(self.max)));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// The buckets as (upper bound, count) pairs, from fastest to slowest.
+    /// The last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        BUCKET_BOUNDS_MICROS
+            .iter()
+            .map(|&micros| Some(Duration::from_micros(micros)))
+            .chain(Some(None))
+            .zip(self.counts.iter().copied())
+    }
+}
+
+/// A histogram that threads can record into at the same time.
+#[derive(Default)]
+pub(super) struct AtomicHistogram {
+    counts: [AtomicU64; BUCKETS],
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl AtomicHistogram {
+    pub fn record(&self, duration: Duration) {
+        let micros = duration.as_micros();
+        let bucket = BUCKET_BOUNDS_MICROS
+            .iter()
+            .position(|&bound| micros <= u128::from(bound))
+            .unwrap_or(BUCKETS - 1);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Histogram {
+        let mut counts = [0; BUCKETS];
+        for (count, atomic) in counts.iter_mut().zip(&self.counts) {
+            *count = atomic.load(Ordering::Relaxed);
+        }
+
+        Histogram {
+            counts,
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// The counters behind `PoolMetrics`.
+#[derive(Default)]
+pub(super) struct Metrics {
+    pub submitted: AtomicU64,
+    pub completed: AtomicU64,
+    pub panicked: AtomicU64,
+    pub rejected: AtomicU64,
+    pub dropped: AtomicU64,
+    pub respawned: AtomicU64,
+    pub queue_wait: AtomicHistogram,
+    pub run_time: AtomicHistogram,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_quantiles() {
+        let histogram = AtomicHistogram::default();
+        for millis in 1..=100 {
+            histogram.record(Duration::from_millis(millis));
+        }
+        let snapshot = histogram.snapshot();
+
+        assert_eq!(100, snapshot.count());
+        assert_eq!(Some(Duration::from_micros(50_500)), snapshot.mean());
+        // The 50th sample, 50ms, is the last one in the 25-50ms bucket.
+        assert_eq!(Some(Duration::from_millis(50)), snapshot.quantile(0.5));
+        // The 99th sample is in the 50-100ms bucket.
+        assert_eq!(Some(Duration::from_millis(100)), snapshot.quantile(0.99));
+        assert_eq!(None, AtomicHistogram::default().snapshot().quantile(0.5));
+    }
+
+    #[test]
+    fn slow_samples_land_in_the_last_bucket() {
+        let histogram = AtomicHistogram::default();
+        histogram.record(Duration::from_secs(60));
+        let snapshot = histogram.snapshot();
+
+        assert_eq!(Some((None, 1)), snapshot.buckets().last());
+        assert_eq!(Some(Duration::from_secs(60)), snapshot.quantile(1.0));
+    }
+}
@@ -0,0 +1,1207 @@
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use self::metrics::Metrics;
+use self::queue::{Local, Queue};
+use self::schedule::{Scheduler, Work};
+
+mod config;
+mod error;
+mod job;
+mod metrics;
+mod observer;
+mod queue;
+mod schedule;
+
+pub use self::config::{OverflowPolicy, PoolConfig};
+pub use self::error::{ExecuteError, PoolCreationError};
+pub use self::job::{JobError, JobHandle};
+pub use self::metrics::{Histogram, PoolMetrics};
+pub use self::observer::{PoolEvent, PoolObserver, StopReason};
+pub use self::queue::Priority;
+pub use self::schedule::CancellationToken;
+
+/// A job carries the time it was queued, for the queue wait metrics.
+/// `Terminate` messages go in the low priority lane, behind every job
+/// queued before them.
+enum Message {
+    NewJob(Job, Instant),
+    Terminate,
+}
+
+/// A pool of worker threads that run queued jobs.
+///
+/// Jobs are scheduled by work stealing: see `queue::Queue`. The number of
+/// workers moves between the bounds in the pool's `PoolConfig`.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+}
+
+/// State the pool shares with its worker threads.
+struct Shared {
+    queue: Queue<Message>,
+    workers: Mutex<Vec<Worker>>,
+    sizing: Mutex<Sizing>,
+    keep_alive: Duration,
+    capacity: Option<usize>,
+    overflow: OverflowPolicy,
+    /// Jobs pushed onto the queue and not yet taken off by a worker.
+    queued: AtomicUsize,
+    /// `execute` calls waiting for room under `OverflowPolicy::Block`.
+    blocked: AtomicUsize,
+    space_lock: Mutex<()>,
+    space: Condvar,
+    scheduler: Scheduler,
+    /// Workers waiting for a message.
+    idle: AtomicUsize,
+    /// Only changed with `sizing` locked, so that no worker is started
+    /// after the shutdown `Terminate` messages have been counted out.
+    shut_down: AtomicBool,
+    next_id: AtomicUsize,
+    metrics: Metrics,
+    observer: Option<Arc<dyn PoolObserver>>,
+}
+
+/// The worker count and its bounds.
+struct Sizing {
+    min: usize,
+    max: usize,
+    /// Worker threads that have started and not yet exited.
+    live: usize,
+    /// `Terminate` messages queued but not yet picked up. Each one will
+    /// take a worker out of `live`.
+    retiring: usize,
+}
+
+impl Sizing {
+    /// The number of workers that will be left once the queued `Terminate`
+    /// messages have been picked up.
+    fn staying(&self) -> usize {
+        self.live.saturating_sub(self.retiring)
+    }
+}
+
+impl Shared {
+    fn spawn_worker(self: &Arc<Self>) -> io::Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let worker = Worker::new(id, Arc::clone(self))?;
+
+        let mut workers = lock(&self.workers);
+        // Forget workers that were reaped, so the list doesn't keep growing.
+        workers.retain(|worker| !worker.thread.as_ref().is_some_and(|t| t.is_finished()));
+        workers.push(worker);
+        Ok(())
+    }
+
+    /// Starts another worker if more jobs are waiting than there are idle
+    /// workers to take them and the pool is below its maximum size.
+    fn grow_if_backed_up(self: &Arc<Self>) {
+        if self.queued.load(Ordering::SeqCst) <= self.idle.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut sizing = lock(&self.sizing);
+        if self.shut_down.load(Ordering::SeqCst) || sizing.staying() >= sizing.max {
+            return;
+        }
+
+        match self.spawn_worker() {
+            Ok(()) => sizing.live += 1,
+            Err(error) => self.emit(PoolEvent::SpawnFailed { error }),
+        }
+    }
+
+    /// Makes room on the queue for `job` as `overflow` says to. Returns the
+    /// job if it should be queued, or `None` if it has already been run.
+    fn admit(&self, job: Job, overflow: OverflowPolicy) -> Result<Option<Job>, ExecuteError> {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => {
+                self.queued.fetch_add(1, Ordering::SeqCst);
+                return Ok(Some(job));
+            }
+        };
+
+        loop {
+            let reserved = self.queued.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                if queued < capacity { Some(queued + 1) } else { None }
+            });
+            if reserved.is_ok() {
+                return Ok(Some(job));
+            }
+
+            match overflow {
+                OverflowPolicy::Reject => {
+                    self.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                    self.emit(PoolEvent::JobRejected);
+                    return Err(ExecuteError::QueueFull);
+                }
+                OverflowPolicy::CallerRuns => {
+                    self.run(job, None, Instant::now());
+                    return Ok(None);
+                }
+                OverflowPolicy::DropOldest => {
+                    // The new job takes over the old one's place in the
+                    // count. If the workers took everything in the meantime
+                    // there is nothing to drop, so try again.
+                    if let Some(oldest) = self.take_oldest_job() {
+                        drop(oldest);
+                        self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                        self.emit(PoolEvent::JobDropped);
+                        return Ok(Some(job));
+                    }
+                    thread::yield_now();
+                }
+                OverflowPolicy::Block => {
+                    let guard = lock(&self.space_lock);
+                    self.blocked.fetch_add(1, Ordering::SeqCst);
+                    // Pairs with the fence in `job_taken`.
+                    atomic::fence(Ordering::SeqCst);
+
+                    let full = self.queued.load(Ordering::SeqCst) >= capacity;
+                    if full && !self.shut_down.load(Ordering::SeqCst) {
+                        let _guard = self.space.wait(guard).unwrap_or_else(PoisonError::into_inner);
+                    }
+                    self.blocked.fetch_sub(1, Ordering::SeqCst);
+
+                    if self.shut_down.load(Ordering::SeqCst) {
+                        return Err(ExecuteError::ShutDown);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes the job that has been waiting longest from the queue.
+    fn take_oldest_job(&self) -> Option<Job> {
+        let mut terminates = 0;
+        let mut oldest = None;
+
+        while let Some(message) = self.queue.steal_oldest() {
+            match message {
+                Message::NewJob(job, _) => {
+                    oldest = Some(job);
+                    break;
+                }
+                Message::Terminate => terminates += 1,
+            }
+        }
+        // Workers still need to see these, so put them back.
+        for _ in 0..terminates {
+            self.queue.push_with_priority(Message::Terminate, Priority::Low);
+        }
+
+        oldest
+    }
+
+    /// Called by a worker when it takes a job off the queue.
+    fn job_taken(&self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+
+        atomic::fence(Ordering::SeqCst);
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            let _guard = lock(&self.space_lock);
+            self.space.notify_one();
+        }
+    }
+
+    /// Runs a job, catching a panic and recording how it went. `worker`
+    /// is `None` when the job runs on the caller's thread.
+    fn run(&self, job: Job, worker: Option<usize>, queued_at: Instant) {
+        let started = Instant::now();
+        let waited = started.saturating_duration_since(queued_at);
+        self.metrics.queue_wait.record(waited);
+        self.emit(PoolEvent::JobStarted {
+            worker,
+            waited,
+            queued_jobs: self.queued.load(Ordering::SeqCst),
+        });
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| job.call_box())).is_err();
+
+        let ran_for = started.elapsed();
+        self.metrics.run_time.record(ran_for);
+        self.metrics.completed.fetch_add(1, Ordering::Relaxed);
+        if panicked {
+            self.metrics.panicked.fetch_add(1, Ordering::Relaxed);
+        }
+        self.emit(PoolEvent::JobFinished { worker, ran_for, panicked });
+    }
+
+    fn emit(&self, event: PoolEvent) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(&event);
+        }
+    }
+
+    /// Called by a worker that has been idle for the keep-alive time.
+    /// Returns true if it should exit, which it may do if that leaves the
+    /// pool above its minimum size and there is no work left for it.
+    fn try_reap(&self) -> bool {
+        let mut sizing = lock(&self.sizing);
+
+        // Checked under the lock: a job pushed after this sees the worker
+        // gone and starts another one.
+        if sizing.staying() > sizing.min && self.queue.is_empty() {
+            sizing.live -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Called by a worker that picked up a `Terminate` message.
+    fn retire(&self) {
+        let mut sizing = lock(&self.sizing);
+        sizing.live -= 1;
+        sizing.retiring = sizing.retiring.saturating_sub(1);
+    }
+
+    /// Tells `count` workers to exit once they reach the messages, behind
+    /// any jobs already queued.
+    fn send_terminate(&self, sizing: &mut Sizing, count: usize) {
+        sizing.retiring += count;
+        for _ in 0..count {
+            self.queue.push_with_priority(Message::Terminate, Priority::Low);
+        }
+    }
+
+    fn execute(self: &Arc<Self>, job: Job, priority: Priority) -> Result<(), ExecuteError> {
+        self.queue_job(job, priority, self.overflow)
+    }
+
+    /// Like `execute`, but a full queue turns `job` away rather than
+    /// blocking or running it on the calling thread.
+    fn try_execute(self: &Arc<Self>, job: Job, priority: Priority) -> Result<(), ExecuteError> {
+        let overflow = match self.overflow {
+            OverflowPolicy::Block | OverflowPolicy::CallerRuns => OverflowPolicy::Reject,
+            overflow => overflow,
+        };
+        self.queue_job(job, priority, overflow)
+    }
+
+    fn queue_job(self: &Arc<Self>, job: Job, priority: Priority, overflow: OverflowPolicy) -> Result<(), ExecuteError> {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return Err(ExecuteError::ShutDown);
+        }
+
+        let admitted = self.admit(job, overflow)?;
+        self.metrics.submitted.fetch_add(1, Ordering::Relaxed);
+
+        let job = match admitted {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+
+        self.queue.push_with_priority(Message::NewJob(job, Instant::now()), priority);
+        self.grow_if_backed_up();
+        Ok(())
+    }
+}
+
+/// Locks `mutex` even if a thread panicked while holding it. Nothing the
+/// pool guards with a mutex can be left half-updated by a panic, so the
+/// poison flag carries no information here.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+//struct Job;
+
+trait FnBox {
+    fn call_box(self: Box<Self>);
+}
+
+impl<F: FnOnce()> FnBox for F {
+    fn call_box(self: Box<F>) {
+        (*self)()
+    }
+}
+
+type Job = Box<dyn FnBox + Send + 'static>;
+
+impl ThreadPool {
+    /// Create a new ThreadPool.
+    ///
+    /// The size is the number of threads in the pool.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the size is zero or a worker thread
+    /// can't be spawned. Use `build` to handle those cases instead.
+    pub fn new(size: usize) -> ThreadPool {
+        match ThreadPool::build(size) {
+            Ok(pool) => pool,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Create a new ThreadPool, returning an error instead of panicking if
+    /// the size is zero or a worker thread can't be spawned.
+    pub fn build(size: usize) -> Result<ThreadPool, PoolCreationError> {
+        ThreadPool::with_config(PoolConfig::fixed(size))
+    }
+
+    /// Create a ThreadPool whose size changes with the load, within the
+    /// bounds in `config`.
+    pub fn with_config(config: PoolConfig) -> Result<ThreadPool, PoolCreationError> {
+        config.validate()?;
+
+        let shared = Arc::new(Shared {
+            queue: Queue::new(),
+            workers: Mutex::new(Vec::with_capacity(config.max_workers)),
+            sizing: Mutex::new(Sizing {
+                min: config.min_workers,
+                max: config.max_workers,
+                live: 0,
+                retiring: 0,
+            }),
+            keep_alive: config.keep_alive,
+            capacity: config.queue_capacity,
+            overflow: config.overflow,
+            queued: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            space_lock: Mutex::new(()),
+            space: Condvar::new(),
+            scheduler: Scheduler::default(),
+            idle: AtomicUsize::new(0),
+            shut_down: AtomicBool::new(false),
+            next_id: AtomicUsize::new(0),
+            metrics: Metrics::default(),
+            observer: config.observer,
+        });
+
+        // If a spawn fails part way through, dropping `pool` on the way out
+        // stops the workers that did start.
+        let pool = ThreadPool { shared };
+
+        pool.start_workers(config.min_workers)
+            .map_err(PoolCreationError::Spawn)?;
+
+        Ok(pool)
+    }
+
+    fn start_workers(&self, count: usize) -> io::Result<()> {
+        let mut sizing = lock(&self.shared.sizing);
+
+        for _ in 0..count {
+            self.shared.spawn_worker()?;
+            sizing.live += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Queues `f` to run on one of the pool's threads.
+    ///
+    /// A panic in `f` is caught and counted, and the worker goes on to the
+    /// next job. Returns an error if the pool has been shut down. If the
+    /// queue is full, what happens depends on the pool's `OverflowPolicy`;
+    /// a job that is rejected or dropped is dropped without being run.
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError>
+        where
+            F: FnOnce() + Send + 'static
+    {
+        self.shared.execute(Box::new(f), Priority::Normal)
+    }
+
+    /// Like `execute`, but queues `f` in the given priority lane. Workers
+    /// take high priority jobs first, and low priority ones only when there
+    /// is nothing else to do.
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Result<(), ExecuteError>
+        where
+            F: FnOnce() + Send + 'static
+    {
+        self.shared.execute(Box::new(f), priority)
+    }
+
+    /// Queues `f` to run once `delay` has passed, and returns a token that
+    /// can cancel it until then.
+    ///
+    /// Jobs that aren't due yet when the pool shuts down never run. When
+    /// they are due, they are queued like any other job, so they may start
+    /// late if the workers are busy, and the overflow policy applies to them.
+    pub fn schedule_after<F>(&self, delay: Duration, f: F) -> Result<CancellationToken, ExecuteError>
+        where
+            F: FnOnce() + Send + 'static
+    {
+        let token = CancellationToken::default();
+        let due = Instant::now() + delay;
+
+        self.shared.scheduler.add(&self.shared, due, Work::Once(Box::new(f)), token.clone())?;
+        Ok(token)
+    }
+
+    /// Runs `f` after `initial_delay` and then every `period` until the
+    /// returned token is cancelled or the pool shuts down.
+    ///
+    /// Runs are timed from when the first one was due rather than from when
+    /// the previous one finished, but never overlap: if a run takes longer
+    /// than `period`, the next one starts as soon as it is over. A run
+    /// turned away by a full queue, or dropped from it to make room, is
+    /// skipped. A full queue never blocks the timer or runs a job on it,
+    /// whatever the overflow policy.
+    ///
+    /// Returns `ExecuteError::ZeroPeriod` if `period` is zero.
+    pub fn schedule_at_fixed_rate<F>(
+        &self,
+        initial_delay: Duration,
+        period: Duration,
+        f: F,
+    ) -> Result<CancellationToken, ExecuteError>
+        where
+            F: Fn() + Send + Sync + 'static
+    {
+        if period == Duration::ZERO {
+            return Err(ExecuteError::ZeroPeriod);
+        }
+
+        let token = CancellationToken::default();
+        let due = Instant::now() + initial_delay;
+        let work = Work::Repeat { run: Arc::new(f), period };
+
+        self.shared.scheduler.add(&self.shared, due, work, token.clone())?;
+        Ok(token)
+    }
+
+    /// Queues `f` to run on one of the pool's threads and returns a handle
+    /// for collecting its return value.
+    ///
+    /// A panic in `f` is handed back through the handle as
+    /// `JobError::Panicked`; it isn't counted by `panic_count`.
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, ExecuteError>
+        where
+            F: FnOnce() -> T + Send + 'static,
+            T: Send + 'static
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+
+        self.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+            // The caller may have dropped the handle; that's fine.
+            let _ = sender.send(result);
+        })?;
+
+        Ok(JobHandle::new(receiver))
+    }
+
+    /// The number of worker threads running right now.
+    pub fn size(&self) -> usize {
+        lock(&self.shared.sizing).live
+    }
+
+    /// The number of workers waiting for a job.
+    pub fn idle_workers(&self) -> usize {
+        self.shared.idle.load(Ordering::SeqCst)
+    }
+
+    /// Resizes the pool to exactly `size` workers. This is shorthand for
+    /// `set_bounds(size, size)`.
+    pub fn set_size(&self, size: usize) -> Result<(), PoolCreationError> {
+        self.set_bounds(size, size)
+    }
+
+    /// Changes the pool's minimum and maximum size.
+    ///
+    /// Workers are started right away to reach a higher minimum. If the
+    /// pool is above the new maximum, workers are told to exit once they
+    /// have run the jobs queued ahead of the message; idle workers between
+    /// the two bounds exit after the keep-alive time as usual. Has no effect
+    /// once the pool has been shut down.
+    pub fn set_bounds(&self, min_workers: usize, max_workers: usize) -> Result<(), PoolCreationError> {
+        config::check_bounds(min_workers, max_workers)?;
+
+        let mut sizing = lock(&self.shared.sizing);
+        if self.shared.shut_down.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        sizing.min = min_workers;
+        sizing.max = max_workers;
+
+        let staying = sizing.staying();
+        if staying > max_workers {
+            self.shared.send_terminate(&mut sizing, staying - max_workers);
+        }
+        // Cancelling queued `Terminate` messages isn't possible, so a pool
+        // that was shrinking grows back by starting new workers.
+        for _ in staying..min_workers {
+            self.shared.spawn_worker().map_err(PoolCreationError::Spawn)?;
+            sizing.live += 1;
+        }
+
+        Ok(())
+    }
+
+    /// The number of jobs waiting for a worker to pick them up.
+    pub fn queued_jobs(&self) -> usize {
+        self.shared.queued.load(Ordering::SeqCst)
+    }
+
+    /// How many jobs passed to `execute` have panicked.
+    pub fn panic_count(&self) -> usize {
+        self.shared.metrics.panicked.load(Ordering::Relaxed) as usize
+    }
+
+    /// How many worker threads have died and been replaced.
+    pub fn respawn_count(&self) -> usize {
+        self.shared.metrics.respawned.load(Ordering::Relaxed) as usize
+    }
+
+    /// Takes a snapshot of the pool's counters and latency histograms.
+    pub fn metrics(&self) -> PoolMetrics {
+        let shared = &self.shared;
+        let metrics = &shared.metrics;
+
+        let workers = self.size();
+        let idle_workers = shared.idle.load(Ordering::SeqCst).min(workers);
+
+        PoolMetrics {
+            workers,
+            active_workers: workers - idle_workers,
+            idle_workers,
+            queued_jobs: self.queued_jobs(),
+            jobs_submitted: metrics.submitted.load(Ordering::Relaxed),
+            jobs_completed: metrics.completed.load(Ordering::Relaxed),
+            jobs_panicked: metrics.panicked.load(Ordering::Relaxed),
+            jobs_rejected: metrics.rejected.load(Ordering::Relaxed),
+            jobs_dropped: metrics.dropped.load(Ordering::Relaxed),
+            workers_respawned: metrics.respawned.load(Ordering::Relaxed),
+            shutting_down: shared.shut_down.load(Ordering::SeqCst),
+            queue_wait: metrics.queue_wait.snapshot(),
+            run_time: metrics.run_time.snapshot(),
+        }
+    }
+
+    /// Stops the pool from taking new jobs and tells the workers to exit
+    /// once the jobs already queued have run. Scheduled jobs that aren't due
+    /// yet are dropped. The workers are joined when the pool is dropped.
+    pub fn shutdown(&self) {
+        let mut sizing = lock(&self.shared.sizing);
+        if self.shared.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        self.shared.emit(PoolEvent::ShutdownStarted {
+            workers: sizing.live,
+            queued_jobs: self.queued_jobs(),
+        });
+
+        let staying = sizing.staying();
+        self.shared.send_terminate(&mut sizing, staying);
+
+        drop(sizing);
+
+        // Wake anyone blocked on a full queue so they can see the flag.
+        {
+            let _guard = lock(&self.shared.space_lock);
+            self.shared.space.notify_all();
+        }
+
+        self.shared.scheduler.stop();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+        self.shared.scheduler.join();
+
+        // A worker that dies while we wait adds its replacement to the
+        // list, so keep going until the list stays empty.
+        loop {
+            let workers = mem::take(&mut *lock(&self.shared.workers));
+            if workers.is_empty() {
+                break;
+            }
+
+            for mut worker in workers {
+                if let Some(thread) = worker.thread.take() {
+                    // A worker that panicked has already reported it.
+                    let _ = thread.join();
+                }
+            }
+        }
+
+        self.shared.emit(PoolEvent::ShutdownFinished);
+    }
+}
+
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, shared: Arc<Shared>) -> io::Result<Worker> {
+        let builder = thread::Builder::new().name(format!("worker-{}", id));
+
+        let thread = builder.spawn(move ||{
+            shared.emit(PoolEvent::WorkerStarted { worker: id });
+
+            let local = shared.queue.register(id);
+            let mut sentinel = Sentinel { id, shared, local, active: true };
+
+            loop {
+                let shared = &sentinel.shared;
+
+                shared.idle.fetch_add(1, Ordering::SeqCst);
+                let message = shared.queue.pop(&sentinel.local, shared.keep_alive);
+                shared.idle.fetch_sub(1, Ordering::SeqCst);
+
+                match message {
+                    Some(Message::NewJob(job, queued_at)) => {
+                        shared.job_taken();
+                        shared.run(job, Some(id), queued_at);
+                    },
+                    Some(Message::Terminate) => {
+                        shared.retire();
+                        shared.emit(PoolEvent::WorkerStopped { worker: id, reason: StopReason::Terminated });
+                        break;
+                    },
+                    None => {
+                        if shared.try_reap() {
+                            shared.emit(PoolEvent::WorkerStopped { worker: id, reason: StopReason::Idle });
+                            break;
+                        }
+                    },
+                }
+            }
+
+            sentinel.active = false;
+        })?;
+
+        Ok(Worker {
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Lives on a worker thread's stack and owns its local deque. When the
+/// thread exits, the jobs still in the deque go back to the shared queue,
+/// and if the thread is unwinding, a replacement worker is started. Jobs run
+/// under `catch_unwind`, so that only happens for panics that escape it,
+/// such as one raised while dropping a panic payload.
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>,
+    local: Local<Message>,
+    active: bool,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        self.shared.queue.unregister(&self.local);
+
+        if self.active && thread::panicking() {
+            self.shared.metrics.respawned.fetch_add(1, Ordering::Relaxed);
+            self.shared.emit(PoolEvent::WorkerStopped { worker: self.id, reason: StopReason::Died });
+
+            if let Err(error) = self.shared.spawn_worker() {
+                lock(&self.shared.sizing).live -= 1;
+                self.shared.emit(PoolEvent::SpawnFailed { error });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Barrier};
+
+    /// Polls `condition` until it holds, failing the test after a while.
+    fn wait_for<F: Fn() -> bool>(condition: F) {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("condition not met in time");
+    }
+
+    /// Queues `count` jobs that each hold on to a worker until `gate` is
+    /// waited on by the caller as well.
+    fn occupy_workers(pool: &ThreadPool, count: usize) -> Arc<Barrier> {
+        let gate = Arc::new(Barrier::new(count + 1));
+        let (started, receiver) = mpsc::channel();
+
+        for _ in 0..count {
+            let gate = Arc::clone(&gate);
+            let started = started.clone();
+            pool.execute(move || {
+                started.send(()).unwrap();
+                gate.wait();
+            }).unwrap();
+        }
+        for _ in 0..count {
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+
+        gate
+    }
+
+    #[test]
+    fn build_rejects_zero_threads() {
+        assert!(matches!(ThreadPool::build(0), Err(PoolCreationError::ZeroSize)));
+    }
+
+    #[test]
+    fn with_config_rejects_a_zero_capacity_queue() {
+        let config = PoolConfig {
+            queue_capacity: Some(0),
+            ..PoolConfig::default()
+        };
+
+        assert!(matches!(ThreadPool::with_config(config), Err(PoolCreationError::ZeroCapacity)));
+    }
+
+    #[test]
+    fn with_config_rejects_bad_bounds() {
+        let config = PoolConfig {
+            min_workers: 3,
+            max_workers: 2,
+            ..PoolConfig::default()
+        };
+
+        assert!(matches!(
+            ThreadPool::with_config(config),
+            Err(PoolCreationError::InvalidBounds { min: 3, max: 2 })
+        ));
+    }
+
+    #[test]
+    fn grows_when_jobs_back_up() {
+        let pool = ThreadPool::with_config(PoolConfig {
+            min_workers: 1,
+            max_workers: 4,
+            ..PoolConfig::default()
+        }).unwrap();
+        assert_eq!(1, pool.size());
+
+        // Only returns if four jobs are running at once.
+        let gate = occupy_workers(&pool, 4);
+
+        assert_eq!(4, pool.size());
+        gate.wait();
+    }
+
+    #[test]
+    fn idle_workers_above_the_minimum_are_reaped() {
+        let pool = ThreadPool::with_config(PoolConfig {
+            min_workers: 0,
+            max_workers: 3,
+            keep_alive: Duration::from_millis(50),
+            ..PoolConfig::default()
+        }).unwrap();
+        assert_eq!(0, pool.size());
+
+        occupy_workers(&pool, 3).wait();
+        wait_for(|| pool.size() == 0);
+
+        // The pool starts again from nothing.
+        assert_eq!(5, pool.submit(|| 5).unwrap().join().unwrap());
+    }
+
+    #[test]
+    fn set_size_grows_and_shrinks_the_pool() {
+        let pool = ThreadPool::build(2).unwrap();
+
+        pool.set_size(4).unwrap();
+        assert_eq!(4, pool.size());
+        occupy_workers(&pool, 4).wait();
+
+        pool.set_size(1).unwrap();
+        wait_for(|| pool.size() == 1);
+        assert_eq!("still running", pool.submit(|| "still running").unwrap().join().unwrap());
+
+        assert!(matches!(pool.set_size(0), Err(PoolCreationError::ZeroSize)));
+    }
+
+    fn bounded(capacity: usize, overflow: OverflowPolicy) -> ThreadPool {
+        ThreadPool::with_config(PoolConfig {
+            queue_capacity: Some(capacity),
+            overflow,
+            ..PoolConfig::fixed(1)
+        }).unwrap()
+    }
+
+    #[test]
+    fn full_queue_rejects_jobs() {
+        let pool = bounded(2, OverflowPolicy::Reject);
+        let gate = occupy_workers(&pool, 1);
+
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| {}).unwrap();
+        assert!(matches!(pool.execute(|| {}), Err(ExecuteError::QueueFull)));
+        assert_eq!(2, pool.queued_jobs());
+
+        gate.wait();
+    }
+
+    #[test]
+    fn full_queue_runs_jobs_on_the_caller() {
+        let pool = bounded(1, OverflowPolicy::CallerRuns);
+        let gate = occupy_workers(&pool, 1);
+
+        let queued = pool.submit(|| thread::current().id()).unwrap();
+        let mut inline = pool.submit(|| thread::current().id()).unwrap();
+
+        assert_eq!(thread::current().id(), inline.try_join().unwrap().unwrap());
+        gate.wait();
+        assert_ne!(thread::current().id(), queued.join().unwrap());
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest_job() {
+        let pool = bounded(2, OverflowPolicy::DropOldest);
+        let gate = occupy_workers(&pool, 1);
+
+        let handles: Vec<JobHandle<i32>> = (1..=3)
+            .map(|n| pool.submit(move || n).unwrap())
+            .collect();
+        gate.wait();
+
+        let results: Vec<Option<i32>> = handles.into_iter().map(|h| h.join().ok()).collect();
+        assert_eq!(vec![None, Some(2), Some(3)], results);
+    }
+
+    #[test]
+    fn full_queue_blocks_until_there_is_room() {
+        let pool = Arc::new(bounded(1, OverflowPolicy::Block));
+        let gate = occupy_workers(&pool, 1);
+        pool.execute(|| {}).unwrap();
+
+        let (done, finished) = mpsc::channel();
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                pool.execute(|| {}).unwrap();
+                done.send(()).unwrap();
+            })
+        };
+
+        assert!(finished.recv_timeout(Duration::from_millis(100)).is_err());
+        gate.wait();
+        assert!(finished.recv_timeout(Duration::from_secs(5)).is_ok());
+        submitter.join().unwrap();
+    }
+
+    #[test]
+    fn blocked_callers_fail_at_shutdown() {
+        let pool = Arc::new(bounded(1, OverflowPolicy::Block));
+        let gate = occupy_workers(&pool, 1);
+        pool.execute(|| {}).unwrap();
+
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || pool.execute(|| {}))
+        };
+        thread::sleep(Duration::from_millis(50));
+        pool.shutdown();
+
+        assert!(matches!(submitter.join().unwrap(), Err(ExecuteError::ShutDown)));
+        gate.wait();
+    }
+
+    #[test]
+    fn higher_priority_jobs_run_first() {
+        let pool = ThreadPool::build(1).unwrap();
+        let gate = occupy_workers(&pool, 1);
+        let (sender, receiver) = mpsc::channel();
+
+        for (priority, name) in [(Priority::Low, "low"), (Priority::Normal, "normal"), (Priority::High, "high")] {
+            let sender = sender.clone();
+            pool.execute_with_priority(priority, move || sender.send(name).unwrap()).unwrap();
+        }
+        gate.wait();
+
+        let order: Vec<&str> = receiver.iter().take(3).collect();
+        assert_eq!(vec!["high", "normal", "low"], order);
+    }
+
+    #[test]
+    fn scheduled_jobs_wait_for_their_delay() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        let started = Instant::now();
+        pool.schedule_after(Duration::from_millis(50), move || sender.send(()).unwrap()).unwrap();
+
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn cancelled_jobs_dont_run() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        let token = pool.schedule_after(Duration::from_millis(50), move || sender.send(()).unwrap()).unwrap();
+        token.cancel();
+
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn fixed_rate_jobs_repeat_until_cancelled() {
+        let pool = ThreadPool::build(2).unwrap();
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&runs);
+        let token = pool.schedule_at_fixed_rate(Duration::ZERO, Duration::from_millis(10), move || {
+            if counter.fetch_add(1, Ordering::SeqCst) == 1 {
+                panic!("one bad run doesn't end the schedule");
+            }
+        }).unwrap();
+
+        wait_for(|| runs.load(Ordering::SeqCst) >= 4);
+        token.cancel();
+        // A run already under way may still finish.
+        thread::sleep(Duration::from_millis(50));
+        let after_cancel = runs.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(after_cancel, runs.load(Ordering::SeqCst));
+        assert_eq!(1, pool.panic_count());
+    }
+
+    #[test]
+    fn fixed_rate_jobs_survive_being_dropped_from_the_queue() {
+        let pool = bounded(1, OverflowPolicy::DropOldest);
+        let gate = occupy_workers(&pool, 1);
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&runs);
+        pool.schedule_at_fixed_rate(Duration::ZERO, Duration::from_millis(20), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }).unwrap();
+        wait_for(|| pool.queued_jobs() == 1);
+        pool.execute(|| {}).unwrap();
+        gate.wait();
+
+        wait_for(|| runs.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    fn a_full_queue_never_blocks_the_timer() {
+        let pool = bounded(1, OverflowPolicy::Block);
+        let gate = occupy_workers(&pool, 1);
+        pool.execute(|| {}).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        let skipped = sender.clone();
+        pool.schedule_after(Duration::ZERO, move || skipped.send("skipped").unwrap()).unwrap();
+        pool.schedule_after(Duration::from_millis(100), move || sender.send("later").unwrap()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        gate.wait();
+
+        assert_eq!("later", receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn fixed_rate_jobs_need_a_period() {
+        let pool = ThreadPool::build(1).unwrap();
+
+        assert!(matches!(
+            pool.schedule_at_fixed_rate(Duration::ZERO, Duration::ZERO, || {}),
+            Err(ExecuteError::ZeroPeriod)
+        ));
+    }
+
+    #[test]
+    fn jobs_not_yet_due_are_dropped_at_shutdown() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (sender, receiver) = mpsc::channel::<()>();
+
+        pool.schedule_after(Duration::from_secs(60), move || sender.send(()).unwrap()).unwrap();
+
+        let started = Instant::now();
+        drop(pool);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // The job was dropped along with its sender.
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn observer_sees_jobs_and_shutdown() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        let pool = ThreadPool::with_config(PoolConfig {
+            observer: Some(Arc::new(move |event: &PoolEvent| {
+                let name = match event {
+                    PoolEvent::WorkerStarted { .. } => "worker started",
+                    PoolEvent::JobStarted { .. } => "job started",
+                    PoolEvent::JobFinished { panicked: false, .. } => "job finished",
+                    PoolEvent::JobFinished { panicked: true, .. } => "job panicked",
+                    PoolEvent::ShutdownStarted { .. } => "shutdown started",
+                    PoolEvent::WorkerStopped { reason: StopReason::Terminated, .. } => "worker terminated",
+                    PoolEvent::ShutdownFinished => "shutdown finished",
+                    other => panic!("unexpected event {:?}", other),
+                };
+                lock(&seen).push(name);
+            })),
+            ..PoolConfig::fixed(1)
+        }).unwrap();
+
+        pool.submit(|| {}).unwrap().join().unwrap();
+        pool.execute(|| panic!("job failed")).unwrap();
+        wait_for(|| lock(&events).len() == 5);
+        drop(pool);
+
+        assert_eq!(
+            vec![
+                "worker started",
+                "job started",
+                "job finished",
+                "job started",
+                "job panicked",
+                "shutdown started",
+                "worker terminated",
+                "shutdown finished",
+            ],
+            *lock(&events)
+        );
+    }
+
+    #[test]
+    fn metrics_count_jobs_and_their_timings() {
+        let pool = bounded(1, OverflowPolicy::Reject);
+        let gate = occupy_workers(&pool, 1);
+
+        pool.execute(|| panic!("job failed")).unwrap();
+        assert!(pool.execute(|| {}).is_err());
+
+        let busy = pool.metrics();
+        assert_eq!((1, 1, 0), (busy.workers, busy.active_workers, busy.idle_workers));
+        assert_eq!(1, busy.queued_jobs);
+
+        gate.wait();
+        wait_for(|| pool.metrics().jobs_completed == 2);
+        let done = pool.metrics();
+
+        assert_eq!(2, done.jobs_submitted);
+        assert_eq!(1, done.jobs_panicked);
+        assert_eq!(1, done.jobs_rejected);
+        assert_eq!(2, done.queue_wait.count());
+        assert_eq!(2, done.run_time.count());
+        assert!(!done.shutting_down);
+    }
+
+    #[test]
+    fn runs_jobs() {
+        let pool = ThreadPool::build(2).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        for i in 0..4 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(i).unwrap()).unwrap();
+        }
+
+        let mut results: Vec<i32> = receiver.iter().take(4).collect();
+        results.sort();
+        assert_eq!(vec![0, 1, 2, 3], results);
+    }
+
+    #[test]
+    fn execute_fails_after_shutdown() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(move || sender.send(()).unwrap()).unwrap();
+        pool.shutdown();
+
+        assert!(matches!(pool.execute(|| {}), Err(ExecuteError::ShutDown)));
+        // Jobs queued before the shutdown still run.
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn submit_returns_the_result() {
+        let pool = ThreadPool::build(2).unwrap();
+
+        let handles: Vec<JobHandle<u64>> = (1..=10)
+            .map(|n| pool.submit(move || n * n).unwrap())
+            .collect();
+        let sum: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+
+        assert_eq!(385, sum);
+    }
+
+    #[test]
+    fn submit_reports_panics() {
+        let pool = ThreadPool::build(1).unwrap();
+
+        let handle = pool.submit(|| -> u32 { panic!("boom") }).unwrap();
+        let error = handle.join().unwrap_err();
+
+        assert_eq!(Some("boom"), error.panic_message());
+        // The worker survived and can run the next job.
+        assert_eq!(7, pool.submit(|| 7).unwrap().join().unwrap());
+    }
+
+    #[test]
+    fn try_join_and_timeouts() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (release, wait) = mpsc::channel::<()>();
+
+        let mut handle = pool.submit(move || {
+            wait.recv().unwrap();
+            "finished"
+        }).unwrap();
+
+        assert!(handle.try_join().is_none());
+        assert!(matches!(handle.join_timeout(Duration::from_millis(20)), Err(JobError::TimedOut)));
+
+        release.send(()).unwrap();
+        assert_eq!("finished", handle.join_timeout(Duration::from_secs(5)).unwrap());
+        assert!(matches!(handle.try_join(), Some(Err(JobError::Cancelled))));
+    }
+
+    #[test]
+    fn panicking_jobs_dont_shrink_the_pool() {
+        let pool = ThreadPool::build(2).unwrap();
+
+        for _ in 0..4 {
+            pool.execute(|| panic!("job failed")).unwrap();
+        }
+        let handles: Vec<JobHandle<thread::ThreadId>> = (0..8)
+            .map(|_| pool.submit(|| thread::current().id()).unwrap())
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(4, pool.panic_count());
+        assert_eq!(0, pool.respawn_count());
+    }
+
+    #[test]
+    fn dead_workers_are_replaced() {
+        /// Panics when dropped, so dropping it while unwinding kills the
+        /// worker thread outright.
+        struct Bomb;
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                panic!("payload exploded");
+            }
+        }
+
+        let pool = ThreadPool::build(1).unwrap();
+        pool.execute(|| panic::panic_any(Bomb)).unwrap();
+
+        let handle = pool.submit(|| "still serving").unwrap();
+
+        assert_eq!("still serving", handle.join().unwrap());
+        assert_eq!(1, pool.respawn_count());
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use super::ExecuteError;
+
+/// Why a worker thread exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// It picked up a `Terminate` message, because the pool was shutting
+    /// down or had been made smaller.
+    Terminated,
+    /// It was above the pool's minimum size and sat idle for longer than
+    /// the keep-alive time.
+    Idle,
+    /// It panicked outside a job. A replacement is started.
+    Died,
+}
+
+/// Something that happened in a `ThreadPool`, as reported to its
+/// `PoolObserver`.
+#[derive(Debug)]
+pub enum PoolEvent {
+    WorkerStarted { worker: usize },
+    WorkerStopped { worker: usize, reason: StopReason },
+    /// A job is about to run. `worker` is `None` for a job run on the
+    /// caller's thread under `OverflowPolicy::CallerRuns`; `queued_jobs` is
+    /// how many jobs are still waiting.
+    JobStarted { worker: Option<usize>, waited: Duration, queued_jobs: usize },
+    JobFinished { worker: Option<usize>, ran_for: Duration, panicked: bool },
+    /// A job was turned away because the queue was full.
+    JobRejected,
+    /// A queued job was thrown away to make room under
+    /// `OverflowPolicy::DropOldest`.
+    JobDropped,
+    /// A scheduled job came due but couldn't be queued.
+    ScheduledJobSkipped { error: ExecuteError },
+    /// A worker or timer thread couldn't be started.
+    SpawnFailed { error: io::Error },
+    ShutdownStarted { workers: usize, queued_jobs: usize },
+    /// Every worker has exited and been joined.
+    ShutdownFinished,
+}
+
+impl fmt::Display for PoolEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolEvent::WorkerStarted { worker } => write!(f, "worker {} started", worker),
+            PoolEvent::WorkerStopped { worker, reason } => {
+                let why = match reason {
+                    StopReason::Terminated => "was told to terminate",
+                    StopReason::Idle => "was idle for too long",
+                    StopReason::Died => "died",
+                };
+                write!(f, "worker {} {}", worker, why)
+            }
+            PoolEvent::JobStarted { worker: Some(worker), waited, .. } => {
+                write!(f, "worker {} started a job after it waited {:?}", worker, waited)
+            }
+            PoolEvent::JobStarted { worker: None, .. } => f.write_str("queue full; running a job on the caller"),
+            PoolEvent::JobFinished { ran_for, panicked, .. } => {
+                let how = if *panicked { "panicked" } else { "finished" };
+                write!(f, "job {} after {:?}", how, ran_for)
+            }
+            PoolEvent::JobRejected => f.write_str("queue full; job rejected"),
+            PoolEvent::JobDropped => f.write_str("queue full; oldest job dropped"),
+            PoolEvent::ScheduledJobSkipped { error } => write!(f, "skipped a scheduled job: {}", error),
+            PoolEvent::SpawnFailed { error } => write!(f, "failed to start a thread: {}", error),
+            PoolEvent::ShutdownStarted { workers, queued_jobs } => write!(
+                f,
+                "shutting down {} workers with {} jobs still queued",
+                workers, queued_jobs
+            ),
+            PoolEvent::ShutdownFinished => f.write_str("all workers have shut down"),
+        }
+    }
+}
+
+/// Receives a pool's events.
+///
+/// It is called on whichever thread the event happened on, often a worker
+/// between jobs, so it should be quick and must not panic.
+pub trait PoolObserver: Send + Sync + 'static {
+    fn on_event(&self, event: &PoolEvent);
+}
+
+impl<F> PoolObserver for F
+    where
+        F: Fn(&PoolEvent) + Send + Sync + 'static
+{
+    fn on_event(&self, event: &PoolEvent) {
+        self(event)
+    }
+}
@@ -0,0 +1,373 @@
+use std::cell::RefCell;
+use std::hint;
+use std::iter;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as Deque};
+
+use super::lock;
+
+/// How many times `pop` looks for work before going to sleep.
+const SPIN_STEPS: u32 = 10;
+
+type Stealers<T> = Arc<Vec<(usize, Stealer<T>)>>;
+
+/// Which lane of a pool's queue a job goes into. Workers take jobs from
+/// the high lane before the normal one, and from the low lane only when the
+/// other two are empty; within a lane, jobs start in the order they were
+/// queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+/// A work-stealing queue shared by a pool's workers.
+///
+/// Items pushed from outside the pool go into a global injector. Each
+/// worker takes batches from there into its own deque and pops from that
+/// without touching shared state; a worker that runs dry steals from the
+/// injector or from the other workers' deques. None of this takes a lock.
+/// The mutex is only used to park workers when there is nothing to do.
+///
+/// High and low priority items skip the batching and go into injectors of
+/// their own, so that each one is taken in its turn.
+pub(super) struct Queue<T> {
+    injector: Injector<T>,
+    high: Injector<T>,
+    low: Injector<T>,
+    stealers: RwLock<Stealers<T>>,
+    /// Bumped whenever `stealers` changes, so that workers know to refresh
+    /// their copy of it.
+    generation: AtomicUsize,
+    sleep: Mutex<()>,
+    wakeup: Condvar,
+    sleeping: AtomicUsize,
+}
+
+/// A worker's end of the queue: its own deque, plus its copy of the list
+/// of deques it can steal from.
+pub(super) struct Local<T> {
+    id: usize,
+    deque: Deque<T>,
+    stealers: RefCell<(usize, Stealers<T>)>,
+}
+
+impl<T> Queue<T> {
+    pub fn new() -> Queue<T> {
+        Queue {
+            injector: Injector::new(),
+            high: Injector::new(),
+            low: Injector::new(),
+            stealers: RwLock::new(Arc::new(Vec::new())),
+            generation: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wakeup: Condvar::new(),
+            sleeping: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        self.push_with_priority(item, Priority::Normal);
+    }
+
+    pub fn push_with_priority(&self, item: T, priority: Priority) {
+        match priority {
+            Priority::High => self.high.push(item),
+            Priority::Normal => self.injector.push(item),
+            Priority::Low => self.low.push(item),
+        }
+
+        // Pairs with the fence in `pop`: either the sleeper sees the new
+        // item, or we see the sleeper and wake it.
+        atomic::fence(Ordering::SeqCst);
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let _guard = lock(&self.sleep);
+            self.wakeup.notify_one();
+        }
+    }
+
+    /// Creates the local deque for worker `id` and makes it visible to
+    /// the other workers for stealing.
+    pub fn register(&self, id: usize) -> Local<T> {
+        let deque = Deque::new_fifo();
+        let stealer = deque.stealer();
+
+        let snapshot = self.update_stealers(|stealers| stealers.push((id, stealer)));
+
+        Local {
+            id,
+            deque,
+            stealers: RefCell::new(snapshot),
+        }
+    }
+
+    /// Removes a worker's deque, handing anything left in it back to the
+    /// injector for the other workers.
+    pub fn unregister(&self, local: &Local<T>) {
+        self.update_stealers(|stealers| stealers.retain(|(worker, _)| *worker != local.id));
+
+        while let Some(item) = local.deque.pop() {
+            self.push(item);
+        }
+    }
+
+    fn update_stealers<F>(&self, change: F) -> (usize, Stealers<T>)
+        where
+            F: FnOnce(&mut Vec<(usize, Stealer<T>)>)
+    {
+        let mut stealers = self.stealers.write().unwrap_or_else(PoisonError::into_inner);
+
+        let mut updated: Vec<_> = stealers.iter().cloned().collect();
+        change(&mut updated);
+        *stealers = Arc::new(updated);
+
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        (generation, Arc::clone(&stealers))
+    }
+
+    /// Takes the next item for the worker owning `local`, waiting up to
+    /// `timeout` for one to arrive.
+    pub fn pop(&self, local: &Local<T>, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            // Jobs tend to arrive in bursts, so spin and then yield for a
+            // little while before paying for a sleep and a wakeup.
+            for step in 0..SPIN_STEPS {
+                if let Some(item) = self.try_pop(local) {
+                    return Some(item);
+                }
+                if step < 6 {
+                    for _ in 0..1 << step {
+                        hint::spin_loop();
+                    }
+                } else {
+                    thread::yield_now();
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+
+            let guard = lock(&self.sleep);
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+            atomic::fence(Ordering::SeqCst);
+
+            if self.is_empty() {
+                let _guard = self.wakeup
+                    .wait_timeout(guard, deadline - now)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    pub fn try_pop(&self, local: &Local<T>) -> Option<T> {
+        steal_one(&self.high)
+            .or_else(|| local.deque.pop())
+            .or_else(|| {
+                iter::repeat_with(|| {
+                    self.injector
+                        .steal_batch_and_pop(&local.deque)
+                        .or_else(|| self.steal_from_others(local))
+                })
+                .find(|steal| !steal.is_retry())
+                .and_then(|steal| steal.success())
+            })
+            .or_else(|| steal_one(&self.low))
+    }
+
+    /// Tries each of the other workers' deques once, starting with the one
+    /// after ours so that the workers don't all pile onto the same victim.
+    fn steal_from_others(&self, local: &Local<T>) -> Steal<T> {
+        let mut snapshot = local.stealers.borrow_mut();
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        if snapshot.0 != generation {
+            let stealers = self.stealers.read().unwrap_or_else(PoisonError::into_inner);
+            *snapshot = (self.generation.load(Ordering::SeqCst), Arc::clone(&stealers));
+        }
+
+        let stealers = &snapshot.1;
+        let start = stealers.iter().position(|(id, _)| *id == local.id).unwrap_or(0);
+
+        stealers
+            .iter()
+            .cycle()
+            .skip(start + 1)
+            .take(stealers.len())
+            .filter(|(id, _)| *id != local.id)
+            .map(|(_, stealer)| stealer.steal())
+            .collect()
+    }
+
+    /// Takes the item that has been waiting longest in the lowest priority
+    /// lane that has any, as far as that can be told: for the normal lane,
+    /// the front of the injector, or failing that, the front of one of the
+    /// workers' deques.
+    pub fn steal_oldest(&self) -> Option<T> {
+        steal_one(&self.low)
+            .or_else(|| steal_one(&self.injector))
+            .or_else(|| {
+                let stealers = self.stealers.read().unwrap_or_else(PoisonError::into_inner);
+                stealers.iter().find_map(|(_, stealer)| {
+                    iter::repeat_with(|| stealer.steal())
+                        .find(|steal| !steal.is_retry())
+                        .and_then(|steal| steal.success())
+                })
+            })
+            .or_else(|| steal_one(&self.high))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.high.is_empty()
+            && self.injector.is_empty()
+            && self.low.is_empty()
+            && self.stealers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .all(|(_, stealer)| stealer.is_empty())
+    }
+}
+
+fn steal_one<T>(injector: &Injector<T>) -> Option<T> {
+    iter::repeat_with(|| injector.steal())
+        .find(|steal| !steal.is_retry())
+        .and_then(|steal| steal.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn items_come_out_in_order_for_one_worker() {
+        let queue = Queue::new();
+        let local = queue.register(0);
+
+        for i in 0..100 {
+            queue.push(i);
+        }
+
+        let items: Vec<i32> = (0..100).map(|_| queue.pop(&local, WAIT).unwrap()).collect();
+        assert_eq!((0..100).collect::<Vec<_>>(), items);
+        assert!(queue.try_pop(&local).is_none());
+    }
+
+    #[test]
+    fn idle_workers_steal_from_busy_ones() {
+        let queue = Queue::new();
+        let busy = queue.register(0);
+        let idle = queue.register(1);
+
+        for i in 0..10 {
+            queue.push(i);
+        }
+        // The first pop moves a batch of items into the busy worker's deque.
+        assert_eq!(Some(0), queue.pop(&busy, WAIT));
+        assert!(!busy.deque.is_empty());
+
+        assert!(queue.try_pop(&idle).is_some());
+    }
+
+    #[test]
+    fn unregistering_gives_items_back() {
+        let queue = Queue::new();
+        let leaving = queue.register(0);
+        let staying = queue.register(1);
+
+        for i in 0..10 {
+            queue.push(i);
+        }
+        queue.pop(&leaving, WAIT);
+        queue.unregister(&leaving);
+
+        assert_eq!(9, iter::from_fn(|| queue.try_pop(&staying)).count());
+    }
+
+    #[test]
+    fn sleeping_workers_are_woken() {
+        let queue = Arc::new(Queue::new());
+
+        let consumers: Vec<_> = (0..4)
+            .map(|id| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    let local = queue.register(id);
+                    let mut sum = 0;
+                    loop {
+                        match queue.pop(&local, WAIT) {
+                            Some(0) => break,
+                            Some(n) => sum += n,
+                            None => {}
+                        }
+                    }
+                    queue.unregister(&local);
+                    sum
+                })
+            })
+            .collect();
+
+        for n in 1..=1000 {
+            queue.push(n);
+        }
+        for _ in 0..4 {
+            queue.push(0);
+        }
+
+        let total: u64 = consumers.into_iter().map(|c| c.join().unwrap()).sum();
+        assert_eq!(500_500, total);
+    }
+
+    #[test]
+    fn steal_oldest_prefers_the_injector() {
+        let queue = Queue::new();
+        let local = queue.register(0);
+
+        for i in 0..100 {
+            queue.push(i);
+        }
+        // Moves a batch into the worker's deque, leaving the rest behind.
+        queue.pop(&local, WAIT);
+        let oldest = queue.steal_oldest().unwrap();
+
+        assert!(!local.deque.is_empty());
+        // Items 1 to n are in the deque, so n + 1 is the oldest left behind.
+        assert_eq!(local.deque.len() as i32 + 1, oldest);
+    }
+
+    #[test]
+    fn higher_priority_lanes_go_first() {
+        let queue = Queue::new();
+        let local = queue.register(0);
+
+        queue.push_with_priority("low", Priority::Low);
+        queue.push("normal 1");
+        queue.push_with_priority("high", Priority::High);
+        queue.push("normal 2");
+
+        let items: Vec<_> = iter::from_fn(|| queue.try_pop(&local)).collect();
+        assert_eq!(vec!["high", "normal 1", "normal 2", "low"], items);
+    }
+
+    #[test]
+    fn pop_gives_up_after_the_timeout() {
+        let queue: Queue<i32> = Queue::new();
+        let local = queue.register(0);
+
+        let started = Instant::now();
+        assert_eq!(None, queue.pop(&local, Duration::from_millis(50)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
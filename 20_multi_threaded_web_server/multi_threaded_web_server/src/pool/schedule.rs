@@ -0,0 +1,233 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{lock, ExecuteError, Job, PoolEvent, Priority, Shared};
+
+/// Stops a job passed to `ThreadPool::schedule_after` or
+/// `ThreadPool::schedule_at_fixed_rate` from running again.
+///
+/// Cancelling doesn't interrupt a run that has already started. Tokens can
+/// be cloned and sent to other threads; cancelling any clone cancels the
+/// job.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+pub(super) enum Work {
+    Once(Job),
+    Repeat {
+        run: Arc<dyn Fn() + Send + Sync + 'static>,
+        period: Duration,
+    },
+}
+
+struct Entry {
+    due: Instant,
+    /// Breaks ties between entries due at the same time, so that they are
+    /// queued in the order they were scheduled.
+    seq: u64,
+    work: Work,
+    token: CancellationToken,
+}
+
+// `BinaryHeap` is a max-heap, so entries compare in reverse to put the one
+// due first at the top.
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> CmpOrdering {
+        (other.due, other.seq).cmp(&(self.due, self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+/// Holds jobs until they are due, then queues them on the pool.
+///
+/// The timer thread is only started the first time a job is scheduled.
+#[derive(Default)]
+pub(super) struct Scheduler {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    stopped: bool,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Scheduler {
+    pub fn add(
+        &self,
+        shared: &Arc<Shared>,
+        due: Instant,
+        work: Work,
+        token: CancellationToken,
+    ) -> Result<(), ExecuteError> {
+        let mut state = lock(&self.state);
+        if state.stopped {
+            return Err(ExecuteError::ShutDown);
+        }
+
+        if state.thread.is_none() {
+            let thread = spawn_timer(Arc::clone(shared)).map_err(|error| {
+                shared.emit(PoolEvent::SpawnFailed { error });
+                ExecuteError::ShutDown
+            })?;
+            state.thread = Some(thread);
+        }
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.entries.push(Entry { due, seq, work, token });
+
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// Drops the jobs that aren't due yet and tells the timer thread to
+    /// exit.
+    pub fn stop(&self) {
+        let mut state = lock(&self.state);
+        state.stopped = true;
+        let entries = mem::take(&mut state.entries);
+        self.changed.notify_one();
+
+        // The jobs are arbitrary closures; don't drop them with the lock held.
+        drop(state);
+        drop(entries);
+    }
+
+    /// Waits for the timer thread to exit after `stop`.
+    pub fn join(&self) {
+        let thread = lock(&self.state).thread.take();
+
+        if let Some(thread) = thread {
+            // It only runs pool code, which doesn't panic.
+            let _ = thread.join();
+        }
+    }
+
+    /// Blocks until an entry is due and returns it, or returns `None` once
+    /// the scheduler has been stopped.
+    fn next_due(&self) -> Option<Entry> {
+        let mut state = lock(&self.state);
+
+        loop {
+            if state.stopped {
+                return None;
+            }
+
+            let now = Instant::now();
+            let wait = match state.entries.peek() {
+                Some(entry) if entry.due <= now => return state.entries.pop(),
+                Some(entry) => Some(entry.due - now),
+                None => None,
+            };
+
+            state = match wait {
+                Some(wait) => self.changed
+                    .wait_timeout(state, wait)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0,
+                None => self.changed.wait(state).unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+fn spawn_timer(shared: Arc<Shared>) -> io::Result<thread::JoinHandle<()>> {
+    thread::Builder::new()
+        .name("pool-scheduler".to_string())
+        .spawn(move || {
+            while let Some(entry) = shared.scheduler.next_due() {
+                dispatch(&shared, entry);
+            }
+        })
+}
+
+/// Queues a due entry on the pool, without waiting for room or running it
+/// here if the queue is full, since other entries may be coming due.
+fn dispatch(shared: &Arc<Shared>, entry: Entry) {
+    let Entry { due, work, token, .. } = entry;
+    if token.is_cancelled() {
+        return;
+    }
+
+    let job: Job = match work {
+        Work::Once(job) => job,
+        Work::Repeat { run, period } => {
+            let next = NextRun {
+                shared: Arc::clone(shared),
+                due: due + period,
+                run,
+                period,
+                token,
+            };
+            Box::new(move || {
+                (next.run)();
+                drop(next);
+            })
+        }
+    };
+
+    if let Err(error) = shared.try_execute(job, Priority::Normal) {
+        shared.emit(PoolEvent::ScheduledJobSkipped { error });
+    }
+}
+
+/// The next run of a repeating job, scheduled when the current run is
+/// dropped: once it has finished, panicked or not, so runs never overlap,
+/// or if it is turned away or dropped from the queue without running, so
+/// that a skipped run doesn't end the schedule. If a run takes longer than
+/// the period, the next one starts straight after it.
+struct NextRun {
+    shared: Arc<Shared>,
+    due: Instant,
+    run: Arc<dyn Fn() + Send + Sync + 'static>,
+    period: Duration,
+    token: CancellationToken,
+}
+
+impl Drop for NextRun {
+    fn drop(&mut self) {
+        if self.token.is_cancelled() {
+            return;
+        }
+
+        let work = Work::Repeat { run: Arc::clone(&self.run), period: self.period };
+        // Fails only once the pool is shutting down, when the schedule ends
+        // anyway.
+        let _ = self.shared.scheduler.add(&self.shared, self.due, work, self.token.clone());
+    }
+}
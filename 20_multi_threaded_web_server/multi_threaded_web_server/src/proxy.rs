@@ -0,0 +1,502 @@
+//! A handler that forwards requests to upstream servers.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::client::{Client, ClientError};
+use crate::headers::Headers;
+use crate::log::{log, LogLevel};
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::{Handler, Params};
+use crate::status::Status;
+
+/// Headers that only describe one connection, and so aren't passed on.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// How a `Proxy` picks an upstream for each request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    /// Each upstream in turn.
+    RoundRobin,
+    /// The upstream with the fewest requests in flight, taking them in turn
+    /// when it's a tie.
+    LeastConnections,
+}
+
+/// Forwards requests to a set of upstream servers over HTTP/1.1 and passes
+/// their responses back.
+///
+/// The request keeps its method, path, query and body. `Host` is set to the
+/// upstream's address, the original going in `X-Forwarded-Host`, and the
+/// client's address is added to `X-Forwarded-For`. Requests go through a
+/// `Client`, which keeps connections to the upstreams open between them,
+/// and the upstream's response is read whole before it is passed on.
+/// Redirects are passed back rather than followed.
+///
+/// An upstream that can't be reached is skipped for another; one that fails
+/// `max_failures` times in a row is left out for `fail_timeout`, unless all
+/// of them are. If the request can't be forwarded, or the upstream fails
+/// while answering, the client gets 502 Bad Gateway, or 504 Gateway Timeout
+/// if it ran out of time.
+///
+/// Clones share the upstreams and what is known of their health, so one
+/// proxy can serve several routes:
+///
+/// ```
+/// use multi_threaded_web_server::proxy::{Balance, Proxy};
+/// use multi_threaded_web_server::Router;
+///
+/// let api = Proxy::new(&["10.0.0.1:8080", "10.0.0.2:8080"])
+///     .with_balance(Balance::LeastConnections)
+///     .with_strip_prefix("/api");
+/// let router = Router::new()
+///     .get("/api/*path", api.clone())
+///     .post("/api/*path", api);
+/// ```
+#[derive(Clone)]
+pub struct Proxy {
+    upstreams: Arc<Vec<Upstream>>,
+    /// Where the next round-robin turn starts.
+    next: Arc<AtomicUsize>,
+    balance: Balance,
+    strip_prefix: Option<String>,
+    forwarded_proto: String,
+    client: Client,
+    max_failures: u32,
+    fail_timeout: Duration,
+}
+
+impl Proxy {
+    /// A proxy to the upstreams at `addrs`, each a `host:port`, balanced
+    /// round-robin.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if `addrs` is empty.
+    pub fn new<S: AsRef<str>>(addrs: &[S]) -> Proxy {
+        assert!(!addrs.is_empty(), "a proxy needs at least one upstream");
+        let upstreams = addrs
+            .iter()
+            .map(|addr| Upstream {
+                addr: addr.as_ref().to_string(),
+                active: AtomicUsize::new(0),
+                health: Mutex::new(Health::default()),
+            })
+            .collect();
+
+        Proxy {
+            upstreams: Arc::new(upstreams),
+            next: Arc::new(AtomicUsize::new(0)),
+            balance: Balance::RoundRobin,
+            strip_prefix: None,
+            forwarded_proto: "http".to_string(),
+            client: Client::new()
+                .with_connect_timeout(Duration::from_secs(2))
+                .with_timeout(Duration::from_secs(30))
+                .with_max_redirects(0),
+            max_failures: 3,
+            fail_timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn with_balance(mut self, balance: Balance) -> Proxy {
+        self.balance = balance;
+        self
+    }
+
+    /// Drops `prefix` from the start of paths before forwarding them, so
+    /// that `/api/users` under a prefix of `/api` goes upstream as `/users`.
+    pub fn with_strip_prefix(mut self, prefix: &str) -> Proxy {
+        self.strip_prefix = Some(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Sets the scheme upstreams are told the client used, in
+    /// `X-Forwarded-Proto`. Use `https` for a server with TLS; the default
+    /// is `http`.
+    pub fn with_forwarded_proto(mut self, proto: &str) -> Proxy {
+        self.forwarded_proto = proto.to_string();
+        self
+    }
+
+    /// Sets how long connecting to an upstream may take before the next one
+    /// is tried. The default is 2 seconds.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.client = self.client.with_connect_timeout(timeout);
+        self
+    }
+
+    /// Sets how long any one read from or write to an upstream may wait.
+    /// The default is 30 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Proxy {
+        self.client = self.client.with_timeout(timeout);
+        self
+    }
+
+    /// Sets how many failures in a row take an upstream out of rotation,
+    /// and for how long. The defaults are 3 and 10 seconds.
+    pub fn with_passive_health(mut self, max_failures: u32, fail_timeout: Duration) -> Proxy {
+        self.max_failures = max_failures.max(1);
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    /// Sets the largest response body taken from an upstream; larger ones
+    /// get 502. The default is 16 MiB.
+    pub fn with_max_response_size(mut self, size: usize) -> Proxy {
+        self.client = self.client.with_max_response_size(size);
+        self
+    }
+
+    /// Picks the next upstream to try from those not `tried` yet, or `None`
+    /// once they all have been.
+    fn pick(&self, tried: &[bool]) -> Option<usize> {
+        let now = Instant::now();
+        let len = self.upstreams.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % len;
+        let in_turn = (0..len).map(|i| (start + i) % len).filter(|&i| !tried[i]);
+
+        // Upstreams that are down are only tried when they all are, so that
+        // they get a chance to come back.
+        let any_up = self.upstreams.iter().any(|upstream| upstream.is_up(now));
+        let mut candidates = in_turn.filter(|&i| !any_up || self.upstreams[i].is_up(now));
+
+        match self.balance {
+            Balance::RoundRobin => candidates.next(),
+            Balance::LeastConnections => {
+                candidates.min_by_key(|&i| self.upstreams[i].active.load(Ordering::Relaxed))
+            }
+        }
+    }
+
+    fn forward(&self, upstream: &Upstream, request: &Request) -> Result<Response, ClientError> {
+        let origin = format!("http://{}", upstream.addr);
+        let mut response = self.client.send(&origin, self.upstream_request(request, &upstream.addr))?;
+        response.headers = end_to_end(&response.headers);
+        // The body is in memory now, and is framed afresh.
+        response.headers.remove("Content-Length");
+        Ok(response)
+    }
+
+    /// The request as it is sent upstream.
+    fn upstream_request(&self, request: &Request, upstream: &str) -> Request {
+        let path = match &self.strip_prefix {
+            Some(prefix) => match request.path.strip_prefix(prefix.as_str()) {
+                Some(rest) if rest.starts_with('/') => rest,
+                Some("") => "/",
+                _ => &request.path,
+            },
+            None => &request.path,
+        };
+        let mut forwarded = Request::new(request.method.clone(), path);
+        forwarded.query = request.query.clone();
+        forwarded.body = request.body.clone();
+
+        let mut headers = end_to_end(&request.headers);
+        let forwarded_for = match (request.header("X-Forwarded-For"), request.remote_addr) {
+            (Some(earlier), Some(addr)) => Some(format!("{}, {}", earlier, addr.ip())),
+            (None, Some(addr)) => Some(addr.ip().to_string()),
+            (earlier, None) => earlier.map(str::to_string),
+        };
+        if let Some(forwarded_for) = forwarded_for {
+            headers.insert("X-Forwarded-For", forwarded_for);
+        }
+        match request.header("Host") {
+            Some(host) => headers.insert("X-Forwarded-Host", host),
+            None => headers.remove("X-Forwarded-Host"),
+        }
+        headers.insert("X-Forwarded-Proto", self.forwarded_proto.as_str());
+        headers.insert("Host", upstream);
+        forwarded.headers = headers;
+        forwarded
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, request: &Request, _: &Params) -> Response {
+        let mut tried = vec![false; self.upstreams.len()];
+
+        while let Some(i) = self.pick(&tried) {
+            tried[i] = true;
+            let upstream = &self.upstreams[i];
+            upstream.active.fetch_add(1, Ordering::Relaxed);
+            let result = self.forward(upstream, request);
+            upstream.active.fetch_sub(1, Ordering::Relaxed);
+
+            match result {
+                Ok(response) => {
+                    upstream.succeeded();
+                    return response;
+                }
+                // Nothing was sent, so another upstream can have a go.
+                Err(e @ ClientError::Connect(_)) | Err(e @ ClientError::InvalidUrl(_)) => {
+                    log(LogLevel::Warn, format_args!("Proxy: can't connect to {}: {}", upstream.addr, e));
+                    upstream.failed(self.max_failures, self.fail_timeout);
+                }
+                Err(e) => {
+                    log(LogLevel::Warn, format_args!("Proxy: error from {}: {}", upstream.addr, e));
+                    upstream.failed(self.max_failures, self.fail_timeout);
+                    if e.is_timeout() {
+                        return Response::text(Status::GatewayTimeout, "Gateway Timeout\n");
+                    }
+                    return Response::text(Status::BadGateway, "Bad Gateway\n");
+                }
+            }
+        }
+
+        Response::text(Status::BadGateway, "Bad Gateway\n")
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let addrs: Vec<&str> = self.upstreams.iter().map(|upstream| upstream.addr.as_str()).collect();
+        f.debug_struct("Proxy")
+            .field("upstreams", &addrs)
+            .field("balance", &self.balance)
+            .finish()
+    }
+}
+
+struct Upstream {
+    addr: String,
+    /// Requests in flight.
+    active: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    /// Failures since the last success.
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+impl Upstream {
+    fn is_up(&self, now: Instant) -> bool {
+        self.health.lock().unwrap().down_until.is_none_or(|until| now >= until)
+    }
+
+    fn succeeded(&self) {
+        *self.health.lock().unwrap() = Health::default();
+    }
+
+    fn failed(&self, max_failures: u32, fail_timeout: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.failures += 1;
+        if health.failures >= max_failures {
+            health.down_until = Some(Instant::now() + fail_timeout);
+        }
+    }
+}
+
+/// `headers` without the hop-by-hop ones, including any named in
+/// `Connection`.
+fn end_to_end(headers: &Headers) -> Headers {
+    let listed: Vec<String> = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_string())
+        .collect();
+
+    let mut kept = Headers::new();
+    for (name, value) in headers.iter() {
+        let hop_by_hop = HOP_BY_HOP.iter().any(|hop| hop.eq_ignore_ascii_case(name))
+            || listed.iter().any(|hop| hop.eq_ignore_ascii_case(name));
+        if !hop_by_hop {
+            kept.append(name, value);
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// A stand-in upstream that answers each connection with `answer`,
+    /// given the request it read, and returns its address.
+    fn upstream<F>(answer: F) -> String
+        where
+            F: Fn(String) -> String + Send + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                while !request.ends_with("\r\n\r\n") {
+                    if reader.read_line(&mut request).unwrap() == 0 {
+                        break;
+                    }
+                }
+                let len = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map_or(0, |len| len.parse().unwrap());
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+
+                let _ = stream.write_all(answer(request).as_bytes());
+            }
+        });
+
+        addr
+    }
+
+    /// An upstream that always answers with `name`.
+    fn named(name: &'static str) -> String {
+        upstream(move |_| format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", name.len(), name))
+    }
+
+    /// An address nothing is listening on.
+    fn closed_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn get(proxy: &Proxy, target: &str) -> Response {
+        let mut request = Request::new(Method::Get, target);
+        request.remote_addr = Some("10.1.2.3:5000".parse().unwrap());
+        proxy.handle(&request, &Params::default())
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(response.body.as_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn forwards_requests_and_rewrites_headers() {
+        let addr = upstream(|request| {
+            format!(
+                "HTTP/1.1 201 Created\r\nConnection: close, X-Secret\r\nX-Secret: 1\r\nSet-Cookie: a=1\r\n\
+Set-Cookie: b=2\r\nContent-Length: {}\r\n\r\n{}",
+                request.len(),
+                request
+            )
+        });
+        let proxy = Proxy::new(&[&addr]).with_strip_prefix("/api/").with_forwarded_proto("https");
+        let mut request = Request::new(Method::Post, "/api/users?page=2");
+        request.headers.insert("Host", "example.com");
+        request.headers.insert("X-Forwarded-For", "192.0.2.1");
+        request.headers.insert("Connection", "keep-alive, X-Hop");
+        request.headers.insert("X-Hop", "1");
+        request.headers.insert("Content-Length", "99");
+        request.body = b"name=ann".to_vec();
+        request.remote_addr = Some("10.1.2.3:5000".parse().unwrap());
+
+        let response = proxy.handle(&request, &Params::default());
+        let received = body(&response);
+
+        assert_eq!(Status::Created, response.status);
+        assert!(received.starts_with("POST /users?page=2 HTTP/1.1\r\n"));
+        assert!(received.contains(&format!("Host: {}\r\n", addr)));
+        assert!(received.contains("X-Forwarded-For: 192.0.2.1, 10.1.2.3\r\n"));
+        assert!(received.contains("X-Forwarded-Host: example.com\r\n"));
+        assert!(received.contains("X-Forwarded-Proto: https\r\n"));
+        assert!(received.contains("Content-Length: 8\r\n"));
+        assert!(received.ends_with("\r\n\r\nname=ann"));
+        assert!(!received.contains("X-Hop"));
+        assert_eq!(None, response.headers.get("X-Secret"));
+        assert_eq!(2, response.headers.get_all("Set-Cookie").count());
+    }
+
+    #[test]
+    fn takes_upstreams_in_turn() {
+        let proxy = Proxy::new(&[named("a"), named("b")]);
+        let bodies: Vec<String> = (0..4).map(|_| body(&get(&proxy, "/")).to_string()).collect();
+
+        assert_eq!(vec!["a", "b", "a", "b"], bodies);
+    }
+
+    #[test]
+    fn least_connections_avoids_busy_upstreams() {
+        let (release, held) = mpsc::channel::<()>();
+        let held = Mutex::new(held);
+        let slow = upstream(move |_| {
+            held.lock().unwrap().recv().unwrap();
+            "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nslow".to_string()
+        });
+        let proxy = Proxy::new(&[slow, named("fast")]).with_balance(Balance::LeastConnections);
+
+        // With nothing in flight, the first request goes to the first
+        // upstream, which is then busy until released.
+        let first = {
+            let proxy = proxy.clone();
+            thread::spawn(move || body(&get(&proxy, "/")).to_string())
+        };
+        while proxy.upstreams[0].active.load(Ordering::Relaxed) == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let others: Vec<String> = (0..3).map(|_| body(&get(&proxy, "/")).to_string()).collect();
+        release.send(()).unwrap();
+
+        assert_eq!("slow", first.join().unwrap());
+        assert_eq!(vec!["fast"; 3], others);
+    }
+
+    #[test]
+    fn skips_upstreams_that_keep_failing() {
+        let proxy = Proxy::new(&[closed_port(), named("up")]).with_passive_health(2, Duration::from_secs(60));
+
+        for _ in 0..4 {
+            assert_eq!("up", body(&get(&proxy, "/")));
+        }
+        let now = Instant::now();
+        assert!(!proxy.upstreams[0].is_up(now));
+        assert!(proxy.upstreams[1].is_up(now));
+    }
+
+    #[test]
+    fn upstream_failures_get_502_or_504() {
+        let down = Proxy::new(&[closed_port()]);
+        let broken = Proxy::new(&[upstream(|_| "nonsense\r\n\r\n".to_string())]);
+        let (_keep, stalled) = mpsc::channel::<()>();
+        let stalled = Mutex::new(stalled);
+        let slow = Proxy::new(&[upstream(move |_| {
+            let _ = stalled.lock().unwrap().recv();
+            String::new()
+        })])
+        .with_timeout(Duration::from_millis(100));
+
+        assert_eq!(Status::BadGateway, get(&down, "/").status);
+        assert_eq!(Status::BadGateway, get(&broken, "/").status);
+        assert_eq!(Status::GatewayTimeout, get(&slow, "/").status);
+    }
+
+    #[test]
+    fn reads_chunked_responses() {
+        let addr = upstream(|_| {
+            "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n"
+                .to_string()
+        });
+        let response = get(&Proxy::new(&[addr]), "/");
+
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!("hello world", body(&response));
+        assert_eq!(None, response.headers.get("Transfer-Encoding"));
+    }
+}
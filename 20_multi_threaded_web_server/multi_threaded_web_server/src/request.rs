@@ -0,0 +1,821 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::str;
+
+use crate::headers::Headers;
+
+const READ_CHUNK: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    Other(String),
+}
+
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+            Method::Other(name) => name,
+        }
+    }
+
+    /// Parses a method token. Method names are case-sensitive, so `get` is
+    /// an extension method rather than `GET`.
+    pub fn parse(token: &str) -> Option<Method> {
+        if token.is_empty() || !token.bytes().all(is_token_byte) {
+            return None;
+        }
+
+        let method = match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "CONNECT" => Method::Connect,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "PATCH" => Method::Patch,
+            other => Method::Other(other.to_string()),
+        };
+
+        Some(method)
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Http10 => f.write_str("HTTP/1.0"),
+            Version::Http11 => f.write_str("HTTP/1.1"),
+        }
+    }
+}
+
+/// A parsed HTTP/1.x request.
+///
+/// `path` is kept exactly as it appeared in the request line, still
+/// percent-encoded; `query` is everything after the `?`, without it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// The client's address, when the request came over a connection.
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl Request {
+    /// Creates an HTTP/1.1 request for `target`, which may include a query
+    /// string.
+    pub fn new(method: Method, target: &str) -> Request {
+        let (path, query) = split_target(target);
+
+        Request {
+            method,
+            path: path.to_string(),
+            query: query.map(|q| q.to_string()),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            remote_addr: None,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Request {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Request {
+        self.body = body.into();
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns the decoded value of the first `name` parameter in the query
+    /// string.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        let query = self.query.as_ref()?;
+
+        query.split('&').find_map(|pair| {
+            let (key, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, ""),
+            };
+
+            if percent_decode(key, true)? == name {
+                percent_decode(value, true)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    UnexpectedEof,
+    RequestLine,
+    Method,
+    Target,
+    Version,
+    Header,
+    MissingHost,
+    ContentLength,
+    TransferEncoding,
+    Chunk,
+    /// The request line and headers were longer than the reader allows.
+    HeaderTooLarge,
+    /// The body was longer than the reader allows.
+    BodyTooLarge,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "error reading request: {}", e),
+            ParseError::UnexpectedEof => f.write_str("connection closed in the middle of a request"),
+            ParseError::RequestLine => f.write_str("malformed request line"),
+            ParseError::Method => f.write_str("invalid request method"),
+            ParseError::Target => f.write_str("invalid request target"),
+            ParseError::Version => f.write_str("unsupported HTTP version"),
+            ParseError::Header => f.write_str("malformed header field"),
+            ParseError::MissingHost => f.write_str("HTTP/1.1 request without a Host header"),
+            ParseError::ContentLength => f.write_str("invalid Content-Length"),
+            ParseError::TransferEncoding => f.write_str("unsupported Transfer-Encoding"),
+            ParseError::Chunk => f.write_str("malformed chunked body"),
+            ParseError::HeaderTooLarge => f.write_str("request header section is too large"),
+            ParseError::BodyTooLarge => f.write_str("request body is too large"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+/// Reads requests one after another from a byte stream.
+///
+/// A single `read` on a socket can return part of a request, or the end of
+/// one request and the start of the next, so bytes that haven't been
+/// consumed yet are kept in a buffer between calls.
+///
+/// By default there is no limit on the size of a request; set one with
+/// `with_max_header_size` and `with_max_body_size` when reading from
+/// untrusted clients.
+///
+/// Nothing is consumed until a whole head or body has arrived, so on a
+/// non-blocking stream `read_head` and `read_body` can fail with
+/// `WouldBlock` and simply be called again once there is more to read.
+pub struct RequestReader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    max_header_size: usize,
+    max_body_size: usize,
+    /// How far through a chunked body a read that would have blocked got.
+    chunked: Option<Chunked>,
+}
+
+/// Progress through a chunked body, in terms of positions in the buffer.
+#[derive(Default)]
+struct Chunked {
+    /// Where the next chunk-size or trailer line starts.
+    pos: usize,
+    /// How much of the line at `pos` has been searched for its end.
+    scanned: usize,
+    chunks: Vec<Range<usize>>,
+    length: usize,
+    /// Set once the last chunk has been read.
+    trailers: bool,
+    trailer_size: usize,
+}
+
+impl<R: Read> RequestReader<R> {
+    pub fn new(inner: R) -> RequestReader<R> {
+        RequestReader {
+            inner,
+            buffer: Vec::new(),
+            max_header_size: usize::MAX,
+            max_body_size: usize::MAX,
+            chunked: None,
+        }
+    }
+
+    /// Limits the request line and headers, including the line endings, to
+    /// `size` bytes. Longer ones fail with `ParseError::HeaderTooLarge`, as
+    /// do chunk-size lines and trailer sections of chunked bodies that are
+    /// longer.
+    pub fn with_max_header_size(mut self, size: usize) -> RequestReader<R> {
+        self.max_header_size = size;
+        self
+    }
+
+    /// Limits bodies to `size` bytes. Longer ones fail with
+    /// `ParseError::BodyTooLarge`, before any of the body is read if it has
+    /// a `Content-Length`.
+    pub fn with_max_body_size(mut self, size: usize) -> RequestReader<R> {
+        self.max_body_size = size;
+        self
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Bytes that have been read from the stream but not yet returned as
+    /// part of a request.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Gives back the stream and the bytes in the buffer, for when the
+    /// connection switches to another protocol.
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        (self.inner, self.buffer)
+    }
+
+    /// Reads the next request, or returns `Ok(None)` if the stream ended
+    /// cleanly before a new request started.
+    pub fn read_request(&mut self) -> Result<Option<Request>, ParseError> {
+        let mut request = match self.read_head()? {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+        self.read_body(&mut request)?;
+
+        Ok(Some(request))
+    }
+
+    /// Reads the next request line and headers but not the body, which
+    /// must be read with `read_body` before the next request. Returns
+    /// `Ok(None)` if the stream ended cleanly before a new request started.
+    pub fn read_head(&mut self) -> Result<Option<Request>, ParseError> {
+        let head_end = loop {
+            // RFC 7230 section 3.5: ignore empty lines before a request line.
+            let blank = self.buffer
+                .iter()
+                .take_while(|&&b| b == b'\r' || b == b'\n')
+                .count();
+            self.buffer.drain(..blank);
+
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                if end + 4 > self.max_header_size {
+                    return Err(ParseError::HeaderTooLarge);
+                }
+                break end;
+            }
+            if self.buffer.len() >= self.max_header_size {
+                return Err(ParseError::HeaderTooLarge);
+            }
+
+            if self.fill()? == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(ParseError::UnexpectedEof);
+            }
+        };
+
+        let head: Vec<u8> = self.buffer.drain(..head_end + 4).collect();
+        parse_head(&head[..head_end]).map(Some)
+    }
+
+    /// Reads the body of a request returned by `read_head` into
+    /// `request.body`.
+    pub fn read_body(&mut self, request: &mut Request) -> Result<(), ParseError> {
+        request.body = self.read_body_for(&request.headers)?;
+        Ok(())
+    }
+
+    fn read_body_for(&mut self, headers: &Headers) -> Result<Vec<u8>, ParseError> {
+        let codings: Vec<&str> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim())
+            .collect();
+
+        if !codings.is_empty() {
+            // A message with both is a classic request smuggling vector.
+            if headers.contains("Content-Length") {
+                return Err(ParseError::TransferEncoding);
+            }
+            if codings.len() != 1 || !codings[0].eq_ignore_ascii_case("chunked") {
+                return Err(ParseError::TransferEncoding);
+            }
+            return self.read_chunked();
+        }
+
+        match content_length(headers)? {
+            Some(length) if length > self.max_body_size => Err(ParseError::BodyTooLarge),
+            Some(length) => self.read_exact(length),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Finds every chunk before consuming any of them, so that a read that
+    /// fails part-way, such as with `WouldBlock` on a non-blocking stream,
+    /// leaves the buffer as it was. How far it got is kept, and the next
+    /// call carries on from there.
+    fn read_chunked(&mut self) -> Result<Vec<u8>, ParseError> {
+        let mut state = self.chunked.take().unwrap_or_default();
+        match self.find_chunks(&mut state) {
+            Ok(()) => {}
+            Err(ParseError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.chunked = Some(state);
+                return Err(ParseError::Io(e));
+            }
+            Err(e) => return Err(e),
+        }
+
+        let mut body = Vec::with_capacity(state.length);
+        for chunk in state.chunks {
+            body.extend_from_slice(&self.buffer[chunk]);
+        }
+        self.buffer.drain(..state.pos);
+
+        Ok(body)
+    }
+
+    fn find_chunks(&mut self, state: &mut Chunked) -> Result<(), ParseError> {
+        while !state.trailers {
+            let end = self.line_at(state.pos, &mut state.scanned, self.max_header_size)?;
+            let line = str::from_utf8(&self.buffer[state.pos..end]).map_err(|_| ParseError::Chunk)?;
+            let size = line.split(';').next().unwrap_or("").trim();
+
+            // `from_str_radix` would also take a leading sign.
+            if size.is_empty() || size.len() > 15 || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(ParseError::Chunk);
+            }
+            let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::Chunk)?;
+            let data = end + 2;
+
+            if size == 0 {
+                state.trailers = true;
+                state.pos = data;
+                state.scanned = data;
+                break;
+            }
+            if size > self.max_body_size - state.length {
+                return Err(ParseError::BodyTooLarge);
+            }
+
+            self.fill_to(data + size + 2)?;
+            if &self.buffer[data + size..data + size + 2] != b"\r\n" {
+                return Err(ParseError::Chunk);
+            }
+            state.chunks.push(data..data + size);
+            state.length += size;
+            state.pos = data + size + 2;
+            state.scanned = state.pos;
+        }
+
+        // Trailer fields aren't used by anything here, so skip over them,
+        // counting them against the same limit as the header section.
+        loop {
+            let limit = self.max_header_size - state.trailer_size;
+            let end = self.line_at(state.pos, &mut state.scanned, limit.saturating_sub(2))?;
+            let empty = end == state.pos;
+            state.trailer_size += end + 2 - state.pos;
+            state.pos = end + 2;
+            state.scanned = state.pos;
+            if empty {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns where the line starting at `pos` in the buffer ends, not
+    /// counting its CRLF, reading more until it is all there. Fails with
+    /// `ParseError::HeaderTooLarge` once the line is known to be longer
+    /// than `limit`.
+    ///
+    /// `scanned` is how far the line has already been searched, and is
+    /// moved on as more of it is.
+    fn line_at(&mut self, pos: usize, scanned: &mut usize, limit: usize) -> Result<usize, ParseError> {
+        loop {
+            // Only what arrived since the last search is new, apart from a
+            // CR that may have been waiting for its LF.
+            let from = scanned.saturating_sub(1).max(pos);
+            if let Some(end) = self.buffer.get(from..).and_then(|rest| find(rest, b"\r\n")) {
+                let end = from + end;
+                if end - pos > limit {
+                    return Err(ParseError::HeaderTooLarge);
+                }
+                return Ok(end);
+            }
+
+            *scanned = self.buffer.len().max(pos);
+            if *scanned - pos > limit.saturating_add(1) {
+                return Err(ParseError::HeaderTooLarge);
+            }
+            if self.fill()? == 0 {
+                return Err(ParseError::UnexpectedEof);
+            }
+        }
+    }
+
+    fn read_exact(&mut self, length: usize) -> Result<Vec<u8>, ParseError> {
+        self.fill_to(length)?;
+        Ok(self.buffer.drain(..length).collect())
+    }
+
+    /// Reads until the buffer holds at least `length` bytes.
+    fn fill_to(&mut self, length: usize) -> Result<(), ParseError> {
+        while self.buffer.len() < length {
+            if self.fill()? == 0 {
+                return Err(ParseError::UnexpectedEof);
+            }
+        }
+        Ok(())
+    }
+
+    fn fill(&mut self) -> Result<usize, ParseError> {
+        let mut chunk = [0; READ_CHUNK];
+
+        loop {
+            match self.inner.read(&mut chunk) {
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    return Ok(n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ParseError::Io(e)),
+            }
+        }
+    }
+}
+
+fn parse_head(head: &[u8]) -> Result<Request, ParseError> {
+    let head = str::from_utf8(head).map_err(|_| ParseError::Header)?;
+    let mut lines = head.split("\r\n");
+
+    let request_line = lines.next().unwrap_or("");
+    let parts: Vec<&str> = request_line.split(' ').collect();
+    if parts.len() != 3 {
+        return Err(ParseError::RequestLine);
+    }
+
+    let method = Method::parse(parts[0]).ok_or(ParseError::Method)?;
+    let target = parse_target(parts[1])?;
+    let version = match parts[2] {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        _ => return Err(ParseError::Version),
+    };
+
+    let mut headers = Headers::new();
+    for line in lines {
+        // Folded header values were deprecated by RFC 7230.
+        if line.starts_with(' ') || line.starts_with('\t') {
+            return Err(ParseError::Header);
+        }
+
+        let colon = line.find(':').ok_or(ParseError::Header)?;
+        let name = &line[..colon];
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(ParseError::Header);
+        }
+
+        let value = line[colon + 1..].trim_matches([' ', '\t']);
+        headers.append(name, value);
+    }
+
+    if version == Version::Http11 && !headers.contains("Host") {
+        return Err(ParseError::MissingHost);
+    }
+
+    let (path, query) = split_target(target);
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query: query.map(|q| q.to_string()),
+        version,
+        headers,
+        body: Vec::new(),
+        remote_addr: None,
+    })
+}
+
+/// Accepts origin-form (`/path?query`), asterisk-form (`*`) and
+/// absolute-form (`http://host/path`) targets, returning the part from the
+/// path onwards.
+fn parse_target(target: &str) -> Result<&str, ParseError> {
+    if target.bytes().any(|b| b.is_ascii_control() || b >= 0x80) {
+        return Err(ParseError::Target);
+    }
+
+    if target.starts_with('/') || target == "*" {
+        return Ok(target);
+    }
+
+    let lower = target.to_ascii_lowercase();
+    let rest = if lower.starts_with("http://") {
+        &target[7..]
+    } else if lower.starts_with("https://") {
+        &target[8..]
+    } else {
+        return Err(ParseError::Target);
+    };
+
+    match rest.find(['/', '?']) {
+        Some(i) if rest.as_bytes()[i] == b'/' => Ok(&rest[i..]),
+        _ => Err(ParseError::Target),
+    }
+}
+
+fn split_target(target: &str) -> (&str, Option<&str>) {
+    match target.find('?') {
+        Some(i) => (&target[..i], Some(&target[i + 1..])),
+        None => (target, None),
+    }
+}
+
+fn content_length(headers: &Headers) -> Result<Option<usize>, ParseError> {
+    let mut length = None;
+
+    for value in headers.get_all("Content-Length").flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::ContentLength);
+        }
+        let parsed: usize = value.parse().map_err(|_| ParseError::ContentLength)?;
+
+        // Repeated values are allowed as long as they all agree.
+        match length {
+            Some(previous) if previous != parsed => return Err(ParseError::ContentLength),
+            _ => length = Some(parsed),
+        }
+    }
+
+    Ok(length)
+}
+
+/// Decodes `%XX` escapes, and `+` as a space when `plus_as_space` is set.
+/// Returns `None` for a malformed escape or a result that isn't UTF-8.
+pub(crate) fn percent_decode(input: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = input.get(i + 1..i + 3)?;
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out its input a few bytes at a time, like a slow socket.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn parse(input: &[u8]) -> Result<Option<Request>, ParseError> {
+        RequestReader::new(input).read_request()
+    }
+
+    #[test]
+    fn request_line_and_headers() {
+        let request = parse(b"GET /search?q=rust+book&page=2 HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\n\r\n")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(Method::Get, request.method);
+        assert_eq!("/search", request.path);
+        assert_eq!(Some("q=rust+book&page=2".to_string()), request.query);
+        assert_eq!(Some("rust book".to_string()), request.query_param("q"));
+        assert_eq!(Version::Http11, request.version);
+        assert_eq!(Some("test"), request.header("user-agent"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn content_length_body_across_partial_reads() {
+        let input = b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 11\r\n\r\nhello world";
+        let mut reader = RequestReader::new(Trickle { data: input, step: 3 });
+
+        let request = reader.read_request().unwrap().unwrap();
+
+        assert_eq!(b"hello world".to_vec(), request.body);
+    }
+
+    #[test]
+    fn chunked_body() {
+        let input = b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nExpires: never\r\n\r\n";
+        let mut reader = RequestReader::new(Trickle { data: input, step: 5 });
+
+        let request = reader.read_request().unwrap().unwrap();
+
+        assert_eq!(b"hello world".to_vec(), request.body);
+    }
+
+    #[test]
+    fn resumes_after_would_block() {
+        /// Fails every other read with `WouldBlock`, like a non-blocking
+        /// socket that has run dry.
+        struct NonBlocking<'a> {
+            inner: Trickle<'a>,
+            ready: bool,
+        }
+
+        impl<'a> Read for NonBlocking<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.ready = !self.ready;
+                if self.ready {
+                    self.inner.read(buf)
+                } else {
+                    Err(io::ErrorKind::WouldBlock.into())
+                }
+            }
+        }
+
+        let input = b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let mut reader = RequestReader::new(NonBlocking {
+            inner: Trickle { data: input, step: 7 },
+            ready: true,
+        });
+
+        let mut request = loop {
+            match reader.read_head() {
+                Ok(request) => break request.unwrap(),
+                Err(ParseError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => panic!("{}", e),
+            }
+        };
+        loop {
+            match reader.read_body(&mut request) {
+                Ok(()) => break,
+                Err(ParseError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        assert_eq!(b"hello world".to_vec(), request.body);
+        assert!(reader.buffer().is_empty());
+        assert!(reader.chunked.is_none());
+    }
+
+    #[test]
+    fn consecutive_requests_on_one_stream() {
+        let input = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut reader = RequestReader::new(&input[..]);
+
+        assert_eq!("/a", reader.read_request().unwrap().unwrap().path);
+        assert_eq!("/b", reader.read_request().unwrap().unwrap().path);
+        assert!(reader.read_request().unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_requests_are_errors() {
+        assert!(matches!(parse(b"GET /\r\n\r\n"), Err(ParseError::RequestLine)));
+        assert!(matches!(parse(b"GET / HTTP/2.0\r\nHost: x\r\n\r\n"), Err(ParseError::Version)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\n\r\n"), Err(ParseError::MissingHost)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nHost x\r\n\r\n"), Err(ParseError::Header)));
+        assert!(matches!(
+            parse(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1, 2\r\n\r\n"),
+            Err(ParseError::ContentLength)
+        ));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nHost: x\r\n"), Err(ParseError::UnexpectedEof)));
+        assert!(matches!(
+            parse(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n+5\r\nhello\r\n0\r\n\r\n"),
+            Err(ParseError::Chunk)
+        ));
+    }
+
+    #[test]
+    fn percent_escapes_are_two_hex_digits() {
+        assert_eq!(Some("a b/c".to_string()), percent_decode("a+b%2fc", true));
+        assert_eq!(None, percent_decode("%+1", false));
+        assert_eq!(None, percent_decode("%4", false));
+    }
+
+    #[test]
+    fn size_limits() {
+        let long_header = format!("GET / HTTP/1.1\r\nHost: x\r\nCookie: {}\r\n\r\n", "a".repeat(100));
+        let limited = |input: &[u8]| {
+            RequestReader::new(Trickle { data: input, step: 7 })
+                .with_max_header_size(64)
+                .with_max_body_size(8)
+                .read_request()
+        };
+
+        assert!(limited(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 8\r\n\r\n12345678").is_ok());
+        assert!(matches!(limited(long_header.as_bytes()), Err(ParseError::HeaderTooLarge)));
+        assert!(matches!(
+            limited(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 9\r\n\r\n123456789"),
+            Err(ParseError::BodyTooLarge)
+        ));
+        assert!(matches!(
+            limited(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n12345\r\n5\r\n67890\r\n0\r\n\r\n"),
+            Err(ParseError::BodyTooLarge)
+        ));
+
+        let long_extension = format!(
+            "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n1;{}\r\na\r\n0\r\n\r\n",
+            "a".repeat(100)
+        );
+        assert!(matches!(limited(long_extension.as_bytes()), Err(ParseError::HeaderTooLarge)));
+        let long_trailers = format!(
+            "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n{}\r\n\r\n",
+            "A: b\r\n".repeat(20)
+        );
+        assert!(matches!(limited(long_trailers.as_bytes()), Err(ParseError::HeaderTooLarge)));
+        assert!(limited(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nA: b\r\n\r\n").is_ok());
+    }
+}
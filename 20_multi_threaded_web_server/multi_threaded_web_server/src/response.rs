@@ -0,0 +1,536 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::mem;
+
+use crate::headers::Headers;
+use crate::request::{Method, Request, Version};
+use crate::status::Status;
+use crate::websocket::Upgrade;
+
+/// How much of a file `Pieces` reads at a time.
+const FILE_PIECE: usize = 16 * 1024;
+
+/// The longest status line or header line `read_response` accepts.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// The chunks of a streamed body. An error ends the response early.
+type Chunks = Box<dyn Iterator<Item = io::Result<Vec<u8>>> + Send>;
+
+/// The payload of a response.
+///
+/// Files are copied to the client straight from disk rather than being read
+/// into memory first. A stream is sent a chunk at a time as the iterator
+/// produces them, without knowing its length in advance.
+pub enum Body {
+    Bytes(Vec<u8>),
+    File { file: File, len: u64 },
+    Stream(Chunks),
+}
+
+impl Body {
+    /// The length in bytes, or `None` for a stream.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File { len, .. } => Some(*len),
+            Body::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Returns the body if it is already in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::File { .. } | Body::Stream(_) => None,
+        }
+    }
+
+    fn write_to<W: Write>(&mut self, framing: Framing, out: &mut W) -> io::Result<()> {
+        match self {
+            Body::Bytes(bytes) => out.write_all(bytes),
+            Body::File { file, len } => {
+                let copied = io::copy(&mut file.take(*len), out)?;
+                if copied < *len {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while being sent"));
+                }
+                Ok(())
+            }
+            Body::Stream(chunks) => {
+                for chunk in chunks {
+                    let chunk = chunk?;
+                    match framing {
+                        // An empty chunk would mark the end of the body.
+                        Framing::Chunked if chunk.is_empty() => {}
+                        Framing::Chunked => {
+                            write!(out, "{:x}\r\n", chunk.len())?;
+                            out.write_all(&chunk)?;
+                            out.write_all(b"\r\n")?;
+                        }
+                        _ => out.write_all(&chunk)?,
+                    }
+                }
+                if framing == Framing::Chunked {
+                    out.write_all(b"0\r\n\r\n")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::File { len, .. } => f.debug_struct("File").field("len", len).finish(),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+/// How the client is told where the body ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    None,
+    Length(u64),
+    Chunked,
+    /// The body ends when the connection closes. Only for HTTP/1.0 clients,
+    /// which don't understand chunked transfer-coding.
+    Close,
+}
+
+/// An HTTP response waiting to be written to a client.
+///
+/// Responses are built up with the `with_` methods:
+///
+/// ```
+/// use multi_threaded_web_server::{Response, Status};
+///
+/// let response = Response::new(Status::Created)
+///     .with_header("Location", "/users/42")
+///     .with_body("created\n");
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    pub status: Status,
+    pub headers: Headers,
+    pub body: Body,
+    /// What takes over the connection once the response is sent, for a
+    /// `101 Switching Protocols`.
+    pub(crate) upgrade: Option<Upgrade>,
+}
+
+impl Response {
+    pub fn new(status: impl Into<Status>) -> Response {
+        Response {
+            status: status.into(),
+            headers: Headers::new(),
+            body: Body::Bytes(Vec::new()),
+            upgrade: None,
+        }
+    }
+
+    /// A response with a `text/html` body.
+    pub fn html(status: impl Into<Status>, body: impl Into<Vec<u8>>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// A response with a `text/plain` body.
+    pub fn text(status: impl Into<Status>, body: impl Into<Vec<u8>>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    pub fn with_status(mut self, status: impl Into<Status>) -> Response {
+        self.status = status.into();
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// Uses the first `len` bytes of `file` as the body.
+    pub fn with_file(mut self, file: File, len: u64) -> Response {
+        self.body = Body::File { file, len };
+        self
+    }
+
+    /// Streams the body from `chunks`, sending each one as it is produced.
+    ///
+    /// If a chunk is an error, the response is cut off there and the
+    /// connection closed, so the client can tell it is incomplete.
+    pub fn with_stream<I>(mut self, chunks: I) -> Response
+        where
+            I: IntoIterator<Item = io::Result<Vec<u8>>>,
+            I::IntoIter: Send + 'static,
+    {
+        self.body = Body::Stream(Box::new(chunks.into_iter()));
+        self
+    }
+
+    /// Hands the connection to `upgrade` once the response is sent.
+    pub(crate) fn with_upgrade(mut self, upgrade: Upgrade) -> Response {
+        self.upgrade = Some(upgrade);
+        self
+    }
+
+    /// Writes the status line, headers and body for an HTTP/1.1 client.
+    /// The framing headers are set from the body, replacing any the handler
+    /// gave: `Content-Length` when its length is known, and
+    /// `Transfer-Encoding: chunked` for a stream.
+    pub fn write_to<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let framing = self.framing(Version::Http11);
+        self.write_head(framing, out)?;
+        self.write_body(framing, out)
+    }
+
+    /// Writes everything but the body, as needed to answer a `HEAD` request.
+    pub fn write_head_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_head(self.framing(Version::Http11), out)
+    }
+
+    /// Writes the response to `request`: just the head for a `HEAD`
+    /// request, and a streamed body without chunked transfer-coding for an
+    /// HTTP/1.0 client. In that case the body ends when the connection
+    /// closes, so it mustn't be kept open afterwards.
+    pub fn write_for<W: Write>(&mut self, request: &Request, out: &mut W) -> io::Result<()> {
+        let framing = self.framing(request.version);
+        self.write_head(framing, out)?;
+        if request.method == Method::Head {
+            return out.flush();
+        }
+        self.write_body(framing, out)
+    }
+
+    /// Turns the response into the bytes to send to a `version` client,
+    /// framing included, a piece at a time; just the head if `head_only`.
+    /// This is for writers that can't block, which take the next piece
+    /// whenever the socket has room for it.
+    ///
+    /// File and stream bodies are read as the pieces are taken, on whatever
+    /// thread takes them.
+    pub(crate) fn into_pieces(self, version: Version, head_only: bool) -> Pieces {
+        let framing = self.framing(version);
+        let mut head = Vec::new();
+        // Writing to a `Vec` can't fail.
+        let _ = self.write_head(framing, &mut head);
+
+        let body = if head_only || framing == Framing::None {
+            None
+        } else {
+            Some(self.body)
+        };
+
+        Pieces {
+            head: Some(head),
+            body,
+            framing,
+        }
+    }
+
+    fn framing(&self, version: Version) -> Framing {
+        if !self.status.has_body() {
+            return Framing::None;
+        }
+        match (self.body.len(), version) {
+            (Some(len), _) => Framing::Length(len),
+            (None, Version::Http11) => Framing::Chunked,
+            (None, Version::Http10) => Framing::Close,
+        }
+    }
+
+    fn write_head<W: Write>(&self, framing: Framing, out: &mut W) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Transfer-Encoding") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        match framing {
+            Framing::Length(len) => head.push_str(&format!("Content-Length: {}\r\n", len)),
+            Framing::Chunked => head.push_str("Transfer-Encoding: chunked\r\n"),
+            Framing::None | Framing::Close => {}
+        }
+        head.push_str("\r\n");
+
+        out.write_all(head.as_bytes())
+    }
+
+    fn write_body<W: Write>(&mut self, framing: Framing, out: &mut W) -> io::Result<()> {
+        if framing != Framing::None {
+            self.body.write_to(framing, out)?;
+        }
+        out.flush()
+    }
+}
+
+/// The bytes of a response, made by `Response::into_pieces`.
+pub(crate) struct Pieces {
+    head: Option<Vec<u8>>,
+    body: Option<Body>,
+    framing: Framing,
+}
+
+impl Iterator for Pieces {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if let Some(head) = self.head.take() {
+            return Some(Ok(head));
+        }
+
+        let piece = match self.body.as_mut()? {
+            Body::Bytes(bytes) => Some(Ok(mem::take(bytes))),
+            Body::File { len: 0, .. } => None,
+            Body::File { file, len } => {
+                let mut piece = vec![0; FILE_PIECE.min(*len as usize)];
+                match file.read(&mut piece) {
+                    Ok(0) => Some(Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while being sent"))),
+                    Ok(n) => {
+                        piece.truncate(n);
+                        *len -= n as u64;
+                        return Some(Ok(piece));
+                    }
+                    Err(e) => Some(Err(e)),
+                }
+            }
+            Body::Stream(chunks) => {
+                let chunked = self.framing == Framing::Chunked;
+                // An empty chunk would mark the end of the body.
+                match chunks.find(|chunk| !matches!(chunk, Ok(chunk) if chunk.is_empty())) {
+                    Some(Ok(chunk)) if chunked => {
+                        let mut piece = format!("{:x}\r\n", chunk.len()).into_bytes();
+                        piece.extend_from_slice(&chunk);
+                        piece.extend_from_slice(b"\r\n");
+                        return Some(Ok(piece));
+                    }
+                    Some(Ok(chunk)) => return Some(Ok(chunk)),
+                    Some(Err(e)) => Some(Err(e)),
+                    None if chunked => Some(Ok(b"0\r\n\r\n".to_vec())),
+                    None => None,
+                }
+            }
+        };
+
+        // Anything that didn't return above was the last piece.
+        self.body = None;
+        piece
+    }
+}
+
+/// Reads a response from a server, to a request that was a `HEAD` request
+/// if `head`. Interim 1xx responses are skipped, and bodies larger than
+/// `max_size` are an error. The headers are kept as they came, framing and
+/// all.
+pub(crate) fn read_response<R: BufRead>(mut reader: R, head: bool, max_size: usize) -> io::Result<Response> {
+    let (status, headers) = loop {
+        let status = read_status(&mut reader)?;
+        let headers = read_headers(&mut reader)?;
+        if !(100..200).contains(&status) {
+            break (Status::from(status), headers);
+        }
+    };
+
+    let body = if head || !status.has_body() {
+        Vec::new()
+    } else if headers.has_token("Transfer-Encoding", "chunked") {
+        read_chunked(&mut reader, max_size)?
+    } else if let Some(len) = headers.get("Content-Length") {
+        let len: usize = len.trim().parse().map_err(|_| invalid("bad Content-Length"))?;
+        if len > max_size {
+            return Err(invalid("response too large"));
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        body
+    } else {
+        let mut body = Vec::new();
+        reader.take(max_size as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > max_size {
+            return Err(invalid("response too large"));
+        }
+        body
+    };
+
+    let mut response = Response::new(status).with_body(body);
+    response.headers = headers;
+    Ok(response)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(if line.is_empty() {
+            io::ErrorKind::UnexpectedEof.into()
+        } else {
+            invalid("line too long")
+        });
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn read_status<R: BufRead>(reader: &mut R) -> io::Result<u16> {
+    let line = read_line(reader)?;
+    let mut parts = line.splitn(3, ' ');
+    match (parts.next(), parts.next().map(str::parse)) {
+        (Some(version), Some(Ok(code))) if version.starts_with("HTTP/1.") => Ok(code),
+        _ => Err(invalid("bad status line")),
+    }
+}
+
+fn read_headers<R: BufRead>(reader: &mut R) -> io::Result<Headers> {
+    let mut headers = Headers::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid("bad header line"))?;
+        headers.append(name.trim(), value.trim());
+    }
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R, max_size: usize) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        // Fifteen hex digits always fit in a `usize`, and `from_str_radix`
+        // would also take a leading sign.
+        if size.is_empty() || size.len() > 15 || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid("bad chunk size"));
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid("bad chunk size"))?;
+        if size == 0 {
+            // Trailers aren't passed on.
+            read_headers(reader)?;
+            return Ok(body);
+        }
+        if size > max_size - body.len() {
+            return Err(invalid("response too large"));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        if !read_line(reader)?.is_empty() {
+            return Err(invalid("bad chunk"));
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(mut response: Response, request: &Request) -> String {
+        let mut out = Vec::new();
+        response.write_for(request, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn chunks(parts: &[&str]) -> Vec<io::Result<Vec<u8>>> {
+        parts.iter().map(|part| Ok(part.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn bodies_get_a_content_length() {
+        let response = Response::text(Status::Ok, "hello").with_header("Content-Length", "99");
+        let output = written(response, &Request::new(Method::Get, "/"));
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 5\r\n\r\nhello",
+            output
+        );
+    }
+
+    #[test]
+    fn streams_are_chunked() {
+        let response = Response::new(200).with_stream(chunks(&["hello", "", " world"]));
+        let output = written(response, &Request::new(Method::Get, "/"));
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+            output
+        );
+    }
+
+    #[test]
+    fn streams_to_http_10_clients_end_at_close() {
+        let mut request = Request::new(Method::Get, "/");
+        request.version = Version::Http10;
+        let output = written(Response::new(200).with_stream(chunks(&["hello", " world"])), &request);
+
+        assert_eq!("HTTP/1.1 200 OK\r\n\r\nhello world", output);
+    }
+
+    #[test]
+    fn head_and_bodiless_responses_have_no_body() {
+        let head = written(Response::text(200, "hello"), &Request::new(Method::Head, "/"));
+        let not_modified = written(Response::text(304, "hello"), &Request::new(Method::Get, "/"));
+
+        assert!(head.ends_with("Content-Length: 5\r\n\r\n"));
+        assert!(not_modified.ends_with("text/plain; charset=utf-8\r\n\r\n"));
+    }
+
+    #[test]
+    fn pieces_match_what_is_written() {
+        let request = Request::new(Method::Get, "/");
+        let pieces = |response: Response| -> Vec<u8> {
+            response.into_pieces(request.version, false).flat_map(Result::unwrap).collect()
+        };
+
+        let bytes = Response::text(200, "hello");
+        let stream = Response::new(200).with_stream(chunks(&["hello", "", " world"]));
+
+        assert_eq!(written(Response::text(200, "hello"), &request).into_bytes(), pieces(bytes));
+        assert_eq!(
+            written(Response::new(200).with_stream(chunks(&["hello", "", " world"])), &request).into_bytes(),
+            pieces(stream)
+        );
+    }
+
+    #[test]
+    fn stream_errors_cut_the_body_short() {
+        let parts = vec![Ok(b"hello".to_vec()), Err(io::Error::other("gone"))];
+        let mut out = Vec::new();
+        let result = Response::new(200).with_stream(parts).write_to(&mut out);
+
+        assert!(result.is_err());
+        assert!(!out.ends_with(b"0\r\n\r\n"));
+    }
+
+    #[test]
+    fn chunk_sizes_are_checked() {
+        let read = |body: &str, max_size| {
+            let raw = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{}", body);
+            read_response(raw.as_bytes(), false, max_size)
+        };
+
+        assert_eq!(Some(&b"hello"[..]), read("5\r\nhello\r\n0\r\n\r\n", 5).unwrap().body.as_bytes());
+        assert!(read("5\r\nhello\r\n0\r\n\r\n", 4).is_err());
+        assert!(read("1\r\na\r\nffffffffffffffff\r\n", usize::MAX).is_err());
+        assert!(read("+5\r\nhello\r\n0\r\n\r\n", usize::MAX).is_err());
+    }
+}
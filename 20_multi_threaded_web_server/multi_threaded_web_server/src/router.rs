@@ -0,0 +1,315 @@
+use crate::middleware::{Middleware, Next};
+use crate::request::{percent_decode, Method, Request};
+use crate::response::Response;
+
+/// Something that can answer a request matched by a `Router`.
+///
+/// Closures taking the request and the parameters extracted from its path
+/// implement this automatically.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: &Request, params: &Params) -> Response;
+}
+
+impl<F> Handler for F
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static
+{
+    fn handle(&self, request: &Request, params: &Params) -> Response {
+        self(request, params)
+    }
+}
+
+/// Values captured by `:name` and `*name` segments of a route pattern.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    entries: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    fn push(&mut self, name: &str, value: String) {
+        self.entries.push((name.to_string(), value));
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+/// Dispatches requests to handlers by method and path pattern.
+///
+/// Patterns are made of `/`-separated segments. A segment is either literal
+/// text, `:name` to capture one segment, or `*name` as the last segment to
+/// capture the rest of the path (possibly empty). Routes are tried in the
+/// order they were added and the first match wins.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Box<dyn Handler>,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            fallback: Box::new(|_: &Request, _: &Params| Response::text(404, "Not Found\n")),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Adds a route for `method` requests whose path matches `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// The `route` function will panic if the pattern doesn't start with `/`
+    /// or has a wildcard segment anywhere but at the end.
+    pub fn route<H: Handler>(mut self, method: Method, pattern: &str, handler: H) -> Router {
+        self.routes.push(Route {
+            method,
+            segments: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Sets the handler for requests that match no route. It defaults to a
+    /// plain-text 404 response.
+    pub fn fallback<H: Handler>(mut self, handler: H) -> Router {
+        self.fallback = Box::new(handler);
+        self
+    }
+
+    /// Runs `middleware` around every request, including ones that match no
+    /// route. The middleware added first is the outermost: it sees the
+    /// request first and the response last.
+    pub fn wrap<M: Middleware>(mut self, middleware: M) -> Router {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Passes the request through the middleware, then runs the handler of
+    /// the first matching route.
+    ///
+    /// `HEAD` requests are sent to `GET` routes unless a `HEAD` route
+    /// matches first. If the path matches but the method doesn't, the
+    /// response is 405 with an `Allow` header listing the methods that would
+    /// have matched.
+    pub fn handle(&self, request: &Request) -> Response {
+        Next::new(&self.middleware, &|request: &Request| self.dispatch(request)).run(request)
+    }
+
+    fn dispatch(&self, request: &Request) -> Response {
+        let mut allowed: Vec<&Method> = Vec::new();
+
+        for route in &self.routes {
+            let params = match match_path(&route.segments, &request.path) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            let method_matches = route.method == request.method
+                || (request.method == Method::Head && route.method == Method::Get);
+
+            if method_matches {
+                return route.handler.handle(request, &params);
+            }
+
+            if !allowed.contains(&&route.method) {
+                allowed.push(&route.method);
+            }
+        }
+
+        if allowed.is_empty() {
+            return self.fallback.handle(request, &Params::default());
+        }
+
+        if allowed.contains(&&Method::Get) && !allowed.contains(&&Method::Head) {
+            allowed.push(&Method::Head);
+        }
+        let allow: Vec<&str> = allowed.iter().map(|m| m.as_str()).collect();
+
+        Response::text(405, "Method Not Allowed\n").with_header("Allow", allow.join(", "))
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    assert!(pattern.starts_with('/'), "route pattern must start with '/': {}", pattern);
+
+    let parts: Vec<&str> = pattern[1..].split('/').collect();
+    let last = parts.len() - 1;
+
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if let Some(name) = part.strip_prefix('*') {
+                assert!(i == last, "wildcard must be the last segment: {}", pattern);
+                Segment::Wildcard(name.to_string())
+            } else if let Some(name) = part.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else {
+                Segment::Literal(part.to_string())
+            }
+        })
+        .collect()
+}
+
+fn match_path(segments: &[Segment], path: &str) -> Option<Params> {
+    let mut rest = path.strip_prefix('/')?;
+    let mut params = Params::default();
+
+    for (i, segment) in segments.iter().enumerate() {
+        if let Segment::Wildcard(name) = segment {
+            params.push(name, percent_decode(rest, false)?);
+            return Some(params);
+        }
+
+        let (part, remainder) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], Some(&rest[slash + 1..])),
+            None => (rest, None),
+        };
+
+        match segment {
+            // Compared decoded, like the parameters, so `/a%20b` matches a
+            // route for `/a b`.
+            Segment::Literal(text) if percent_decode(part, false).as_deref() == Some(text.as_str()) => {}
+            Segment::Param(name) if !part.is_empty() => {
+                params.push(name, percent_decode(part, false)?);
+            }
+            _ => return None,
+        }
+
+        match remainder {
+            Some(remainder) => rest = remainder,
+            // The path ran out; it matches only if the pattern did too.
+            None if i == segments.len() - 1 => return Some(params),
+            None => return match segments.get(i + 1) {
+                Some(Segment::Wildcard(name)) if i + 2 == segments.len() => {
+                    params.push(name, String::new());
+                    Some(params)
+                }
+                _ => None,
+            },
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::Status;
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(response.body.as_bytes().unwrap()).unwrap()
+    }
+
+    fn echo_params(_: &Request, params: &Params) -> Response {
+        let pairs: Vec<String> = params.iter().map(|(n, v)| format!("{}={}", n, v)).collect();
+        Response::text(200, pairs.join("&"))
+    }
+
+    #[test]
+    fn literal_and_param_segments() {
+        let router = Router::new()
+            .get("/", |_: &Request, _: &Params| Response::text(200, "home"))
+            .get("/users/:id", echo_params)
+            .get("/users/:id/posts/:post", echo_params);
+
+        assert_eq!("home", body(&router.handle(&Request::new(Method::Get, "/"))));
+        assert_eq!("id=42", body(&router.handle(&Request::new(Method::Get, "/users/42"))));
+        assert_eq!(
+            "id=a b&post=7",
+            body(&router.handle(&Request::new(Method::Get, "/users/a%20b/posts/7?x=1")))
+        );
+        assert_eq!(Status::NotFound, router.handle(&Request::new(Method::Get, "/users")).status);
+        assert_eq!(Status::NotFound, router.handle(&Request::new(Method::Get, "/users/42/extra")).status);
+    }
+
+    #[test]
+    fn literal_segments_match_encoded_paths() {
+        let router = Router::new().get("/a b/c", |_: &Request, _: &Params| Response::text(200, "found"));
+
+        assert_eq!("found", body(&router.handle(&Request::new(Method::Get, "/a%20b/c"))));
+        assert_eq!(Status::NotFound, router.handle(&Request::new(Method::Get, "/a%2/c")).status);
+    }
+
+    #[test]
+    fn wildcard_captures_the_tail() {
+        let router = Router::new().get("/static/*path", echo_params);
+
+        assert_eq!(
+            "path=css/site.css",
+            body(&router.handle(&Request::new(Method::Get, "/static/css/site.css")))
+        );
+        assert_eq!("path=", body(&router.handle(&Request::new(Method::Get, "/static"))));
+        assert_eq!("path=", body(&router.handle(&Request::new(Method::Get, "/static/"))));
+    }
+
+    #[test]
+    fn wrong_method_is_405_with_allow() {
+        let router = Router::new()
+            .get("/items", echo_params)
+            .post("/items", echo_params);
+
+        let response = router.handle(&Request::new(Method::Delete, "/items"));
+
+        assert_eq!(Status::MethodNotAllowed, response.status);
+        assert_eq!(Some("GET, POST, HEAD"), response.headers.get("Allow"));
+    }
+
+    #[test]
+    fn head_uses_get_routes() {
+        let router = Router::new().get("/", echo_params);
+
+        assert_eq!(Status::Ok, router.handle(&Request::new(Method::Head, "/")).status);
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard must be the last segment")]
+    fn wildcard_in_the_middle_panics() {
+        Router::new().get("/*path/edit", echo_params);
+    }
+}
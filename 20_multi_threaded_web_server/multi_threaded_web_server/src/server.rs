@@ -0,0 +1,746 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::connection::{serve_connection, ConnectionConfig, ConnectionState};
+use crate::event_loop::{self, Context};
+use crate::executor::Executor;
+use crate::log::{log, LogLevel};
+use crate::response::Response;
+use crate::router::Router;
+use crate::pool::{PoolConfig, PoolCreationError, ThreadPool};
+use crate::tls::{TlsConfig, TlsStream};
+
+const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+/// What a client turned away because the server is busy is told to wait,
+/// in seconds.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// An HTTP server that runs its handlers on a `ThreadPool`, or another
+/// `Executor` given to `with_executor`.
+///
+/// By default each connection is handed to a worker for as long as it stays
+/// open. `with_event_loop` switches to waiting for requests on a few event
+/// loop threads instead, so that workers only run handlers.
+///
+/// `run` accepts connections until shutdown is requested through a
+/// `ShutdownHandle`. It then stops accepting, closes connections that are
+/// waiting for a request, and gives the others until the drain timeout to
+/// finish their current response before they are closed too.
+///
+/// The router, connection limits, TLS certificate and number of workers can
+/// be changed while it runs through a `ReloadHandle`.
+pub struct Server {
+    listener: TcpListener,
+    /// `None` to give each connection a worker.
+    event_loop_threads: Option<usize>,
+    /// `None` to run on a `ThreadPool` built from the pool configuration.
+    executor: Option<Arc<dyn Executor>>,
+    drain_timeout: Duration,
+    shutdown: ShutdownHandle,
+    reload: ReloadHandle,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, router: Router) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = ShutdownHandle {
+            inner: Arc::new(ShutdownInner {
+                requested: AtomicBool::new(false),
+                wake_addr: wake_addr(listener.local_addr()?),
+            }),
+        };
+
+        let settings = Settings {
+            router: Arc::new(router),
+            connection_config: ConnectionConfig::default(),
+            tls: None,
+        };
+        let workers = Workers {
+            config: PoolConfig {
+                queue_capacity: Some(DEFAULT_QUEUE_CAPACITY),
+                ..PoolConfig::fixed(4)
+            },
+            pool: None,
+        };
+        let reload = ReloadHandle {
+            inner: Arc::new(ReloadInner {
+                settings: Mutex::new(Arc::new(settings)),
+                workers: Mutex::new(workers),
+            }),
+        };
+
+        Ok(Server {
+            listener,
+            event_loop_threads: None,
+            executor: None,
+            drain_timeout: Duration::from_secs(30),
+            shutdown,
+            reload,
+        })
+    }
+
+    /// Sets a fixed number of worker threads. The default is 4.
+    pub fn with_workers(self, workers: usize) -> Server {
+        {
+            let mut pool = self.reload.workers();
+            pool.config.min_workers = workers;
+            pool.config.max_workers = workers;
+        }
+        self
+    }
+
+    /// Sets up the worker pool from `config`. By default there are 4
+    /// workers and up to 256 connections can wait for one; connections
+    /// beyond that are answered with 503 Service Unavailable.
+    pub fn with_pool_config(self, config: PoolConfig) -> Server {
+        self.reload.workers().config = config;
+        self
+    }
+
+    /// Runs connections, or with an event loop handlers, on `executor`
+    /// rather than on the server's own `ThreadPool`. The pool settings then
+    /// have no effect.
+    ///
+    /// With `executor::Inline` connections are served one at a time on the
+    /// thread calling `run`, so shutdown waits for the one being served to
+    /// close.
+    pub fn with_executor<E: Executor + 'static>(mut self, executor: E) -> Server {
+        self.executor = Some(Arc::new(executor));
+        self
+    }
+
+    /// Reads requests and writes responses on `threads` event loops, which
+    /// each watch many connections at once, and only hands the pool the
+    /// handlers to run. Idle keep-alive connections and slow clients then
+    /// don't hold on to workers, so a few threads can keep thousands of
+    /// connections open.
+    ///
+    /// Streamed response bodies are read on the event loop, so their
+    /// iterators shouldn't block for long.
+    pub fn with_event_loop(mut self, threads: usize) -> Server {
+        self.event_loop_threads = Some(threads);
+        self
+    }
+
+    pub fn with_connection_config(self, config: ConnectionConfig) -> Server {
+        self.reload.set_connection_config(config);
+        self
+    }
+
+    /// Serves HTTPS instead of plain HTTP. Use `tls::https_redirect` on a
+    /// second server to send clients of the plain port over.
+    ///
+    /// Connections turned away because the pool's queue is full are closed
+    /// without the usual 503, since answering would mean a TLS handshake
+    /// on the thread accepting connections.
+    pub fn with_tls(self, config: TlsConfig) -> Server {
+        self.reload.update(|settings| settings.tls = Some(config));
+        self
+    }
+
+    /// Sets how long in-flight requests get to finish once shutdown starts.
+    /// The default is 30 seconds.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Server {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn reload_handle(&self) -> ReloadHandle {
+        self.reload.clone()
+    }
+
+    /// Serves connections until shutdown is requested and the pool has
+    /// finished its last job.
+    pub fn run(self) -> io::Result<()> {
+        let executor = match &self.executor {
+            Some(executor) => Arc::clone(executor),
+            None => {
+                let mut workers = self.reload.workers();
+                let pool = ThreadPool::with_config(workers.config.clone()).map_err(io::Error::other)?;
+                let pool = Arc::new(pool);
+                workers.pool = Some(Arc::clone(&pool));
+                pool
+            }
+        };
+
+        let result = match self.event_loop_threads {
+            Some(threads) => {
+                let context = Context {
+                    settings: &self.reload,
+                    executor: &*executor,
+                    shutdown: &self.shutdown,
+                    drain_timeout: self.drain_timeout,
+                };
+                event_loop::run(&self.listener, threads, &context)
+            }
+            None => Ok(self.run_per_connection(&*executor)),
+        };
+        if let Ok(false) = result {
+            log(LogLevel::Warn, format_args!("Drain timeout reached; closing remaining connections."));
+        }
+
+        // Take the pool back from the reload handle, so that dropping it
+        // waits for the workers to finish.
+        self.reload.workers().pool = None;
+        drop(executor);
+        result.map(|_| ())
+    }
+
+    /// Hands each connection to the executor. Returns false if connections
+    /// were still open at the drain deadline.
+    fn run_per_connection(&self, executor: &dyn Executor) -> bool {
+        let tracker = Arc::new(Tracker::default());
+
+        for stream in self.listener.incoming() {
+            if self.shutdown.is_requested() {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log(LogLevel::Warn, format_args!("Error accepting connection: {}", e));
+                    continue;
+                }
+            };
+
+            let registration = match tracker.register(&stream) {
+                Ok(registration) => registration,
+                Err(e) => {
+                    log(LogLevel::Warn, format_args!("Error accepting connection: {}", e));
+                    continue;
+                }
+            };
+            let connection = QueuedConnection {
+                stream,
+                settings: self.reload.current(),
+                registration,
+                started: false,
+            };
+
+            let result = executor.execute(Box::new(move || connection.serve()));
+
+            if let Err(e) = result {
+                log(LogLevel::Warn, format_args!("Dropping connection: {}", e));
+            }
+        }
+
+        log(LogLevel::Info, format_args!("Shutting down."));
+
+        let deadline = Instant::now() + self.drain_timeout;
+        tracker.start_draining();
+        if !tracker.wait_until_closed(deadline) {
+            tracker.close_all();
+            return false;
+        }
+
+        true
+    }
+}
+
+/// A connection waiting in the pool's queue. If the pool throws the job
+/// away because the queue is full, dropping it tells the client to come
+/// back later.
+struct QueuedConnection {
+    stream: TcpStream,
+    settings: Arc<Settings>,
+    registration: Registration,
+    started: bool,
+}
+
+impl QueuedConnection {
+    fn serve(mut self) {
+        self.started = true;
+        let state = &self.registration.state;
+        let router = &self.settings.router;
+        let config = &self.settings.connection_config;
+
+        match &self.settings.tls {
+            Some(tls) => match tls.accept() {
+                Ok(connection) => serve_connection(TlsStream::new(connection, &self.stream), router, config, state),
+                Err(e) => log(LogLevel::Warn, format_args!("Error on connection: {}", e)),
+            },
+            None => serve_connection(&self.stream, router, config, state),
+        }
+    }
+}
+
+impl Drop for QueuedConnection {
+    fn drop(&mut self) {
+        if self.started || self.settings.tls.is_some() {
+            return;
+        }
+
+        // This runs on the accept loop's thread, so don't let a slow client
+        // hold it up.
+        let _ = self.stream.set_write_timeout(Some(Duration::from_secs(1)));
+        let mut response = busy_response();
+
+        if response.write_to(&mut &self.stream).is_ok() {
+            let _ = self.stream.shutdown(Shutdown::Write);
+            discard_unread(&self.stream);
+        }
+    }
+}
+
+/// What connections are served with. Each connection keeps the settings
+/// that were current when it was accepted until it closes.
+#[derive(Clone)]
+pub(crate) struct Settings {
+    pub router: Arc<Router>,
+    pub connection_config: ConnectionConfig,
+    pub tls: Option<TlsConfig>,
+}
+
+/// Changes how a `Server` serves new connections, before or while it runs,
+/// without disturbing the ones already open. Handles can be cloned and
+/// sent to other threads.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    inner: Arc<ReloadInner>,
+}
+
+struct ReloadInner {
+    settings: Mutex<Arc<Settings>>,
+    workers: Mutex<Workers>,
+}
+
+/// The pool's configuration, and the pool itself while the server runs.
+struct Workers {
+    config: PoolConfig,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl ReloadHandle {
+    pub fn set_router(&self, router: Router) {
+        self.update(|settings| settings.router = Arc::new(router));
+    }
+
+    pub fn set_connection_config(&self, config: ConnectionConfig) {
+        self.update(|settings| settings.connection_config = config);
+    }
+
+    /// Swaps the certificate, say after it has been renewed, on a server
+    /// that already serves HTTPS. A plain HTTP server stays one: clients and
+    /// redirects are set up for what a port speaks, so that takes a restart
+    /// with `Server::with_tls`.
+    pub fn set_tls(&self, config: TlsConfig) {
+        self.update(|settings| {
+            if settings.tls.is_some() {
+                settings.tls = Some(config);
+            }
+        });
+    }
+
+    /// Changes the pool's minimum and maximum number of workers; see
+    /// `ThreadPool::set_bounds`.
+    pub fn set_workers(&self, min_workers: usize, max_workers: usize) -> Result<(), PoolCreationError> {
+        let mut workers = self.workers();
+        let config = worker_bounds(&workers.config, min_workers, max_workers)?;
+
+        if let Some(pool) = &workers.pool {
+            pool.set_bounds(min_workers, max_workers)?;
+        }
+        workers.config = config;
+
+        Ok(())
+    }
+
+    /// Whether `set_workers` would take these bounds, without changing
+    /// anything. It can then fail only if a new worker can't be started.
+    pub fn check_workers(&self, min_workers: usize, max_workers: usize) -> Result<(), PoolCreationError> {
+        worker_bounds(&self.workers().config, min_workers, max_workers).map(drop)
+    }
+
+    pub(crate) fn current(&self) -> Arc<Settings> {
+        Arc::clone(&self.inner.settings.lock().unwrap())
+    }
+
+    fn update<F: FnOnce(&mut Settings)>(&self, f: F) {
+        let mut current = self.inner.settings.lock().unwrap();
+        let mut settings = Settings::clone(&current);
+        f(&mut settings);
+        *current = Arc::new(settings);
+    }
+
+    fn workers(&self) -> MutexGuard<'_, Workers> {
+        self.inner.workers.lock().unwrap()
+    }
+}
+
+fn worker_bounds(config: &PoolConfig, min_workers: usize, max_workers: usize) -> Result<PoolConfig, PoolCreationError> {
+    let config = PoolConfig {
+        min_workers,
+        max_workers,
+        ..config.clone()
+    };
+    config.validate()?;
+    Ok(config)
+}
+
+/// What a client turned away because the pool's queue is full is told.
+pub(crate) fn busy_response() -> Response {
+    Response::text(503, "The server is too busy to answer; try again shortly.\n")
+        .with_header("Retry-After", RETRY_AFTER_SECS.to_string())
+        .with_header("Connection", "close")
+}
+
+/// Reads whatever the client has already sent, without waiting for more.
+/// Closing a socket with unread data makes it send a reset, which can
+/// arrive before the client has read the response.
+fn discard_unread(mut stream: &TcpStream) {
+    if stream.set_nonblocking(true).is_err() {
+        return;
+    }
+
+    let mut buffer = [0; 4096];
+    while let Ok(n) = stream.read(&mut buffer) {
+        if n == 0 {
+            break;
+        }
+    }
+}
+
+/// Asks a running `Server` to shut down. Handles can be cloned and sent to
+/// other threads.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownInner>,
+}
+
+struct ShutdownInner {
+    requested: AtomicBool,
+    wake_addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    /// Starts a graceful shutdown. Calling this more than once is harmless.
+    pub fn shutdown(&self) {
+        if !self.inner.requested.swap(true, Ordering::SeqCst) {
+            // The accept loop is blocked in `accept`, so connect to it to
+            // make it notice the flag.
+            let _ = TcpStream::connect_timeout(&self.inner.wake_addr, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.inner.requested.load(Ordering::SeqCst)
+    }
+
+    /// Starts a graceful shutdown when the process receives SIGINT or
+    /// SIGTERM.
+    #[cfg(unix)]
+    pub fn shutdown_on_signals(&self) -> io::Result<()> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        use signal_hook::iterator::Signals;
+        use std::thread;
+
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let handle = self.clone();
+
+        thread::Builder::new()
+            .name("shutdown-signals".to_string())
+            .spawn(move || {
+                if let Some(signal) = signals.forever().next() {
+                    log(LogLevel::Info, format_args!("Received signal {}; shutting down.", signal));
+                    handle.shutdown();
+                }
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Keeps track of open connections so that they can be drained at shutdown.
+#[derive(Default)]
+struct Tracker {
+    open: Mutex<Connections>,
+    closed: Condvar,
+}
+
+#[derive(Default)]
+struct Connections {
+    next_id: u64,
+    streams: HashMap<u64, (TcpStream, Arc<ConnectionState>)>,
+}
+
+/// Removes its connection from the tracker when the job serving it ends,
+/// however it ends.
+struct Registration {
+    id: u64,
+    tracker: Arc<Tracker>,
+    state: Arc<ConnectionState>,
+}
+
+impl Tracker {
+    fn register(self: &Arc<Self>, stream: &TcpStream) -> io::Result<Registration> {
+        let clone = stream.try_clone()?;
+        let state = Arc::new(ConnectionState::default());
+
+        let mut open = self.open.lock().unwrap();
+        let id = open.next_id;
+        open.next_id += 1;
+        open.streams.insert(id, (clone, Arc::clone(&state)));
+
+        Ok(Registration {
+            id,
+            tracker: Arc::clone(self),
+            state,
+        })
+    }
+
+    /// Tells every connection to close after its current response, and
+    /// closes the ones that are between requests right away.
+    fn start_draining(&self) {
+        let open = self.open.lock().unwrap();
+
+        for (stream, state) in open.streams.values() {
+            state.draining.store(true, Ordering::SeqCst);
+            if state.idle.load(Ordering::SeqCst) {
+                let _ = stream.shutdown(Shutdown::Read);
+            }
+        }
+    }
+
+    /// Returns false if connections were still open at the deadline.
+    fn wait_until_closed(&self, deadline: Instant) -> bool {
+        let mut open = self.open.lock().unwrap();
+
+        while !open.streams.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            open = self.closed.wait_timeout(open, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+
+    fn close_all(&self) {
+        let open = self.open.lock().unwrap();
+
+        for (stream, _) in open.streams.values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut open = self.tracker.open.lock().unwrap();
+        open.streams.remove(&self.id);
+        self.tracker.closed.notify_all();
+    }
+}
+
+/// The address to connect to in order to wake up a listener bound to
+/// `addr`; a wildcard address is reached through loopback.
+fn wake_addr(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), addr.port()),
+        IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(Ipv6Addr::LOCALHOST.into(), addr.port()),
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::router::Params;
+    use std::io::{Read, Write};
+    use std::thread;
+
+    fn start(router: Router) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<io::Result<()>>) {
+        let server = Server::bind("127.0.0.1:0", router)
+            .unwrap()
+            .with_workers(2)
+            .with_drain_timeout(Duration::from_secs(5));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+
+        (addr, handle, thread::spawn(move || server.run()))
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path).unwrap();
+        stream
+    }
+
+    fn read_response(stream: &mut TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn in_flight_requests_finish_before_run_returns() {
+        let router = Router::new().get("/slow", |_: &Request, _: &Params| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(200, "done")
+        });
+        let (addr, handle, server) = start(router);
+
+        let mut slow = get(addr, "/slow");
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        let response = read_response(&mut slow);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close"));
+        assert!(response.ends_with("done"));
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn queued_connections_are_answered_at_shutdown() {
+        let router = Router::new()
+            .get("/", |_: &Request, _: &Params| Response::text(200, "hi"))
+            .get("/slow", |_: &Request, _: &Params| {
+                thread::sleep(Duration::from_millis(300));
+                Response::text(200, "done")
+            });
+        let (addr, handle, server) = start(router);
+
+        let _busy = [get(addr, "/slow"), get(addr, "/slow")];
+        thread::sleep(Duration::from_millis(100));
+        // Waits for a worker, and only gets one after shutdown has started.
+        let mut queued = get(addr, "/");
+        thread::sleep(Duration::from_millis(50));
+        handle.shutdown();
+
+        let response = read_response(&mut queued);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close"));
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn busy_server_answers_503() {
+        let router = Router::new().get("/slow", |_: &Request, _: &Params| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(200, "done")
+        });
+        let server = Server::bind("127.0.0.1:0", router)
+            .unwrap()
+            .with_pool_config(PoolConfig {
+                queue_capacity: Some(1),
+                ..PoolConfig::fixed(1)
+            });
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run());
+
+        let connect = || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+            stream
+        };
+
+        let mut slow = connect();
+        thread::sleep(Duration::from_millis(100));
+        // Waits in the queue behind the slow request.
+        let mut queued = connect();
+        thread::sleep(Duration::from_millis(50));
+
+        let turned_away = read_response(&mut connect());
+        assert!(turned_away.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(turned_away.contains("Retry-After: 1\r\n"));
+
+        assert!(read_response(&mut slow).ends_with("done"));
+        assert!(read_response(&mut queued).ends_with("done"));
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn idle_keep_alive_connections_are_closed() {
+        let router = Router::new().get("/", |_: &Request, _: &Params| Response::text(200, "hi"));
+        let (addr, handle, server) = start(router);
+
+        let mut idle = get(addr, "/");
+        let mut buffer = [0; 1024];
+        let n = idle.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..n]).contains("keep-alive"));
+
+        let started = Instant::now();
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(0, idle.read(&mut buffer).unwrap());
+    }
+
+    #[test]
+    fn reloading_leaves_open_connections_alone() {
+        let version = |v: &'static str| Router::new().get("/", move |_: &Request, _: &Params| Response::text(200, v));
+        let server = Server::bind("127.0.0.1:0", version("v1")).unwrap().with_workers(2);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let reload = server.reload_handle();
+        let server = thread::spawn(move || server.run());
+
+        let mut old = get(addr, "/");
+        let mut buffer = [0; 1024];
+        let n = old.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..n]).ends_with("v1"));
+
+        reload.set_router(version("v2"));
+        assert!(reload.check_workers(3, 1).is_err());
+        reload.check_workers(2, 4).unwrap();
+        reload.set_workers(2, 4).unwrap();
+        assert!(reload.set_workers(3, 1).is_err());
+
+        let mut new = TcpStream::connect(addr).unwrap();
+        new.write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+        assert!(read_response(&mut new).ends_with("v2"));
+        old.write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+        assert!(read_response(&mut old).ends_with("v1"));
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn serves_on_any_executor() {
+        use crate::executor::{Inline, ThreadPerConnection};
+        use crate::testing::TestServer;
+
+        fn serve<E: Executor + 'static>(executor: E, event_loop: bool) {
+            let router = Router::new()
+                .get("/", |_: &Request, _: &Params| Response::text(200, "hello"))
+                .get("/panic", |_: &Request, _: &Params| -> Response { panic!("oops") });
+            let mut server = Server::bind("127.0.0.1:0", router).unwrap().with_executor(executor);
+            if event_loop {
+                server = server.with_event_loop(1);
+            }
+            let server = TestServer::run(server);
+
+            server.get("/").assert_status(200).assert_body("hello");
+            let _ = server.connect().write_all(b"GET /panic HTTP/1.1\r\nHost: test\r\n\r\n");
+            server.get("/").assert_status(200).assert_body("hello");
+            server.shutdown().unwrap();
+        }
+
+        for &event_loop in &[false, true] {
+            serve(Inline, event_loop);
+            serve(ThreadPerConnection, event_loop);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::date::{format_http_date, parse_http_date, whole_seconds};
+use crate::request::{percent_decode, Method, Request};
+use crate::response::Response;
+use crate::router::{Handler, Params};
+
+/// Serves files from beneath a document root.
+///
+/// Mounted on a pattern ending in `*path`, the captured tail is the file to
+/// serve; used anywhere else (such as a router's fallback) the whole request
+/// path is. Directories are served through their `index.html`.
+pub struct StaticFiles {
+    root: PathBuf,
+    not_found_page: Option<PathBuf>,
+}
+
+impl StaticFiles {
+    /// Creates a handler for the files under `root`, which must be an
+    /// existing directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<StaticFiles> {
+        let root = fs::canonicalize(root)?;
+
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", root.display()),
+            ));
+        }
+
+        Ok(StaticFiles {
+            root,
+            not_found_page: None,
+        })
+    }
+
+    /// Uses the file at `path`, relative to the document root, as the body
+    /// of 404 responses.
+    pub fn not_found_page(mut self, path: &str) -> StaticFiles {
+        self.not_found_page = Some(self.root.join(path));
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Serves `path`, a decoded path relative to the document root.
+    ///
+    /// Paths with a `..` segment are refused with 403 rather than resolved,
+    /// as are symbolic links that lead outside the document root.
+    pub fn serve(&self, request: &Request, path: &str) -> Response {
+        let mut file_path = self.root.clone();
+
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return Response::text(403, "Forbidden\n"),
+                s if s.contains('\\') || s.contains('\0') => {
+                    return Response::text(403, "Forbidden\n");
+                }
+                s => file_path.push(s),
+            }
+        }
+
+        if file_path.is_dir() {
+            file_path.push("index.html");
+        }
+        // Resolved only once the path names a file, so that a symlink can't
+        // lead out of the root, not even one in place of an index.html.
+        let file_path = match fs::canonicalize(&file_path) {
+            Ok(resolved) => resolved,
+            Err(_) => return self.not_found(),
+        };
+        if !file_path.starts_with(&self.root) {
+            return Response::text(403, "Forbidden\n");
+        }
+
+        match open(&file_path) {
+            Ok((file, metadata)) => conditional_response(request, &file_path, file, metadata),
+            Err(_) => self.not_found(),
+        }
+    }
+
+    fn not_found(&self) -> Response {
+        let page = self.not_found_page.as_ref().and_then(|path| {
+            let (file, metadata) = open(path).ok()?;
+            Some(
+                Response::new(404)
+                    .with_header("Content-Type", content_type(path))
+                    .with_file(file, metadata.len()),
+            )
+        });
+
+        page.unwrap_or_else(|| Response::text(404, "Not Found\n"))
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &Request, params: &Params) -> Response {
+        if request.method != Method::Get && request.method != Method::Head {
+            return Response::text(405, "Method Not Allowed\n").with_header("Allow", "GET, HEAD");
+        }
+
+        match params.get("path") {
+            Some(path) => self.serve(request, path),
+            None => match percent_decode(&request.path, false) {
+                Some(path) => self.serve(request, &path),
+                None => Response::text(400, "Bad Request\n"),
+            },
+        }
+    }
+}
+
+fn open(path: &Path) -> io::Result<(File, fs::Metadata)> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+
+    if !metadata.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not a regular file"));
+    }
+    Ok((file, metadata))
+}
+
+/// Builds the response for an opened file, answering 304 when the client's
+/// `If-None-Match` or `If-Modified-Since` shows that its copy is current.
+fn conditional_response(request: &Request, path: &Path, file: File, metadata: fs::Metadata) -> Response {
+    let modified = metadata.modified().ok().map(whole_seconds);
+    let etag = entity_tag(&metadata);
+
+    let not_modified = match request.header("If-None-Match") {
+        // If-None-Match takes precedence over If-Modified-Since when both
+        // are present (RFC 7232 section 6).
+        Some(tags) => tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag),
+        None => match (request.header("If-Modified-Since").and_then(parse_http_date), modified) {
+            (Some(since), Some(modified)) => modified <= since,
+            _ => false,
+        },
+    };
+
+    let mut response = if not_modified {
+        Response::new(304)
+    } else {
+        Response::new(200)
+            .with_header("Content-Type", content_type(path))
+            .with_file(file, metadata.len())
+    };
+
+    response.headers.insert("ETag", etag);
+    if let Some(modified) = modified {
+        response.headers.insert("Last-Modified", format_http_date(modified));
+    }
+
+    response
+}
+
+fn entity_tag(metadata: &fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    format!("\"{:x}-{:x}-{:x}\"", metadata.len(), modified.as_secs(), modified.subsec_nanos())
+}
+
+/// Picks a `Content-Type` from the file extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "avif" => "image/avif",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::Status;
+    use std::env;
+    use std::process;
+
+    /// A scratch document root that is removed when dropped.
+    struct TempRoot(PathBuf);
+
+    impl TempRoot {
+        fn new(name: &str) -> TempRoot {
+            let dir = env::temp_dir().join(format!("static-files-{}-{}", name, process::id()));
+            fs::create_dir_all(dir.join("css")).unwrap();
+            fs::write(dir.join("index.html"), "<h1>home</h1>").unwrap();
+            fs::write(dir.join("css/site.css"), "body {}").unwrap();
+            fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G', 0, 0xff]).unwrap();
+            TempRoot(dir)
+        }
+    }
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn get(path: &str) -> Request {
+        Request::new(Method::Get, path)
+    }
+
+    #[test]
+    fn serves_files_with_content_type() {
+        let root = TempRoot::new("types");
+        let files = StaticFiles::new(&root.0).unwrap();
+
+        let css = files.serve(&get("/css/site.css"), "css/site.css");
+        let png = files.serve(&get("/logo.png"), "logo.png");
+        let index = files.serve(&get("/"), "");
+
+        assert_eq!(Status::Ok, css.status);
+        assert_eq!(Some("text/css; charset=utf-8"), css.headers.get("Content-Type"));
+        assert_eq!(Some("image/png"), png.headers.get("Content-Type"));
+        assert_eq!(Some(6), png.body.len());
+        assert_eq!(Some("text/html; charset=utf-8"), index.headers.get("Content-Type"));
+    }
+
+    #[test]
+    fn refuses_to_leave_the_root() {
+        let root = TempRoot::new("traversal");
+        let files = StaticFiles::new(root.0.join("css")).unwrap();
+
+        assert_eq!(Status::Forbidden, files.serve(&get("/"), "../index.html").status);
+        assert_eq!(Status::Forbidden, files.serve(&get("/"), "a/../../index.html").status);
+        assert_eq!(Status::NotFound, files.serve(&get("/"), "missing.css").status);
+
+        #[cfg(unix)]
+        {
+            fs::create_dir(root.0.join("css/linked")).unwrap();
+            std::os::unix::fs::symlink(root.0.join("index.html"), root.0.join("css/linked/index.html")).unwrap();
+            assert_eq!(Status::Forbidden, files.serve(&get("/"), "linked").status);
+        }
+    }
+
+    #[test]
+    fn revalidation_returns_304() {
+        let root = TempRoot::new("conditional");
+        let files = StaticFiles::new(&root.0).unwrap();
+        let first = files.serve(&get("/index.html"), "index.html");
+        let etag = first.headers.get("ETag").unwrap().to_string();
+        let modified = first.headers.get("Last-Modified").unwrap().to_string();
+
+        let mut by_tag = get("/index.html");
+        by_tag.headers.insert("If-None-Match", etag.clone());
+        let mut by_date = get("/index.html");
+        by_date.headers.insert("If-Modified-Since", modified);
+        let mut stale = get("/index.html");
+        stale.headers.insert("If-None-Match", "\"something-else\"");
+
+        assert_eq!(Status::NotModified, files.serve(&by_tag, "index.html").status);
+        assert_eq!(Some(etag.as_str()), files.serve(&by_tag, "index.html").headers.get("ETag"));
+        assert_eq!(Status::NotModified, files.serve(&by_date, "index.html").status);
+        assert_eq!(Status::Ok, files.serve(&stale, "index.html").status);
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+
+macro_rules! statuses {
+    ($($name:ident = $code:expr, $reason:expr;)*) => {
+        /// An HTTP status code.
+        ///
+        /// The common codes have their own variants; any other code is kept
+        /// in `Other`. Converting from a `u16` always picks the named variant
+        /// when there is one, so `Status::from(404) == Status::NotFound`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Status {
+            $($name,)*
+            Other(u16),
+        }
+
+        impl Status {
+            pub fn code(self) -> u16 {
+                match self {
+                    $(Status::$name => $code,)*
+                    Status::Other(code) => code,
+                }
+            }
+
+            /// The standard reason phrase, or "Unknown" for codes without a
+            /// variant.
+            pub fn reason_phrase(self) -> &'static str {
+                match self {
+                    $(Status::$name => $reason,)*
+                    Status::Other(_) => "Unknown",
+                }
+            }
+        }
+
+        impl From<u16> for Status {
+            fn from(code: u16) -> Status {
+                match code {
+                    $($code => Status::$name,)*
+                    code => Status::Other(code),
+                }
+            }
+        }
+    };
+}
+
+statuses! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NoContent = 204, "No Content";
+    PartialContent = 206, "Partial Content";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    LengthRequired = 411, "Length Required";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    UpgradeRequired = 426, "Upgrade Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+}
+
+impl Status {
+    /// Responses with these codes never have a body, or a `Content-Length`
+    /// describing one.
+    pub fn has_body(self) -> bool {
+        let code = self.code();
+        !(100..200).contains(&code) && code != 204 && code != 304
+    }
+}
+
+impl From<Status> for u16 {
+    fn from(status: Status) -> u16 {
+        status.code()
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason_phrase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_map_to_named_variants() {
+        assert_eq!(Status::NotFound, Status::from(404));
+        assert_eq!(404, Status::NotFound.code());
+        assert_eq!(Status::Other(418), Status::from(418));
+        assert_eq!(418, u16::from(Status::Other(418)));
+        assert_eq!("431 Request Header Fields Too Large", Status::from(431).to_string());
+        assert_eq!("Unknown", Status::from(599).reason_phrase());
+    }
+}
@@ -0,0 +1,303 @@
+//! Helpers for testing routers and servers. They are only built with the
+//! `testing` feature, which a crate enables for its tests alone:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! multi_threaded_web_server = { version = "0.1", features = ["testing"] }
+//! ```
+//!
+//! A `TestClient` runs requests through a router and its middleware in the
+//! test's own thread, without a socket in sight:
+//!
+//! ```
+//! use multi_threaded_web_server::testing::TestClient;
+//! use multi_threaded_web_server::{Params, Request, Response, Router};
+//!
+//! let client = TestClient::new(Router::new().get("/hello/:name", |_: &Request, params: &Params| {
+//!     Response::text(200, format!("Hello, {}!", params.get("name").unwrap()))
+//! }));
+//!
+//! client
+//!     .get("/hello/ann")
+//!     .assert_status(200)
+//!     .assert_header("Content-Type", "text/plain; charset=utf-8")
+//!     .assert_body("Hello, ann!");
+//! ```
+//!
+//! A `TestServer` runs a whole `Server` on a port of its own, for testing
+//! what happens on the wire, and shuts it down when dropped.
+
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::headers::Headers;
+use crate::request::{Method, Request};
+use crate::response::{read_response, Body, Response};
+use crate::router::Router;
+use crate::server::{Server, ShutdownHandle};
+use crate::status::Status;
+
+/// How long a `TestServer` request waits for the server before failing.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest response body a `TestServer` request reads.
+const MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Sends requests straight to a router.
+pub struct TestClient {
+    router: Router,
+}
+
+impl TestClient {
+    pub fn new(router: Router) -> TestClient {
+        TestClient { router }
+    }
+
+    pub fn get(&self, target: &str) -> TestResponse {
+        self.send(Request::new(Method::Get, target))
+    }
+
+    pub fn post(&self, target: &str, body: impl Into<Vec<u8>>) -> TestResponse {
+        self.send(Request::new(Method::Post, target).with_body(body))
+    }
+
+    /// Runs `request` through the router, as if from `127.0.0.1`. The
+    /// response is as the handler and middleware left it, without the
+    /// headers a connection adds, like `Content-Length`.
+    pub fn send(&self, mut request: Request) -> TestResponse {
+        if !request.headers.contains("Host") {
+            request.headers.insert("Host", "localhost");
+        }
+        request.remote_addr.get_or_insert_with(|| SocketAddr::from(([127, 0, 0, 1], 0)));
+
+        let response = self.router.handle(&request);
+        TestResponse::from_response(response, request.method == Method::Head)
+    }
+}
+
+/// A `Server` running on its own thread, on a port picked by the system.
+/// Dropping it shuts the server down.
+pub struct TestServer {
+    addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    thread: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl TestServer {
+    /// Serves `router` on `127.0.0.1` with the default settings.
+    ///
+    /// # Panics
+    ///
+    /// The `start` function will panic if it can't bind a port.
+    pub fn start(router: Router) -> TestServer {
+        TestServer::run(Server::bind("127.0.0.1:0", router).expect("can't bind a port for the test server"))
+    }
+
+    /// Runs a server that has been set up already, for testing other
+    /// settings.
+    pub fn run(server: Server) -> TestServer {
+        let addr = server.local_addr().expect("the test server has no address");
+        let shutdown = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+
+        TestServer {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The `http://` URL of `path` on the server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Opens a connection to the server.
+    pub fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).expect("can't connect to the test server");
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        stream
+    }
+
+    pub fn get(&self, target: &str) -> TestResponse {
+        self.send(&Request::new(Method::Get, target))
+    }
+
+    /// Sends `request` on a connection of its own, which the server is
+    /// asked to close afterwards.
+    ///
+    /// # Panics
+    ///
+    /// The `send` function will panic if the server doesn't answer with a
+    /// valid response, with a body of at most 16 MiB, within a few seconds.
+    pub fn send(&self, request: &Request) -> TestResponse {
+        let mut stream = self.connect();
+        stream.write_all(&request_bytes(request)).expect("can't send the request");
+
+        let head = request.method == Method::Head;
+        let response = read_response(BufReader::new(stream), head, MAX_RESPONSE_SIZE).expect("bad response");
+        TestResponse::from_response(response, head)
+    }
+
+    /// Shuts the server down and waits for it, returning what `run`
+    /// returned.
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        self.shutdown.shutdown();
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| Err(io::Error::other("the server panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// `request` as it goes on the wire, with a `Host` header if it had none,
+/// and asking for the connection to be closed.
+fn request_bytes(request: &Request) -> Vec<u8> {
+    let mut head = format!("{} {}", request.method, request.path);
+    if let Some(query) = &request.query {
+        head.push('?');
+        head.push_str(query);
+    }
+    head.push_str(&format!(" {}\r\n", request.version));
+
+    let mut headers = request.headers.clone();
+    if !headers.contains("Host") {
+        headers.insert("Host", "localhost");
+    }
+    headers.insert("Connection", "close");
+    headers.insert("Content-Length", request.body.len().to_string());
+    for (name, value) in headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(&request.body);
+    bytes
+}
+
+/// A response with its body read into memory, and assertions on it. The
+/// assertions return the response, so they can be chained.
+pub struct TestResponse {
+    pub status: Status,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Reads the body of `response`, leaving it empty for a `HEAD` request
+    /// as a connection would.
+    ///
+    /// # Panics
+    ///
+    /// The `from_response` function will panic if a file or stream body
+    /// fails.
+    pub fn from_response(response: Response, head: bool) -> TestResponse {
+        let mut body = Vec::new();
+        if !head && response.status.has_body() {
+            match response.body {
+                Body::Bytes(bytes) => body = bytes,
+                Body::File { file, len } => {
+                    file.take(len).read_to_end(&mut body).expect("can't read the file body");
+                }
+                Body::Stream(chunks) => {
+                    for chunk in chunks {
+                        body.extend(chunk.expect("the stream body failed"));
+                    }
+                }
+            }
+        }
+
+        TestResponse {
+            status: response.status,
+            headers: response.headers,
+            body,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The body as text, with anything that isn't UTF-8 replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    #[track_caller]
+    pub fn assert_status(&self, status: impl Into<Status>) -> &TestResponse {
+        let status = status.into();
+        assert!(self.status == status, "expected status {}, got\n{}", status, self);
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_header(&self, name: &str, value: &str) -> &TestResponse {
+        assert!(
+            self.header(name) == Some(value),
+            "expected header {}: {}, got\n{}",
+            name,
+            value,
+            self
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_no_header(&self, name: &str) -> &TestResponse {
+        assert!(!self.headers.contains(name), "expected no {} header, got\n{}", name, self);
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_body(&self, body: impl AsRef<[u8]>) -> &TestResponse {
+        let body = body.as_ref();
+        assert!(
+            self.body == body,
+            "expected body {:?}, got\n{}",
+            String::from_utf8_lossy(body),
+            self
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_body_contains(&self, text: &str) -> &TestResponse {
+        assert!(self.text().contains(text), "expected the body to contain {:?}, got\n{}", text, self);
+        self
+    }
+}
+
+/// Shows the response much as it was sent, for assertion messages.
+impl fmt::Display for TestResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.status)?;
+        for (name, value) in self.headers.iter() {
+            writeln!(f, "{}: {}", name, value)?;
+        }
+        write!(f, "\n{}", self.text())
+    }
+}
+
+impl fmt::Debug for TestResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,178 @@
+//! HTTPS, with TLS provided by rustls.
+
+use std::fs;
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::connection::Transport;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::{Params, Router};
+use crate::status::Status;
+
+/// The certificate and private key a `Server` proves its identity with.
+/// Cloning one is cheap.
+#[derive(Clone)]
+pub struct TlsConfig {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsConfig {
+    /// Loads a certificate chain, the server's own certificate first, and
+    /// its private key from PEM files.
+    pub fn from_pem_files<C, K>(cert_chain: C, key: K) -> io::Result<TlsConfig>
+        where
+            C: AsRef<Path>,
+            K: AsRef<Path>
+    {
+        TlsConfig::from_pem(&read(cert_chain.as_ref())?, &read(key.as_ref())?)
+    }
+
+    /// Like `from_pem_files`, from PEM already in memory. The key may be in
+    /// PKCS#8, PKCS#1 or SEC1 form.
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> io::Result<TlsConfig> {
+        let certs = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(format!("can't read certificate: {}", e)))?;
+        if certs.is_empty() {
+            return Err(invalid("no certificate found".to_string()));
+        }
+        let key = PrivateKeyDer::from_pem_slice(key).map_err(|e| invalid(format!("can't read private key: {}", e)))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid(e.to_string()))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| invalid(format!("can't use certificate and key: {}", e)))?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(TlsConfig { config: Arc::new(config) })
+    }
+
+    /// Starts the server side of a new TLS connection. The handshake
+    /// happens as the connection is first read from and written to.
+    pub(crate) fn accept(&self) -> io::Result<ServerConnection> {
+        ServerConnection::new(Arc::clone(&self.config)).map_err(io::Error::other)
+    }
+}
+
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A TLS connection on a client's socket, as served by a worker.
+pub(crate) type TlsStream<'a> = StreamOwned<ServerConnection, &'a TcpStream>;
+
+impl<'a> Transport for TlsStream<'a> {
+    fn socket(&self) -> &TcpStream {
+        self.sock
+    }
+
+    fn close_write(&mut self) -> io::Result<()> {
+        // Without a close_notify the client can't tell the end of the
+        // connection from an attacker cutting it short.
+        self.conn.send_close_notify();
+        self.flush()?;
+        self.sock.shutdown(Shutdown::Write)
+    }
+
+    fn into_tls(self) -> Option<ServerConnection> {
+        Some(self.conn)
+    }
+}
+
+/// A router that sends every request on to the same host and path over
+/// HTTPS on `https_port`, for a `Server` listening on the plain HTTP port.
+///
+/// The redirect is a 308 Permanent Redirect, which keeps the method and
+/// body; requests without a `Host` header get 400 Bad Request.
+pub fn https_redirect(https_port: u16) -> Router {
+    Router::new().fallback(move |request: &Request, _: &Params| match https_location(request, https_port) {
+        Some(location) => Response::text(Status::PermanentRedirect, format!("Moved to {}\n", location))
+            .with_header("Location", location),
+        None => Response::text(Status::BadRequest, "Missing Host header\n"),
+    })
+}
+
+/// Where `request` is found over HTTPS, or `None` if it doesn't say which
+/// host it is for.
+fn https_location(request: &Request, https_port: u16) -> Option<String> {
+    let host = request.header("Host")?.trim();
+    // Drop the plain port, taking care over IPv6 addresses like `[::1]:80`.
+    let name = match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    };
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut location = format!("https://{}", name);
+    if https_port != 443 {
+        location.push_str(&format!(":{}", https_port));
+    }
+    location.push_str(&request.path);
+    if let Some(query) = &request.query {
+        location.push('?');
+        location.push_str(query);
+    }
+
+    Some(location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+
+    fn from_host(host: &str, target: &str) -> Request {
+        let mut request = Request::new(Method::Get, target);
+        request.headers.insert("Host", host);
+        request
+    }
+
+    #[test]
+    fn redirects_to_the_https_port() {
+        let request = from_host("example.com:8080", "/a/b?c=d");
+
+        assert_eq!(Some("https://example.com/a/b?c=d".to_string()), https_location(&request, 443));
+        assert_eq!(
+            Some("https://[::1]:8443/".to_string()),
+            https_location(&from_host("[::1]:8080", "/"), 8443)
+        );
+        assert_eq!(
+            Some("https://[::1]:8443/".to_string()),
+            https_location(&from_host("[::1]", "/"), 8443)
+        );
+    }
+
+    #[test]
+    fn needs_a_host() {
+        let router = https_redirect(443);
+        let response = router.handle(&Request::new(Method::Post, "/form"));
+        let redirected = router.handle(&from_host("example.com", "/form"));
+
+        assert_eq!(Status::BadRequest, response.status);
+        assert_eq!(Status::PermanentRedirect, redirected.status);
+        assert_eq!(Some("https://example.com/form"), redirected.headers.get("Location"));
+    }
+
+    #[test]
+    fn rejects_bad_pem() {
+        let error = TlsConfig::from_pem(b"not a certificate", b"nor a key").err().unwrap();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+}
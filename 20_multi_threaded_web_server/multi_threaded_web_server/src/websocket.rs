@@ -0,0 +1,847 @@
+//! WebSocket connections (RFC 6455), upgraded from HTTP requests.
+//!
+//! A route made with `upgrade` answers the opening handshake and hands the
+//! connection to its handler as a `Sender` and a `Receiver`:
+//!
+//! ```no_run
+//! use multi_threaded_web_server::websocket::{self, Receiver, Sender};
+//! use multi_threaded_web_server::{Params, Request, Router};
+//!
+//! let router = Router::new().get(
+//!     "/echo",
+//!     websocket::upgrade(|_: &Request, _: &Params, sender: Sender, receiver: Receiver| {
+//!         for message in receiver {
+//!             if sender.send(message).is_err() {
+//!                 break;
+//!             }
+//!         }
+//!     }),
+//! );
+//! ```
+//!
+//! Each handler runs on a thread of its own rather than on the worker pool,
+//! so a connection can stay open as long as the client keeps answering
+//! without holding up requests. The `Receiver` answers pings and the
+//! client's close frame itself, and pings a client that goes quiet; the
+//! connection closes once the handler has returned and the last `Sender`
+//! is dropped.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rustls::ServerConnection;
+use sha1::{Digest, Sha1};
+
+use crate::connection::{is_timeout, ConnectionConfig};
+use crate::log::{log, LogLevel};
+use crate::request::{Request, Version};
+use crate::response::Response;
+use crate::router::{Handler, Params};
+use crate::server::busy_response;
+use crate::status::Status;
+
+/// Close codes, from RFC 6455 section 7.4.1.
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+/// Stands in for the code in a close frame that didn't have one. It is
+/// never sent.
+pub const CLOSE_NO_STATUS: u16 = 1005;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// What the client's key is hashed with to make `Sec-WebSocket-Accept`.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// How long to wait for the client to answer a close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// Takes over a connection once the handshake is done. Implemented for
+/// closures taking the request, its route parameters and the connection's
+/// two ends.
+///
+/// The handler has the thread to itself, so it may loop over the
+/// `Receiver` for as long as the connection lasts.
+pub trait WebSocketHandler: Send + Sync + 'static {
+    fn open(&self, request: &Request, params: &Params, sender: Sender, receiver: Receiver);
+}
+
+impl<F> WebSocketHandler for F
+    where
+        F: Fn(&Request, &Params, Sender, Receiver) + Send + Sync + 'static
+{
+    fn open(&self, request: &Request, params: &Params, sender: Sender, receiver: Receiver) {
+        self(request, params, sender, receiver)
+    }
+}
+
+/// A route handler that upgrades requests to WebSocket connections and
+/// hands them to `handler`.
+///
+/// Requests without `Upgrade: websocket`, or for a version of the protocol
+/// other than 13, get 426 Upgrade Required; ones with a bad key get 400 Bad
+/// Request. Messages may be as large as the server's `max_body_size`.
+pub fn upgrade<H: WebSocketHandler>(handler: H) -> Endpoint<H> {
+    Endpoint {
+        handler: Arc::new(handler),
+        open: Arc::new(AtomicUsize::new(0)),
+        max_connections: 1024,
+        idle_timeout: Duration::from_secs(60),
+    }
+}
+
+/// The route handler made by `upgrade`.
+pub struct Endpoint<H> {
+    handler: Arc<H>,
+    /// How many of its connections are open.
+    open: Arc<AtomicUsize>,
+    max_connections: usize,
+    idle_timeout: Duration,
+}
+
+impl<H: WebSocketHandler> Endpoint<H> {
+    /// Limits how many connections, each with a thread of its own, may be
+    /// open at once. Handshakes beyond that get 503 Service Unavailable.
+    /// The default is 1024.
+    pub fn with_max_connections(mut self, max: usize) -> Endpoint<H> {
+        self.max_connections = max;
+        self
+    }
+
+    /// How long a connection may go without a frame from the client before
+    /// it is pinged, and then without an answer before it is closed. The
+    /// default is a minute.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Endpoint<H> {
+        self.idle_timeout = timeout;
+        self
+    }
+}
+
+impl<H: WebSocketHandler> Handler for Endpoint<H> {
+    fn handle(&self, request: &Request, params: &Params) -> Response {
+        let accept = match handshake(request) {
+            Ok(accept) => accept,
+            Err(response) => return response,
+        };
+        let reserved = self.open.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+            if open < self.max_connections { Some(open + 1) } else { None }
+        });
+        if reserved.is_err() {
+            return busy_response();
+        }
+
+        let slot = Slot(Arc::clone(&self.open));
+        let handler = Arc::clone(&self.handler);
+        let request = request.clone();
+        let params = params.clone();
+        let open = move |sender, receiver| {
+            handler.open(&request, &params, sender, receiver);
+            drop(slot);
+        };
+
+        Response::new(Status::SwitchingProtocols)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", accept)
+            .with_upgrade(Upgrade {
+                open: Box::new(open),
+                idle_timeout: self.idle_timeout,
+            })
+    }
+}
+
+/// One of an `Endpoint`'s open connections, given back when dropped: once
+/// the handler returns, or if the connection never gets to it.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Checks that `request` opens a WebSocket connection, returning the
+/// `Sec-WebSocket-Accept` value to answer it with, or the response that
+/// turns it down.
+fn handshake(request: &Request) -> Result<String, Response> {
+    if !request.headers.has_token("Upgrade", "websocket") || !request.headers.has_token("Connection", "upgrade") {
+        return Err(Response::text(Status::UpgradeRequired, "Expected a WebSocket handshake\n")
+            .with_header("Upgrade", "websocket"));
+    }
+    if request.version != Version::Http11 {
+        return Err(Response::text(Status::BadRequest, "WebSockets need HTTP/1.1\n"));
+    }
+    if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        return Err(Response::text(Status::UpgradeRequired, "Unsupported WebSocket version\n")
+            .with_header("Sec-WebSocket-Version", "13"));
+    }
+
+    let key = request.header("Sec-WebSocket-Key").unwrap_or("").trim();
+    match BASE64.decode(key) {
+        Ok(nonce) if nonce.len() == 16 => Ok(accept_key(key)),
+        _ => Err(Response::text(Status::BadRequest, "Bad Sec-WebSocket-Key\n")),
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+/// What a `101 Switching Protocols` response hands its connection to.
+pub(crate) struct Upgrade {
+    open: Box<dyn FnOnce(Sender, Receiver) + Send>,
+    idle_timeout: Duration,
+}
+
+impl Upgrade {
+    /// Starts the handler on a thread of its own. The connection is
+    /// `socket`, in blocking mode, with `tls` on top for HTTPS; `buffered`
+    /// is what has already been read past the handshake.
+    pub(crate) fn start(
+        self,
+        socket: TcpStream,
+        tls: Option<ServerConnection>,
+        buffered: Vec<u8>,
+        config: &ConnectionConfig,
+    ) {
+        let (sender, receiver) = match open(socket, tls, buffered, self.idle_timeout, config) {
+            Ok(ends) => ends,
+            Err(e) => {
+                log(LogLevel::Warn, format_args!("Error on connection: {}", e));
+                return;
+            }
+        };
+
+        let open = self.open;
+        let spawned = thread::Builder::new()
+            .name("websocket".to_string())
+            .spawn(move || open(sender, receiver));
+        if let Err(e) = spawned {
+            log(LogLevel::Error, format_args!("Error starting WebSocket handler: {}", e));
+        }
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Upgrade")
+    }
+}
+
+fn open(
+    socket: TcpStream,
+    tls: Option<ServerConnection>,
+    buffered: Vec<u8>,
+    idle_timeout: Duration,
+    config: &ConnectionConfig,
+) -> io::Result<(Sender, Receiver)> {
+    socket.set_read_timeout(Some(idle_timeout))?;
+    socket.set_write_timeout(Some(config.write_timeout))?;
+
+    let tls = tls.map(|tls| Arc::new(Mutex::new(tls)));
+    let reading = Link {
+        socket: socket.try_clone()?,
+        tls: tls.clone(),
+    };
+    let sender = Sender {
+        writer: Arc::new(Mutex::new(Writer {
+            link: Link { socket, tls },
+            close_sent: false,
+            finished: false,
+        })),
+    };
+    let receiver = Receiver {
+        reader: BufReader::new(Cursor::new(buffered).chain(reading)),
+        sender: sender.clone(),
+        max_message_size: config.max_body_size,
+        fragments: None,
+        close_frame: None,
+        closed: false,
+        pinged: false,
+    };
+
+    Ok((sender, receiver))
+}
+
+/// A whole message, put back together from its fragments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Message {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Message {
+        Message::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Message {
+        Message::Binary(data)
+    }
+}
+
+/// Why the client closed the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// `CLOSE_NO_STATUS` if the client didn't give one.
+    pub code: u16,
+    pub reason: String,
+}
+
+/// Sends messages to the client. Clones share the connection and can be
+/// handed to other threads, to push updates from wherever they happen;
+/// each message goes out whole.
+#[derive(Clone)]
+pub struct Sender {
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl Sender {
+    pub fn send(&self, message: impl Into<Message>) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        match message.into() {
+            Message::Text(text) => writer.send(TEXT, text.as_bytes()),
+            Message::Binary(data) => writer.send(BINARY, &data),
+        }
+    }
+
+    /// Sends a ping with up to 125 bytes of `payload`, for the client to
+    /// answer with a pong.
+    pub fn ping(&self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > 125 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "ping payload over 125 bytes"));
+        }
+        self.writer.lock().unwrap().send(PING, payload)
+    }
+
+    /// Starts the closing handshake with `code` and up to 123 bytes of
+    /// `reason`. Nothing more can be sent afterwards, and the `Receiver`
+    /// ends when the client answers, or after a few seconds if it doesn't.
+    pub fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+        if reason.len() > 123 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "close reason over 123 bytes"));
+        }
+
+        let mut writer = self.writer.lock().unwrap();
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        writer.send_close(&payload)?;
+        writer.link.socket.set_read_timeout(Some(CLOSE_TIMEOUT))
+    }
+
+    /// Whether a close frame has been sent, by `close` or in answer to the
+    /// client's.
+    pub fn is_closed(&self) -> bool {
+        self.writer.lock().unwrap().close_sent
+    }
+}
+
+/// The sending end of a connection, shared by the `Sender`s and the
+/// `Receiver`. Dropping it closes the connection.
+struct Writer {
+    link: Link,
+    close_sent: bool,
+    finished: bool,
+}
+
+impl Writer {
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if self.close_sent {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "the WebSocket is closed"));
+        }
+
+        let mut frame = frame_header(opcode, payload.len());
+        frame.extend_from_slice(payload);
+        self.link.write_all(&frame)
+    }
+
+    fn send_close(&mut self, payload: &[u8]) -> io::Result<()> {
+        let result = self.send(CLOSE, payload);
+        self.close_sent = true;
+        result
+    }
+
+    /// Ends the connection, once the closing handshake is over or has
+    /// failed.
+    fn finish(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            self.link.close_write();
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if !self.close_sent && !self.finished {
+            let _ = self.send_close(&CLOSE_NORMAL.to_be_bytes());
+        }
+        self.finish();
+    }
+}
+
+/// Receives the client's messages, answering its pings and its close frame
+/// along the way.
+///
+/// As an iterator it ends when the connection closes or fails; `recv` tells
+/// the two apart.
+pub struct Receiver {
+    reader: BufReader<io::Chain<Cursor<Vec<u8>>, Link>>,
+    sender: Sender,
+    max_message_size: usize,
+    /// The opcode and data so far of a message arriving in fragments.
+    fragments: Option<(u8, Vec<u8>)>,
+    close_frame: Option<CloseFrame>,
+    closed: bool,
+    /// Whether the client has been pinged for going quiet, and not been
+    /// heard from since.
+    pinged: bool,
+}
+
+impl Receiver {
+    /// Waits for the next message. Returns `Ok(None)` once the client has
+    /// closed the connection, and an error if it fails or the client breaks
+    /// the protocol, in which case it is closed with the matching code.
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        if self.closed {
+            return Ok(None);
+        }
+
+        let result = self.read_message();
+        if !matches!(result, Ok(Some(_))) {
+            self.closed = true;
+        }
+
+        match result {
+            Ok(message) => Ok(message),
+            Err(ReadError::Io(e)) => {
+                self.sender.writer.lock().unwrap().finish();
+                Err(e)
+            }
+            Err(ReadError::Protocol(code, message)) => {
+                let mut writer = self.sender.writer.lock().unwrap();
+                if !writer.close_sent {
+                    let mut payload = code.to_be_bytes().to_vec();
+                    payload.extend_from_slice(message.as_bytes());
+                    let _ = writer.send_close(&payload);
+                }
+                writer.finish();
+                Err(io::Error::new(io::ErrorKind::InvalidData, message))
+            }
+        }
+    }
+
+    /// What the client's close frame said, once it has arrived.
+    pub fn close_frame(&self) -> Option<&CloseFrame> {
+        self.close_frame.as_ref()
+    }
+
+    fn read_message(&mut self) -> Result<Option<Message>, ReadError> {
+        loop {
+            self.wait_for_frame()?;
+            let frame = read_frame(&mut self.reader, self.max_message_size)?;
+            self.pinged = false;
+
+            match frame.opcode {
+                PING => {
+                    let mut writer = self.sender.writer.lock().unwrap();
+                    if !writer.close_sent {
+                        writer.send(PONG, &frame.payload)?;
+                    }
+                    continue;
+                }
+                PONG => continue,
+                CLOSE => {
+                    let close = parse_close(&frame.payload)?;
+                    let mut writer = self.sender.writer.lock().unwrap();
+                    if !writer.close_sent {
+                        // Echo the code, as the handshake asks.
+                        let _ = writer.send_close(&frame.payload[..frame.payload.len().min(2)]);
+                    }
+                    writer.finish();
+                    self.close_frame = Some(close);
+                    return Ok(None);
+                }
+                TEXT | BINARY if self.fragments.is_some() => {
+                    return Err(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "expected a continuation frame"));
+                }
+                TEXT | BINARY => self.fragments = Some((frame.opcode, frame.payload)),
+                CONTINUATION => match &mut self.fragments {
+                    Some((_, data)) if data.len() + frame.payload.len() > self.max_message_size => {
+                        return Err(ReadError::Protocol(CLOSE_MESSAGE_TOO_BIG, "message too big"));
+                    }
+                    Some((_, data)) => data.extend_from_slice(&frame.payload),
+                    None => return Err(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "unexpected continuation frame")),
+                },
+                _ => return Err(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "unknown opcode")),
+            }
+
+            if frame.fin {
+                if let Some((opcode, data)) = self.fragments.take() {
+                    return message(opcode, data).map(Some);
+                }
+            }
+        }
+    }
+}
+
+impl Receiver {
+    /// Waits for the client to start sending a frame. A client that stays
+    /// quiet for the idle timeout is pinged, and one that is still quiet
+    /// after another is given up on.
+    fn wait_for_frame(&mut self) -> io::Result<()> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok(_) => return Ok(()),
+                // Once closing, the wait is for the close frame alone.
+                Err(e) if is_timeout(&e) && !self.pinged && !self.sender.is_closed() => {
+                    self.pinged = true;
+                    self.sender.ping(b"")?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Iterator for Receiver {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Message> {
+        self.recv().ok().flatten()
+    }
+}
+
+enum ReadError {
+    Io(io::Error),
+    /// The client broke the protocol; the connection is closed with the
+    /// code and message.
+    Protocol(u16, &'static str),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> ReadError {
+        ReadError::Io(error)
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Reads one frame from the client and unmasks its payload.
+fn read_frame<R: Read>(reader: &mut R, max_size: usize) -> Result<Frame, ReadError> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0f;
+
+    if head[0] & 0x70 != 0 {
+        return Err(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "reserved bits set"));
+    }
+    if head[1] & 0x80 == 0 {
+        return Err(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "client frames must be masked"));
+    }
+
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    if opcode >= CLOSE && (!fin || len > 125) {
+        return Err(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "control frames must be whole and short"));
+    }
+    if len > max_size as u64 {
+        return Err(ReadError::Protocol(CLOSE_MESSAGE_TOO_BIG, "message too big"));
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Frame { fin, opcode, payload })
+}
+
+/// The head of an unfragmented frame from the server, which isn't masked.
+fn frame_header(opcode: u8, len: usize) -> Vec<u8> {
+    let mut header = vec![0x80 | opcode];
+    match len {
+        0..=125 => header.push(len as u8),
+        126..=0xffff => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    header
+}
+
+fn message(opcode: u8, data: Vec<u8>) -> Result<Message, ReadError> {
+    if opcode == BINARY {
+        return Ok(Message::Binary(data));
+    }
+    String::from_utf8(data)
+        .map(Message::Text)
+        .map_err(|_| ReadError::Protocol(CLOSE_INVALID_DATA, "text message isn't UTF-8"))
+}
+
+fn parse_close(payload: &[u8]) -> Result<CloseFrame, ReadError> {
+    let (code, reason) = match payload {
+        [] => (CLOSE_NO_STATUS, &[][..]),
+        [_] => return Err(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "close frame too short")),
+        [high, low, reason @ ..] => (u16::from_be_bytes([*high, *low]), reason),
+    };
+
+    // Codes 1004 to 1006 and 1015 are only for reporting, never sending.
+    let sendable = matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999);
+    if !sendable && !payload.is_empty() {
+        return Err(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "invalid close code"));
+    }
+    let reason = str::from_utf8(reason)
+        .map_err(|_| ReadError::Protocol(CLOSE_INVALID_DATA, "close reason isn't UTF-8"))?;
+
+    Ok(CloseFrame {
+        code,
+        reason: reason.to_string(),
+    })
+}
+
+/// One thread's hold on the connection: its own handle on the socket, and
+/// the TLS session, if any, shared with the other.
+struct Link {
+    socket: TcpStream,
+    tls: Option<Arc<Mutex<ServerConnection>>>,
+}
+
+impl Link {
+    fn write_all(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        let tls = match &self.tls {
+            Some(tls) => tls,
+            None => return self.socket.write_all(bytes),
+        };
+
+        let mut tls = tls.lock().unwrap();
+        while !bytes.is_empty() {
+            // The session only takes as much as it has room for.
+            let n = tls.writer().write(bytes)?;
+            bytes = &bytes[n..];
+            send_tls(&mut tls, &self.socket)?;
+        }
+        Ok(())
+    }
+
+    fn close_write(&mut self) {
+        if let Some(tls) = &self.tls {
+            let mut tls = tls.lock().unwrap();
+            tls.send_close_notify();
+            let _ = send_tls(&mut tls, &self.socket);
+        }
+        let _ = self.socket.shutdown(Shutdown::Write);
+    }
+}
+
+impl Read for Link {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let tls = match &self.tls {
+            Some(tls) => tls,
+            None => return self.socket.read(buf),
+        };
+
+        loop {
+            match tls.lock().unwrap().reader().read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+
+            // Wait for more without holding the session, so that sending
+            // can carry on meanwhile. Reading nothing tells it the
+            // connection has ended.
+            let mut incoming = [0; 16 * 1024];
+            let n = (&self.socket).read(&mut incoming)?;
+            let mut received = &incoming[..n];
+
+            let mut tls = tls.lock().unwrap();
+            loop {
+                tls.read_tls(&mut received)?;
+                if let Err(e) = tls.process_new_packets() {
+                    // Let the client know why, if it is listening.
+                    let _ = send_tls(&mut tls, &self.socket);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                if received.is_empty() {
+                    break;
+                }
+            }
+            send_tls(&mut tls, &self.socket)?;
+        }
+    }
+}
+
+/// Writes out the TLS records waiting in `tls`.
+fn send_tls(tls: &mut ServerConnection, mut socket: &TcpStream) -> io::Result<()> {
+    while tls.wants_write() {
+        tls.write_tls(&mut socket)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+
+    fn opening(key: &str, version: &str) -> Request {
+        let mut request = Request::new(Method::Get, "/chat");
+        request.headers.insert("Upgrade", "websocket");
+        request.headers.insert("Connection", "keep-alive, Upgrade");
+        request.headers.insert("Sec-WebSocket-Key", key);
+        request.headers.insert("Sec-WebSocket-Version", version);
+        request
+    }
+
+    /// A frame as a client sends it, masked.
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![first, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn answers_the_handshake() {
+        // The example from RFC 6455.
+        let accept = handshake(&opening("dGhlIHNhbXBsZSBub25jZQ==", "13"));
+
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept.ok().unwrap());
+    }
+
+    #[test]
+    fn turns_down_bad_handshakes() {
+        let mut plain = opening("dGhlIHNhbXBsZSBub25jZQ==", "13");
+        plain.headers.remove("Upgrade");
+        let old_version = handshake(&opening("dGhlIHNhbXBsZSBub25jZQ==", "8")).err().unwrap();
+        let short_key = handshake(&opening("c2hvcnQ=", "13")).err().unwrap();
+
+        assert_eq!(Status::UpgradeRequired, handshake(&plain).err().unwrap().status);
+        assert_eq!(Status::UpgradeRequired, old_version.status);
+        assert_eq!(Some("13"), old_version.headers.get("Sec-WebSocket-Version"));
+        assert_eq!(Status::BadRequest, short_key.status);
+    }
+
+    #[test]
+    fn limits_open_connections() {
+        let endpoint = upgrade(|_: &Request, _: &Params, _: Sender, _: Receiver| {}).with_max_connections(1);
+        let request = opening("dGhlIHNhbXBsZSBub25jZQ==", "13");
+
+        let first = endpoint.handle(&request, &Params::default());
+        let second = endpoint.handle(&request, &Params::default());
+        assert_eq!(Status::SwitchingProtocols, first.status);
+        assert_eq!(Status::ServiceUnavailable, second.status);
+        assert!(second.headers.get("Retry-After").is_some());
+
+        // A connection that never reached its handler gives its place back.
+        drop(first);
+        assert_eq!(Status::SwitchingProtocols, endpoint.handle(&request, &Params::default()).status);
+    }
+
+    #[test]
+    fn pings_quiet_clients_then_gives_up() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (socket, _) = listener.accept().unwrap();
+
+        let config = ConnectionConfig::default();
+        let (_sender, mut receiver) = open(socket, None, Vec::new(), Duration::from_millis(100), &config).unwrap();
+        let server = thread::spawn(move || receiver.recv().map(|_| ()));
+
+        let mut ping = [0; 2];
+        client.read_exact(&mut ping).unwrap();
+        assert_eq!([0x80 | PING, 0], ping);
+        assert!(server.join().unwrap().is_err());
+        assert_eq!(0, client.read(&mut [0; 16]).unwrap());
+    }
+
+    #[test]
+    fn reads_masked_frames() {
+        let mut input = client_frame(0x01, b"Hel");
+        input.extend(client_frame(0x80, b"lo"));
+        let mut reader = &input[..];
+
+        let first = read_frame(&mut reader, 100).ok().unwrap();
+        let last = read_frame(&mut reader, 100).ok().unwrap();
+
+        assert!(!first.fin);
+        assert_eq!((TEXT, &b"Hel"[..]), (first.opcode, &first.payload[..]));
+        assert!(last.fin);
+        assert_eq!((CONTINUATION, &b"lo"[..]), (last.opcode, &last.payload[..]));
+    }
+
+    #[test]
+    fn rejects_frames_that_break_the_rules() {
+        let error = |input: &[u8], max_size| match read_frame(&mut &input[..], max_size) {
+            Err(ReadError::Protocol(code, _)) => code,
+            _ => panic!("expected a protocol error"),
+        };
+        let unmasked = [0x81, 0x02, b'h', b'i'];
+        let fragmented_ping = client_frame(0x09, b"");
+
+        assert_eq!(CLOSE_PROTOCOL_ERROR, error(&unmasked, 100));
+        assert_eq!(CLOSE_PROTOCOL_ERROR, error(&fragmented_ping, 100));
+        assert_eq!(CLOSE_MESSAGE_TOO_BIG, error(&client_frame(0x82, &[0; 10]), 5));
+        assert!(parse_close(&[0x03, 0xed]).is_err());
+        assert_eq!(CLOSE_NO_STATUS, parse_close(&[]).ok().unwrap().code);
+    }
+
+    #[test]
+    fn frame_headers_grow_with_the_payload() {
+        assert_eq!(vec![0x81, 5], frame_header(TEXT, 5));
+        assert_eq!(vec![0x82, 126, 0x01, 0x00], frame_header(BINARY, 256));
+        assert_eq!(vec![0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0], frame_header(BINARY, 65536));
+    }
+}
@@ -0,0 +1,72 @@
+// Each test file uses its own share of these.
+#![allow(dead_code)]
+
+use multi_threaded_web_server::middleware::{Compression, RequestId};
+use multi_threaded_web_server::websocket::{self, Message, Receiver, Sender};
+use multi_threaded_web_server::{Handler, Params, Request, Response, Router, Server, ShutdownHandle};
+
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::thread;
+
+/// The opening handshake for `/echo`, from the example in RFC 6455.
+pub const HANDSHAKE: &[u8] = b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+/// What the server answers `HANDSHAKE` with in `Sec-WebSocket-Accept`.
+pub const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+/// A small site with the usual middleware: a greeting, an echo of posted
+/// bodies, a page big enough to compress and a WebSocket echo at `/echo`.
+pub fn app() -> Router {
+    Router::new()
+        .get("/", |_: &Request, _: &Params| Response::text(200, "hello"))
+        .post("/echo", |request: &Request, _: &Params| Response::text(200, request.body.clone()))
+        .get("/big", |_: &Request, _: &Params| Response::text(200, "hello ".repeat(1000)))
+        .get("/echo", echo_socket())
+        .wrap(RequestId::new())
+        .wrap(Compression::new())
+}
+
+/// Echoes WebSocket messages back, and starts closing with 1001 when it is
+/// sent `bye`.
+pub fn echo_socket() -> impl Handler {
+    websocket::upgrade(|_: &Request, _: &Params, sender: Sender, receiver: Receiver| {
+        for message in receiver {
+            if message == Message::from("bye") {
+                sender.close(websocket::CLOSE_GOING_AWAY, "bye").unwrap();
+            } else if sender.send(message).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Runs `server` on a thread of its own.
+pub fn start(server: Server) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<io::Result<()>>) {
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+
+    (addr, handle, thread::spawn(move || server.run()))
+}
+
+/// A frame as a client sends it, masked.
+pub fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    let mut frame = vec![first, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+    frame
+}
+
+/// Reads a short frame from the server, returning its first byte and
+/// payload.
+pub fn read_frame<R: Read>(reader: &mut R) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head).unwrap();
+    assert_eq!(0, head[1] & 0x80, "server frames aren't masked");
+    let mut payload = vec![0; usize::from(head[1])];
+    reader.read_exact(&mut payload).unwrap();
+
+    (head[0], payload)
+}
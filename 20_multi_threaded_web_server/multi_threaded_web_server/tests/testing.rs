@@ -0,0 +1,68 @@
+mod common;
+
+use common::app;
+use multi_threaded_web_server::testing::{TestClient, TestServer};
+use multi_threaded_web_server::{Method, Request};
+
+use flate2::read::GzDecoder;
+
+use std::io::Read;
+
+#[test]
+fn the_client_runs_requests_through_the_middleware() {
+    let client = TestClient::new(app());
+
+    let response = client.get("/");
+    response.assert_status(200).assert_body("hello");
+    assert!(response.header("X-Request-Id").is_some());
+
+    client
+        .send(Request::new(Method::Get, "/").with_header("X-Request-Id", "abc-123"))
+        .assert_header("X-Request-Id", "abc-123");
+    client.post("/echo", "ping").assert_status(200).assert_body("ping");
+    client.get("/missing").assert_status(404);
+}
+
+#[test]
+fn the_client_leaves_head_bodies_empty() {
+    let client = TestClient::new(app());
+
+    client
+        .send(Request::new(Method::Head, "/"))
+        .assert_status(200)
+        .assert_body("");
+}
+
+#[test]
+fn the_server_answers_on_its_own_port() {
+    let server = TestServer::start(app());
+    assert!(server.url("/a").ends_with(&format!(":{}/a", server.addr().port())));
+
+    server
+        .get("/")
+        .assert_status(200)
+        .assert_header("Content-Length", "5")
+        .assert_header("Connection", "close")
+        .assert_body("hello");
+
+    let response = server.send(&Request::new(Method::Get, "/big").with_header("Accept-Encoding", "gzip"));
+    response.assert_status(200).assert_header("Content-Encoding", "gzip");
+    let mut body = String::new();
+    GzDecoder::new(&response.body[..]).read_to_string(&mut body).unwrap();
+    assert_eq!("hello ".repeat(1000), body);
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn dropping_the_server_shuts_it_down() {
+    let addr = TestServer::start(app()).addr();
+
+    assert!(std::net::TcpStream::connect(addr).is_err());
+}
+
+#[test]
+#[should_panic(expected = "expected status 201 Created, got\n200 OK")]
+fn assertions_show_the_response() {
+    TestClient::new(app()).get("/").assert_status(201);
+}
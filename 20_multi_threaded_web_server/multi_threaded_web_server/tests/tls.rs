@@ -0,0 +1,249 @@
+mod common;
+
+use common::{frame, read_frame, start};
+use multi_threaded_web_server::client::Client;
+use multi_threaded_web_server::{tls, Params, Request, Response, Router, Server, ShutdownHandle, TlsConfig};
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+type TlsClient = BufReader<StreamOwned<ClientConnection, TcpStream>>;
+
+/// A certificate for `localhost`, made up for the test, and the client
+/// configuration that trusts it.
+struct Identity {
+    cert_pem: String,
+    key_pem: String,
+    client: Arc<ClientConfig>,
+}
+
+fn identity() -> Identity {
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(generated.cert.der().clone()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let client = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Identity {
+        cert_pem: generated.cert.pem(),
+        key_pem: generated.key_pair.serialize_pem(),
+        client: Arc::new(client),
+    }
+}
+
+fn router() -> Router {
+    Router::new()
+        .get("/", |_: &Request, _: &Params| Response::text(200, "hello over tls"))
+        .get("/big", |_: &Request, _: &Params| Response::text(200, vec![b'x'; 1024 * 1024]))
+        .get("/echo", common::echo_socket())
+}
+
+fn start_https(identity: &Identity, event_loop: bool) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<io::Result<()>>) {
+    let config = TlsConfig::from_pem(identity.cert_pem.as_bytes(), identity.key_pem.as_bytes()).unwrap();
+    let mut server = Server::bind("127.0.0.1:0", router()).unwrap().with_tls(config);
+    if event_loop {
+        server = server.with_event_loop(1);
+    }
+
+    start(server)
+}
+
+fn connect(addr: SocketAddr, identity: &Identity) -> TlsClient {
+    let name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(Arc::clone(&identity.client), name).unwrap();
+    let socket = TcpStream::connect(addr).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    BufReader::new(StreamOwned::new(connection, socket))
+}
+
+/// Reads one response with a `Content-Length`, returning its head and body.
+fn read_response<R: BufRead>(reader: &mut R) -> (String, Vec<u8>) {
+    let mut head = String::new();
+    loop {
+        let n = reader.read_line(&mut head).unwrap();
+        if n == 0 || head.ends_with("\r\n\r\n") {
+            break;
+        }
+    }
+
+    let len = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .map_or(0, |len| len.parse().unwrap());
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).unwrap();
+
+    (head, body)
+}
+
+fn keeps_a_connection_alive(event_loop: bool) {
+    let identity = identity();
+    let (addr, handle, server) = start_https(&identity, event_loop);
+    let mut client = connect(addr, &identity);
+
+    client.get_mut().write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let (head, body) = read_response(&mut client);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Connection: keep-alive\r\n"));
+    assert_eq!(b"hello over tls", &body[..]);
+
+    client.get_mut().write_all(b"GET /big HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let (head, body) = read_response(&mut client);
+    assert!(head.contains("Connection: close\r\n"));
+    assert_eq!(1024 * 1024, body.len());
+
+    // The server ends the connection cleanly, with a close_notify; without
+    // one, this would fail with an unexpected end of file.
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn serves_https_on_workers() {
+    keeps_a_connection_alive(false);
+}
+
+#[test]
+fn serves_https_on_event_loops() {
+    keeps_a_connection_alive(true);
+}
+
+fn echoes_over_websocket(event_loop: bool) {
+    let identity = identity();
+    let (addr, handle, server) = start_https(&identity, event_loop);
+    let mut client = connect(addr, &identity);
+
+    client.get_mut().write_all(common::HANDSHAKE).unwrap();
+    let (head, _) = read_response(&mut client);
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+
+    client.get_mut().write_all(&frame(0x81, b"hi")).unwrap();
+    assert_eq!((0x81, b"hi".to_vec()), read_frame(&mut client));
+
+    // Closing with code 1000 gets the same back, then a clean end.
+    client.get_mut().write_all(&frame(0x88, &[0x03, 0xe8])).unwrap();
+    assert_eq!((0x88, vec![0x03, 0xe8]), read_frame(&mut client));
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn serves_websockets_over_tls_on_workers() {
+    echoes_over_websocket(false);
+}
+
+#[test]
+fn serves_websockets_over_tls_on_event_loops() {
+    echoes_over_websocket(true);
+}
+
+#[test]
+fn the_client_speaks_https() {
+    let identity = identity();
+    let (addr, handle, server) = start_https(&identity, false);
+    let url = format!("https://localhost:{}/", addr.port());
+    let client = Client::new().with_tls(Arc::clone(&identity.client));
+
+    for _ in 0..2 {
+        let response = client.get(&url).unwrap();
+        assert_eq!(Some(&b"hello over tls"[..]), response.body.as_bytes());
+    }
+    // Trusting some other certificate, the handshake fails.
+    let stranger = self::identity();
+    assert!(Client::new().with_tls(stranger.client).get(&url).is_err());
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn plain_http_clients_are_redirected() {
+    let (addr, handle, server) = start(Server::bind("127.0.0.1:0", tls::https_redirect(8443)).unwrap());
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET /a?b=c HTTP/1.1\r\nHost: localhost:8080\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"));
+    assert!(response.contains("Location: https://localhost:8443/a?b=c\r\n"));
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn reloading_swaps_certificates_but_never_turns_tls_on() {
+    let old = identity();
+    let new = identity();
+    let config = |identity: &Identity| {
+        TlsConfig::from_pem(identity.cert_pem.as_bytes(), identity.key_pem.as_bytes()).unwrap()
+    };
+
+    let server = Server::bind("127.0.0.1:0", router()).unwrap().with_tls(config(&old));
+    let reload = server.reload_handle();
+    let (addr, handle, server) = start(server);
+    reload.set_tls(config(&new));
+    let mut client = connect(addr, &new);
+    client.get_mut().write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    assert_eq!(b"hello over tls", &read_response(&mut client).1[..]);
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+
+    let server = Server::bind("127.0.0.1:0", router()).unwrap();
+    let reload = server.reload_handle();
+    let (addr, handle, server) = start(server);
+    reload.set_tls(config(&new));
+    let mut stream = BufReader::new(TcpStream::connect(addr).unwrap());
+    stream.get_mut().set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.get_mut().write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    assert_eq!(b"hello over tls", &read_response(&mut stream).1[..]);
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn loads_pem_files_and_checks_the_key_matches() {
+    let identity = identity();
+    let other_key = rcgen::KeyPair::generate().unwrap().serialize_pem();
+    let dir = std::env::temp_dir().join(format!("tls-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let cert = dir.join("cert.pem");
+    let key = dir.join("key.pem");
+    let wrong_key = dir.join("wrong-key.pem");
+    fs::write(&cert, &identity.cert_pem).unwrap();
+    fs::write(&key, &identity.key_pem).unwrap();
+    fs::write(&wrong_key, other_key).unwrap();
+
+    let loaded = TlsConfig::from_pem_files(&cert, &key);
+    let mismatched = TlsConfig::from_pem_files(&cert, &wrong_key);
+    let missing = TlsConfig::from_pem_files(dir.join("missing.pem"), &key);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(loaded.is_ok());
+    assert_eq!(io::ErrorKind::InvalidData, mismatched.err().unwrap().kind());
+    assert_eq!(io::ErrorKind::NotFound, missing.err().unwrap().kind());
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::{frame, read_frame};
+use multi_threaded_web_server::{Server, ShutdownHandle};
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+/// Serves `common::app()` with a single worker, so that a WebSocket
+/// holding on to it would stop the server answering anything else.
+fn start(event_loop: bool) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<io::Result<()>>) {
+    let mut server = Server::bind("127.0.0.1:0", common::app()).unwrap().with_workers(1);
+    if event_loop {
+        server = server.with_event_loop(1);
+    }
+
+    common::start(server)
+}
+
+/// Opens a WebSocket, checks the handshake and returns the connection.
+/// `early` is sent right behind the handshake, before the answer to it.
+fn open(addr: SocketAddr, early: &[u8]) -> BufReader<TcpStream> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut handshake = common::HANDSHAKE.to_vec();
+    handshake.extend_from_slice(early);
+    stream.write_all(&handshake).unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        assert!(reader.read_line(&mut head).unwrap() > 0);
+    }
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(head.contains(&format!("Sec-WebSocket-Accept: {}\r\n", common::ACCEPT)));
+
+    reader
+}
+
+fn get(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn echoes_messages(event_loop: bool) {
+    let (addr, handle, server) = start(event_loop);
+    let mut socket = open(addr, &frame(0x81, b"early"));
+    assert_eq!((0x81, b"early".to_vec()), read_frame(&mut socket));
+
+    // The connection doesn't hold on to the only worker.
+    assert!(get(addr).ends_with("hello"));
+
+    // A fragmented message, with a ping in the middle of it.
+    let mut input = frame(0x01, b"hel");
+    input.extend(frame(0x89, b"are you there?"));
+    input.extend(frame(0x80, b"lo"));
+    socket.get_mut().write_all(&input).unwrap();
+    assert_eq!((0x8a, b"are you there?".to_vec()), read_frame(&mut socket));
+    assert_eq!((0x81, b"hello".to_vec()), read_frame(&mut socket));
+
+    socket.get_mut().write_all(&frame(0x82, &[1, 2, 3])).unwrap();
+    assert_eq!((0x82, vec![1, 2, 3]), read_frame(&mut socket));
+
+    // The client closes; the server answers with the same code and hangs up.
+    socket.get_mut().write_all(&frame(0x88, &[0x03, 0xe8])).unwrap();
+    assert_eq!((0x88, vec![0x03, 0xe8]), read_frame(&mut socket));
+    let mut rest = Vec::new();
+    socket.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn echoes_messages_on_workers() {
+    echoes_messages(false);
+}
+
+#[test]
+fn echoes_messages_on_event_loops() {
+    echoes_messages(true);
+}
+
+#[test]
+fn the_server_can_close_first() {
+    let (addr, handle, server) = start(false);
+    let mut socket = open(addr, b"");
+
+    socket.get_mut().write_all(&frame(0x81, b"bye")).unwrap();
+    let (first, payload) = read_frame(&mut socket);
+    assert_eq!(0x88, first);
+    assert_eq!(b"\x03\xe9bye", &payload[..]);
+
+    socket.get_mut().write_all(&frame(0x88, &[0x03, 0xe9])).unwrap();
+    let mut rest = Vec::new();
+    socket.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn protocol_errors_close_the_connection() {
+    let (addr, handle, server) = start(true);
+    let mut socket = open(addr, b"");
+
+    // Clients must mask their frames.
+    socket.get_mut().write_all(&[0x81, 0x02, b'h', b'i']).unwrap();
+    let (first, payload) = read_frame(&mut socket);
+    assert_eq!(0x88, first);
+    assert_eq!([0x03, 0xea], payload[..2]);
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn plain_requests_get_426() {
+    let (addr, handle, server) = start(false);
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+    assert!(response.contains("Upgrade: websocket\r\n"));
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+}
@@ -1,35 +1,33 @@
+use multi_threaded_web_server::executor::Inline;
+use multi_threaded_web_server::{Params, Request, Response, Router, Server};
+
 use std::fs;
-use std::io::prelude::*;
-use std::net::TcpStream;
-use std::net::TcpListener;
+use std::process;
 
+/// Serves `hello.html` at `/` and `404.html` for everything else, answering
+/// one connection at a time on the thread that accepts them.
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-
-        handle_connection(stream);
+    let router = Router::new()
+        .get("/", |_: &Request, _: &Params| page(200, "hello.html"))
+        .fallback(|_: &Request, _: &Params| page(404, "404.html"));
+
+    let server = Server::bind("127.0.0.1:7878", router).unwrap_or_else(|e| {
+        eprintln!("Problem binding 127.0.0.1:7878: {}", e);
+        process::exit(1);
+    });
+
+    if let Err(e) = server.with_executor(Inline).run() {
+        eprintln!("Server error: {}", e);
+        process::exit(1);
     }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 512];
-    stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK\r\n\r\n", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND\r\n\r\n", "404.html")
-    };
-
-    let contents = fs::read_to_string(filename).unwrap();
-
-    let response = format!("{}{}", status_line, contents);
-
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
- 
-}
\ No newline at end of file
+fn page(status: u16, filename: &str) -> Response {
+    match fs::read_to_string(filename) {
+        Ok(contents) => Response::html(status, contents),
+        Err(e) => {
+            eprintln!("Can't read {}: {}", filename, e);
+            Response::text(500, "Internal Server Error\n")
+        }
+    }
+}